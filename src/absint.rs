@@ -0,0 +1,1262 @@
+use crate::recursion::{fixpoint_combinator, Definition, Extracted};
+use crate::scope::free_vars;
+use crate::*;
+
+use std::collections::HashSet;
+
+// Abstract interpretation: what values each subexpression can take, found
+// without running the program. Integers are tracked as an interval and a
+// parity, booleans as which of `true` and `false` are possible, strings and
+// functions by kind only.
+//
+// Recursive functions, from a fixed-point combinator or `extract_recursion`,
+// get a summary: the join of the arguments of every call and of the results.
+// Summaries are recomputed until nothing changes, bounds that keep growing
+// are widened to infinity, and a few narrowing rounds then win back what
+// widening lost. Conditions comparing a variable narrow it in each branch,
+// so `x` is at least 1 in the else branch of `? B< v<x> I" ...`.
+//
+// Facts are sound for the evaluations that return a value. A node that
+// never does, because it is not reached or always fails, has no fact or
+// `Value::Bottom`. Arithmetic that could overflow i64 gives any integer.
+// A call nested deeper than `MAX_DEPTH` is not followed, and then only the
+// nodes outside every lambda keep their facts.
+
+const WIDEN_AFTER: usize = 3;
+const NARROWING_ROUNDS: usize = 3;
+// Nested applications of non-recursive lambdas, deeper ones give `Top`
+const MAX_DEPTH: usize = 200;
+const MAX_ROUNDS: usize = 1000;
+// Parameters of a function the program evaluates to
+const MAX_ESCAPING_PARAMS: usize = 16;
+
+// Closed interval, `None` for an unbounded side
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interval {
+    pub lo: Option<i64>,
+    pub hi: Option<i64>,
+}
+
+fn min_bound(a: Option<i64>, b: Option<i64>) -> Option<i64> {
+    Some(a?.min(b?))
+}
+
+fn max_bound(a: Option<i64>, b: Option<i64>) -> Option<i64> {
+    Some(a?.max(b?))
+}
+
+impl Interval {
+    pub const TOP: Interval = Interval { lo: None, hi: None };
+
+    pub fn exact(x: i64) -> Interval {
+        Interval {
+            lo: Some(x),
+            hi: Some(x),
+        }
+    }
+
+    pub fn singleton(&self) -> Option<i64> {
+        match (self.lo, self.hi) {
+            (Some(lo), Some(hi)) if lo == hi => Some(lo),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, x: i64) -> bool {
+        self.lo.is_none_or(|lo| lo <= x) && self.hi.is_none_or(|hi| x <= hi)
+    }
+
+    fn is_empty(&self) -> bool {
+        matches!((self.lo, self.hi), (Some(lo), Some(hi)) if lo > hi)
+    }
+
+    fn join(&self, other: &Interval) -> Interval {
+        Interval {
+            lo: min_bound(self.lo, other.lo),
+            hi: max_bound(self.hi, other.hi),
+        }
+    }
+
+    fn meet(&self, other: &Interval) -> Interval {
+        let lo = match (self.lo, other.lo) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        let hi = match (self.hi, other.hi) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        Interval { lo, hi }
+    }
+
+    // Drops the bounds that moved
+    fn widen(&self, next: &Interval) -> Interval {
+        let lo = if next.lo.is_some() && min_bound(self.lo, next.lo) == self.lo {
+            self.lo
+        } else {
+            None
+        };
+        let hi = if next.hi.is_some() && max_bound(self.hi, next.hi) == self.hi {
+            self.hi
+        } else {
+            None
+        };
+        Interval { lo, hi }
+    }
+
+    fn is_non_negative(&self) -> bool {
+        self.lo.is_some_and(|lo| lo >= 0)
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.lo {
+            Some(lo) => write!(f, "[{}, ", lo)?,
+            None => write!(f, "(-inf, ")?,
+        }
+        match self.hi {
+            Some(hi) => write!(f, "{}]", hi),
+            None => write!(f, "+inf)"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    Even,
+    Odd,
+    Any,
+}
+
+impl Parity {
+    fn of(x: i64) -> Parity {
+        if x % 2 == 0 {
+            Parity::Even
+        } else {
+            Parity::Odd
+        }
+    }
+
+    fn join(self, other: Parity) -> Parity {
+        if self == other {
+            self
+        } else {
+            Parity::Any
+        }
+    }
+
+    // Of a sum or a difference
+    fn add(self, other: Parity) -> Parity {
+        match (self, other) {
+            (Parity::Any, _) | (_, Parity::Any) => Parity::Any,
+            (a, b) if a == b => Parity::Even,
+            _ => Parity::Odd,
+        }
+    }
+
+    fn mul(self, other: Parity) -> Parity {
+        match (self, other) {
+            (Parity::Even, _) | (_, Parity::Even) => Parity::Even,
+            (Parity::Odd, Parity::Odd) => Parity::Odd,
+            _ => Parity::Any,
+        }
+    }
+}
+
+// Function values: a fixed-point combinator, a closure, or a recursive
+// function, see `Function`, applied to some of its arguments
+#[derive(Clone, Debug)]
+pub enum Callable {
+    Combinator,
+    Lambda(ExprPtr, Vec<(i64, Value)>),
+    // Function and the arguments it has been given so far
+    Rec(usize, Vec<Value>),
+}
+
+impl PartialEq for Callable {
+    fn eq(&self, other: &Callable) -> bool {
+        match (self, other) {
+            (Callable::Combinator, Callable::Combinator) => true,
+            (Callable::Lambda(a, env_a), Callable::Lambda(b, env_b)) => {
+                Rc::ptr_eq(a, b) && env_a == env_b
+            }
+            (Callable::Rec(a, args_a), Callable::Rec(b, args_b)) => a == b && args_a == args_b,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    // No value: not reached, or always fails
+    Bottom,
+    Int(Interval, Parity),
+    Bool { can_true: bool, can_false: bool },
+    Str,
+    Fun(Callable),
+    Top,
+}
+
+const ANY_BOOL: Value = Value::Bool {
+    can_true: true,
+    can_false: true,
+};
+
+fn boolean(b: bool) -> Value {
+    Value::Bool {
+        can_true: b,
+        can_false: !b,
+    }
+}
+
+fn join_envs(a: &[(i64, Value)], b: &[(i64, Value)], widen: bool) -> Option<Vec<(i64, Value)>> {
+    if a.len() != b.len() || a.iter().zip(b.iter()).any(|((x, _), (y, _))| x != y) {
+        return None;
+    }
+    let env = a
+        .iter()
+        .zip(b.iter())
+        .map(|((x, u), (_, v))| (*x, u.join_with(v, widen)))
+        .collect();
+    Some(env)
+}
+
+impl Value {
+    // An integer, with the bounds moved to the nearest value of the parity
+    pub fn int(interval: Interval, parity: Parity) -> Value {
+        let mut interval = interval;
+        let mut parity = parity;
+        if parity != Parity::Any {
+            if let Some(lo) = interval.lo.filter(|lo| Parity::of(*lo) != parity) {
+                interval.lo = lo.checked_add(1);
+                if interval.lo.is_none() {
+                    return Value::Bottom;
+                }
+            }
+            if let Some(hi) = interval.hi.filter(|hi| Parity::of(*hi) != parity) {
+                interval.hi = hi.checked_sub(1);
+                if interval.hi.is_none() {
+                    return Value::Bottom;
+                }
+            }
+        }
+        if interval.is_empty() {
+            return Value::Bottom;
+        }
+        if let Some(x) = interval.singleton() {
+            parity = Parity::of(x);
+        }
+        Value::Int(interval, parity)
+    }
+
+    fn any_int() -> Value {
+        Value::Int(Interval::TOP, Parity::Any)
+    }
+
+    pub fn interval(&self) -> Option<Interval> {
+        match self {
+            Value::Int(interval, _) => Some(*interval),
+            _ => None,
+        }
+    }
+
+    // `Some(b)` when a boolean can only be `b`
+    pub fn decided(&self) -> Option<bool> {
+        match self {
+            Value::Bool {
+                can_true,
+                can_false,
+            } if can_true != can_false => Some(*can_true),
+            _ => None,
+        }
+    }
+
+    pub fn join(&self, other: &Value) -> Value {
+        self.join_with(other, false)
+    }
+
+    fn join_with(&self, other: &Value, widen: bool) -> Value {
+        match (self, other) {
+            (Value::Bottom, v) | (v, Value::Bottom) => v.clone(),
+            (Value::Int(a, p), Value::Int(b, q)) => {
+                let interval = if widen { a.widen(b) } else { a.join(b) };
+                Value::int(interval, p.join(*q))
+            }
+            (
+                Value::Bool {
+                    can_true: t1,
+                    can_false: f1,
+                },
+                Value::Bool {
+                    can_true: t2,
+                    can_false: f2,
+                },
+            ) => Value::Bool {
+                can_true: *t1 || *t2,
+                can_false: *f1 || *f2,
+            },
+            (Value::Str, Value::Str) => Value::Str,
+            (Value::Fun(a), Value::Fun(b)) if a == b => self.clone(),
+            (Value::Fun(Callable::Lambda(a, env_a)), Value::Fun(Callable::Lambda(b, env_b)))
+                if Rc::ptr_eq(a, b) =>
+            {
+                match join_envs(env_a, env_b, widen) {
+                    Some(env) => Value::Fun(Callable::Lambda(a.clone(), env)),
+                    None => Value::Top,
+                }
+            }
+            (Value::Fun(Callable::Rec(a, args_a)), Value::Fun(Callable::Rec(b, args_b)))
+                if a == b && args_a.len() == args_b.len() =>
+            {
+                let args = args_a
+                    .iter()
+                    .zip(args_b.iter())
+                    .map(|(u, v)| u.join_with(v, widen))
+                    .collect();
+                Value::Fun(Callable::Rec(*a, args))
+            }
+            _ => Value::Top,
+        }
+    }
+
+    fn meet_int(&self, interval: &Interval, parity: Parity) -> Value {
+        match self {
+            Value::Int(a, p) => {
+                let parity = match (*p, parity) {
+                    (Parity::Any, q) | (q, Parity::Any) => q,
+                    (p, q) if p == q => p,
+                    _ => return Value::Bottom,
+                };
+                Value::int(a.meet(interval), parity)
+            }
+            _ => self.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Bottom => write!(f, "bottom"),
+            Value::Int(interval, _) if interval.singleton().is_some() => {
+                write!(f, "{}", interval.lo.unwrap())
+            }
+            Value::Int(interval, Parity::Any) => write!(f, "{}", interval),
+            Value::Int(interval, Parity::Even) => write!(f, "even {}", interval),
+            Value::Int(interval, Parity::Odd) => write!(f, "odd {}", interval),
+            Value::Bool {
+                can_true,
+                can_false,
+            } => match (can_true, can_false) {
+                (true, false) => write!(f, "true"),
+                (false, true) => write!(f, "false"),
+                _ => write!(f, "bool"),
+            },
+            Value::Str => write!(f, "str"),
+            Value::Fun(_) => write!(f, "function"),
+            Value::Top => write!(f, "top"),
+        }
+    }
+}
+
+fn checked(
+    x: Option<i64>,
+    y: Option<i64>,
+    op: fn(i64, i64) -> Option<i64>,
+) -> Result<Option<i64>, ()> {
+    match (x, y) {
+        (Some(x), Some(y)) => op(x, y).map(Some).ok_or(()),
+        _ => Ok(None),
+    }
+}
+
+fn add(a: &Interval, b: &Interval) -> Interval {
+    let bounds = (|| {
+        let lo = checked(a.lo, b.lo, i64::checked_add)?;
+        let hi = checked(a.hi, b.hi, i64::checked_add)?;
+        Ok::<_, ()>(Interval { lo, hi })
+    })();
+    bounds.unwrap_or(Interval::TOP)
+}
+
+fn neg(a: &Interval) -> Interval {
+    let bounds = (|| {
+        let lo = a.hi.map(|x| x.checked_neg().ok_or(())).transpose()?;
+        let hi = a.lo.map(|x| x.checked_neg().ok_or(())).transpose()?;
+        Ok::<_, ()>(Interval { lo, hi })
+    })();
+    bounds.unwrap_or(Interval::TOP)
+}
+
+fn mul(a: &Interval, b: &Interval) -> Interval {
+    if a.singleton() == Some(0) || b.singleton() == Some(0) {
+        return Interval::exact(0);
+    }
+    if let (Some(a_lo), Some(a_hi), Some(b_lo), Some(b_hi)) = (a.lo, a.hi, b.lo, b.hi) {
+        let products: Option<Vec<i64>> = [(a_lo, b_lo), (a_lo, b_hi), (a_hi, b_lo), (a_hi, b_hi)]
+            .iter()
+            .map(|(x, y)| x.checked_mul(*y))
+            .collect();
+        return match products {
+            Some(products) => Interval {
+                lo: products.iter().min().copied(),
+                hi: products.iter().max().copied(),
+            },
+            None => Interval::TOP,
+        };
+    }
+    if a.is_non_negative() && b.is_non_negative() {
+        if let Some(lo) = checked(a.lo, b.lo, i64::checked_mul).ok().flatten() {
+            return Interval {
+                lo: Some(lo),
+                hi: None,
+            };
+        }
+    }
+    Interval::TOP
+}
+
+// `a / [c, d]` with `1 <= c`, `d` unbounded when `None`
+fn div_positive(a: &Interval, c: i64, d: Option<i64>) -> Interval {
+    // Truncating division is monotone in each operand, and `x / y` goes to 0
+    // as `y` grows
+    let by = |x: i64| {
+        let q = x / c;
+        let r = d.map_or(0, |d| x / d);
+        (q.min(r), q.max(r))
+    };
+    Interval {
+        lo: a.lo.map(|x| by(x).0),
+        hi: a.hi.map(|x| by(x).1),
+    }
+}
+
+fn div(a: &Interval, b: &Interval) -> Option<Interval> {
+    let mut res: Option<Interval> = None;
+    let mut join = |i: Interval| {
+        res = Some(match res {
+            Some(r) => r.join(&i),
+            None => i,
+        });
+    };
+    // The positive part of the divisor
+    if b.hi.is_none_or(|hi| hi >= 1) {
+        let c = b.lo.map_or(1, |lo| lo.max(1));
+        join(div_positive(a, c, b.hi));
+    }
+    // The negative part, `a / -y = -(a / y)`
+    if b.lo.is_none_or(|lo| lo <= -1) {
+        let c = b.hi.map_or(-1, |hi| hi.min(-1)).checked_neg();
+        let d = b.lo.and_then(|lo| lo.checked_neg());
+        match c {
+            Some(c) => join(neg(&div_positive(a, c, d))),
+            None => join(Interval::TOP),
+        }
+    }
+    // `i64::MIN / -1` overflows
+    if a.contains(i64::MIN) && b.contains(-1) {
+        return Some(Interval::TOP);
+    }
+    res
+}
+
+fn rem(a: &Interval, b: &Interval) -> Interval {
+    // |a % b| < |b| and |a % b| <= |a|, with the sign of `a`
+    let magnitude = |x: Option<i64>| x.and_then(|x| x.checked_abs());
+    let max_b = max_bound(magnitude(b.lo), magnitude(b.hi)).map(|m| m - 1);
+    let min_b = if b.contains(0) {
+        None
+    } else {
+        min_bound(magnitude(b.lo), magnitude(b.hi))
+    };
+    let max_a = max_bound(magnitude(a.lo), magnitude(a.hi));
+    // `a` already smaller than every divisor
+    if let (Some(m), Some(n)) = (max_a, min_b) {
+        if m < n {
+            return *a;
+        }
+    }
+    let limit = min_bound(max_b, max_a).or(max_b).or(max_a);
+    let lo = if a.is_non_negative() {
+        Some(0)
+    } else {
+        limit.map(|m| -m)
+    };
+    let hi = if a.hi.is_some_and(|hi| hi <= 0) {
+        Some(0)
+    } else {
+        limit
+    };
+    Interval { lo, hi }
+}
+
+fn arithmetic(op: char, a: &Value, b: &Value) -> Value {
+    let (a, p, b, q) = match (a, b) {
+        (Value::Bottom, _) | (_, Value::Bottom) => return Value::Bottom,
+        (Value::Int(a, p), Value::Int(b, q)) => (*a, *p, *b, *q),
+        (Value::Int(_, _) | Value::Top, Value::Int(_, _) | Value::Top) => {
+            return Value::any_int();
+        }
+        // Always fails
+        _ => return Value::Bottom,
+    };
+    match op {
+        '+' => Value::int(add(&a, &b), p.add(q)),
+        '-' => Value::int(add(&a, &neg(&b)), p.add(q)),
+        '*' => Value::int(mul(&a, &b), p.mul(q)),
+        '/' => match div(&a, &b) {
+            Some(interval) => Value::int(interval, Parity::Any),
+            None => Value::Bottom,
+        },
+        '%' => {
+            if b.singleton() == Some(0) {
+                return Value::Bottom;
+            }
+            // An even divisor keeps the parity
+            let parity = match b.singleton() {
+                Some(d) if d % 2 == 0 => p,
+                _ => Parity::Any,
+            };
+            Value::int(rem(&a, &b), parity)
+        }
+        _ => panic!("Unexpected op: {}", op),
+    }
+}
+
+fn compare(op: char, a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Bottom, _) | (_, Value::Bottom) => Value::Bottom,
+        (Value::Int(a, _), Value::Int(b, _)) => {
+            let (a, b) = if op == '<' { (a, b) } else { (b, a) };
+            // a < b
+            let can_true = match (a.lo, b.hi) {
+                (Some(x), Some(y)) => x < y,
+                _ => true,
+            };
+            let can_false = match (a.hi, b.lo) {
+                (Some(x), Some(y)) => x >= y,
+                _ => true,
+            };
+            Value::Bool {
+                can_true,
+                can_false,
+            }
+        }
+        (Value::Int(_, _) | Value::Top, Value::Int(_, _) | Value::Top) => ANY_BOOL,
+        _ => Value::Bottom,
+    }
+}
+
+fn equal(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Bottom, _) | (_, Value::Bottom) => Value::Bottom,
+        (Value::Int(x, p), Value::Int(y, q)) => {
+            let overlap = !x.meet(y).is_empty()
+                && !matches!(
+                    (p, q),
+                    (Parity::Even, Parity::Odd) | (Parity::Odd, Parity::Even)
+                );
+            let same = x.singleton().is_some() && x.singleton() == y.singleton();
+            Value::Bool {
+                can_true: overlap,
+                can_false: !same,
+            }
+        }
+        (Value::Bool { .. }, Value::Bool { .. }) => match (a.decided(), b.decided()) {
+            (Some(x), Some(y)) => boolean(x == y),
+            _ => ANY_BOOL,
+        },
+        (Value::Str, Value::Str) => ANY_BOOL,
+        (Value::Top, _) | (_, Value::Top) => ANY_BOOL,
+        _ => Value::Bottom,
+    }
+}
+
+fn logic(op: char, a: &Value, b: &Value) -> Value {
+    let sets = |v: &Value| match v {
+        Value::Bool {
+            can_true,
+            can_false,
+        } => Some((*can_true, *can_false)),
+        Value::Top => Some((true, true)),
+        _ => None,
+    };
+    let (Some((t1, f1)), Some((t2, f2))) = (sets(a), sets(b)) else {
+        return Value::Bottom;
+    };
+    let (can_true, can_false) = if op == '|' {
+        (t1 || t2, f1 && f2)
+    } else {
+        (t1 && t2, f1 || f2)
+    };
+    Value::Bool {
+        can_true,
+        can_false,
+    }
+}
+
+fn string_op(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Bottom, _) | (_, Value::Bottom) => Value::Bottom,
+        _ => Value::Str,
+    }
+}
+
+// A recursive function: `f = L self. L x1. ... L xn. body` under a
+// fixed-point combinator, or an extracted definition. Its summary holds the
+// join of the values of its free variables and arguments over every use,
+// then the result for them.
+struct Function {
+    self_var: i64,
+    free: Vec<i64>,
+    params: Vec<i64>,
+    body: ExprPtr,
+    // Free variables, then arguments
+    inputs: Vec<Value>,
+    result: Value,
+    // While narrowing, what this round computes
+    next_inputs: Vec<Value>,
+    next_result: Value,
+    // Called at least once
+    reached: bool,
+    updates: usize,
+}
+
+struct Analyzer {
+    functions: Vec<Function>,
+    // By the lambda under the combinator, or the definition's name
+    by_node: HashMap<*const RefCell<Expr>, usize>,
+    by_name: HashMap<i64, usize>,
+    definitions: HashMap<i64, Definition>,
+    narrowing: bool,
+    changed: bool,
+    depth: usize,
+    // Whether a call was not followed this round, see `MAX_DEPTH`
+    cut_off: bool,
+    facts: HashMap<*const RefCell<Expr>, (ExprPtr, Value)>,
+}
+
+fn lookup(env: &[(i64, Value)], x: i64) -> Option<&Value> {
+    env.iter().rev().find(|(y, _)| *y == x).map(|(_, v)| v)
+}
+
+impl Analyzer {
+    fn new() -> Analyzer {
+        Analyzer {
+            functions: Vec::new(),
+            by_node: HashMap::new(),
+            by_name: HashMap::new(),
+            definitions: HashMap::new(),
+            narrowing: false,
+            changed: false,
+            depth: 0,
+            cut_off: false,
+            facts: HashMap::new(),
+        }
+    }
+
+    fn register(&mut self, self_var: i64, f: &ExprPtr, params: Vec<i64>, body: ExprPtr) -> usize {
+        // Other definitions are looked up by name instead
+        let mut free: Vec<i64> = free_vars(&f.borrow())
+            .into_iter()
+            .filter(|x| !self.definitions.contains_key(x))
+            .collect();
+        free.sort();
+        let inputs = vec![Value::Bottom; free.len() + params.len()];
+        self.functions.push(Function {
+            self_var,
+            free,
+            params,
+            body,
+            next_inputs: inputs.clone(),
+            inputs,
+            result: Value::Bottom,
+            next_result: Value::Bottom,
+            reached: false,
+            updates: 0,
+        });
+        self.functions.len() - 1
+    }
+
+    // Joins `values` into the inputs of `index` from `offset` on
+    fn contribute(&mut self, index: usize, offset: usize, values: &[Value]) {
+        let narrowing = self.narrowing;
+        let function = &mut self.functions[index];
+        let widen = function.updates >= WIDEN_AFTER;
+        let mut changed = false;
+        for (i, v) in values.iter().enumerate() {
+            if narrowing {
+                let next = &mut function.next_inputs[offset + i];
+                *next = next.join(v);
+            } else {
+                let old = &function.inputs[offset + i];
+                let new = old.join_with(v, widen);
+                if new != *old {
+                    function.inputs[offset + i] = new;
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            function.updates += 1;
+            self.changed = true;
+        }
+    }
+
+    // The function value at a use, with the free variables from `env`
+    fn reference(&mut self, index: usize, env: &[(i64, Value)]) -> Value {
+        let free = self.functions[index].free.clone();
+        let values: Vec<Value> = free
+            .iter()
+            .map(|x| lookup(env, *x).cloned().unwrap_or(Value::Top))
+            .collect();
+        self.contribute(index, 0, &values);
+        if self.functions[index].params.is_empty() {
+            return self.call(index, Vec::new());
+        }
+        Value::Fun(Callable::Rec(index, Vec::new()))
+    }
+
+    fn call(&mut self, index: usize, args: Vec<Value>) -> Value {
+        let offset = self.functions[index].free.len();
+        if !self.functions[index].reached {
+            self.functions[index].reached = true;
+            self.changed = true;
+        }
+        self.contribute(index, offset, &args);
+        self.functions[index].result.clone()
+    }
+
+    // `B$ Y f`, with `f` a closure
+    fn fixpoint(&mut self, f: &Value) -> Value {
+        let Value::Fun(Callable::Lambda(lambda, env)) = f else {
+            return match f {
+                Value::Bottom => Value::Bottom,
+                _ => Value::Top,
+            };
+        };
+        let key = Rc::as_ptr(lambda);
+        let index = match self.by_node.get(&key) {
+            Some(index) => *index,
+            None => {
+                let (self_var, mut body) = match &*lambda.borrow() {
+                    Expr::Lambda(x, body) => (*x, body.clone()),
+                    _ => unreachable!(),
+                };
+                let mut params = Vec::new();
+                loop {
+                    let inner = match &*body.borrow() {
+                        Expr::Lambda(x, inner) => {
+                            params.push(*x);
+                            inner.clone()
+                        }
+                        _ => break,
+                    };
+                    body = inner;
+                }
+                let index = self.register(self_var, lambda, params, body);
+                self.by_node.insert(key, index);
+                index
+            }
+        };
+        self.reference(index, env)
+    }
+
+    fn definition(&mut self, x: i64, env: &[(i64, Value)]) -> Option<Value> {
+        let index = match self.by_name.get(&x) {
+            Some(index) => *index,
+            None => {
+                let def = self.definitions.get(&x)?.clone();
+                let f = def
+                    .params
+                    .iter()
+                    .rev()
+                    .fold(def.body.clone(), |e, p| as_ptr(Expr::Lambda(*p, e)));
+                let f = as_ptr(Expr::Lambda(x, f));
+                let index = self.register(x, &f, def.params.clone(), def.body.clone());
+                self.by_name.insert(x, index);
+                index
+            }
+        };
+        Some(self.reference(index, env))
+    }
+
+    fn apply(&mut self, f: Value, arg: Value) -> Value {
+        match f {
+            Value::Bottom => Value::Bottom,
+            Value::Fun(Callable::Combinator) => self.fixpoint(&arg),
+            Value::Fun(Callable::Lambda(lambda, mut env)) => {
+                if self.depth >= MAX_DEPTH {
+                    self.cut_off = true;
+                    return Value::Top;
+                }
+                let (x, body) = match &*lambda.borrow() {
+                    Expr::Lambda(x, body) => (*x, body.clone()),
+                    _ => unreachable!(),
+                };
+                env.push((x, arg));
+                self.depth += 1;
+                let res = self.eval(&body, &mut env);
+                self.depth -= 1;
+                res
+            }
+            Value::Fun(Callable::Rec(index, mut args)) => {
+                args.push(arg);
+                if args.len() == self.functions[index].params.len() {
+                    self.call(index, args)
+                } else {
+                    Value::Fun(Callable::Rec(index, args))
+                }
+            }
+            // Not a function, always fails
+            Value::Int(_, _) | Value::Bool { .. } | Value::Str => Value::Bottom,
+            Value::Top => Value::Top,
+        }
+    }
+
+    // `env` with the variables `cond` compares narrowed for when it is
+    // `truth`, `None` if it cannot be
+    fn refine(&mut self, cond: &ExprPtr, truth: bool, env: &mut Vec<(i64, Value)>) -> Option<()> {
+        let e = cond.borrow().clone();
+        match &e {
+            Expr::Unary('!', a) => self.refine(a, !truth, env),
+            Expr::Binary('&', a, b) if truth => {
+                self.refine(a, true, env)?;
+                self.refine(b, true, env)
+            }
+            Expr::Binary('|', a, b) if !truth => {
+                self.refine(a, false, env)?;
+                self.refine(b, false, env)
+            }
+            Expr::Binary(op @ ('<' | '>' | '='), a, b) => {
+                // `<` with the operands swapped
+                let (op, a, b) = if *op == '>' { ('<', b, a) } else { (*op, a, b) };
+                if let Expr::Var(x) = *a.borrow() {
+                    let other = self.eval(b, env);
+                    self.narrow(x, op, true, truth, &other, env)?;
+                }
+                if let Expr::Var(x) = *b.borrow() {
+                    let other = self.eval(a, env);
+                    self.narrow(x, op, false, truth, &other, env)?;
+                }
+                Some(())
+            }
+            _ => Some(()),
+        }
+    }
+
+    // Narrows `x` for `x op other` being `truth`, or `other op x` when
+    // `left` is false
+    fn narrow(
+        &mut self,
+        x: i64,
+        op: char,
+        left: bool,
+        truth: bool,
+        other: &Value,
+        env: &mut Vec<(i64, Value)>,
+    ) -> Option<()> {
+        let (Some(value), Some(bound)) = (lookup(env, x).cloned(), other.interval()) else {
+            return Some(());
+        };
+        let Value::Int(_, _) = value else {
+            return Some(());
+        };
+        let below = |b: Option<i64>, d: i64| Interval {
+            lo: None,
+            hi: b.and_then(|b| b.checked_sub(d)),
+        };
+        let above = |b: Option<i64>, d: i64| Interval {
+            lo: b.and_then(|b| b.checked_add(d)),
+            hi: None,
+        };
+        let constraint = match (op, left, truth) {
+            // x < b, x >= b, b < x, b >= x
+            ('<', true, true) => below(bound.hi, 1),
+            ('<', true, false) => above(bound.lo, 0),
+            ('<', false, true) => above(bound.lo, 1),
+            ('<', false, false) => below(bound.hi, 0),
+            ('=', _, true) => bound,
+            ('=', _, false) => match (value.interval(), bound.singleton()) {
+                (Some(own), Some(c)) if own.lo == Some(c) => above(Some(c), 1),
+                (Some(own), Some(c)) if own.hi == Some(c) => below(Some(c), 1),
+                _ => Interval::TOP,
+            },
+            _ => Interval::TOP,
+        };
+        let refined = value.meet_int(&constraint, Parity::Any);
+        if refined == Value::Bottom {
+            return None;
+        }
+        env.push((x, refined));
+        Some(())
+    }
+
+    fn eval(&mut self, expr_ptr: &ExprPtr, env: &mut Vec<(i64, Value)>) -> Value {
+        let res = self.eval_impl(expr_ptr, env);
+        let key = Rc::as_ptr(expr_ptr);
+        let entry = self
+            .facts
+            .entry(key)
+            .or_insert_with(|| (expr_ptr.clone(), Value::Bottom));
+        entry.1 = entry.1.join(&res);
+        res
+    }
+
+    fn eval_impl(&mut self, expr_ptr: &ExprPtr, env: &mut Vec<(i64, Value)>) -> Value {
+        let e = expr_ptr.borrow().clone();
+        if matches!(e, Expr::Lambda(_, _) | Expr::Binary('$', _, _))
+            && fixpoint_combinator(&e).is_some()
+        {
+            return Value::Fun(Callable::Combinator);
+        }
+        match &e {
+            Expr::Boolean(b) => boolean(*b),
+            Expr::Integer(x) => Value::int(Interval::exact(*x), Parity::of(*x)),
+            Expr::BigInteger(_) => Value::any_int(),
+            Expr::String(_) => Value::Str,
+            Expr::Var(x) => match lookup(env, *x) {
+                Some(v) => v.clone(),
+                None => self.definition(*x, env).unwrap_or(Value::Top),
+            },
+            Expr::Lambda(_, _) => Value::Fun(Callable::Lambda(expr_ptr.clone(), env.clone())),
+            Expr::Unary(op, a) => {
+                let a = self.eval(a, env);
+                match (*op, &a) {
+                    (_, Value::Bottom) => Value::Bottom,
+                    ('-', Value::Int(interval, p)) => Value::int(neg(interval), *p),
+                    ('-', Value::Top) => Value::any_int(),
+                    (
+                        '!',
+                        Value::Bool {
+                            can_true,
+                            can_false,
+                        },
+                    ) => Value::Bool {
+                        can_true: *can_false,
+                        can_false: *can_true,
+                    },
+                    ('!', Value::Top) => ANY_BOOL,
+                    ('#', Value::Str | Value::Top) => Value::Int(
+                        Interval {
+                            lo: Some(0),
+                            hi: None,
+                        },
+                        Parity::Any,
+                    ),
+                    ('$', Value::Int(_, _) | Value::Top) => Value::Str,
+                    _ => Value::Bottom,
+                }
+            }
+            Expr::Binary('$', f, arg) => {
+                let f = self.eval(f, env);
+                // Evaluation is call-by-name, but an argument has the same
+                // values wherever it is used, so it is evaluated once here
+                let arg = self.eval(arg, env);
+                self.apply(f, arg)
+            }
+            Expr::Binary(op, a, b) => {
+                let op = *op;
+                let a = self.eval(a, env);
+                let b = self.eval(b, env);
+                match op {
+                    '+' | '-' | '*' | '/' | '%' => arithmetic(op, &a, &b),
+                    '<' | '>' => compare(op, &a, &b),
+                    '=' => equal(&a, &b),
+                    '|' | '&' => logic(op, &a, &b),
+                    '.' | 'T' | 'D' => string_op(&a, &b),
+                    _ => panic!("Unexpected op: {}", op),
+                }
+            }
+            Expr::If(cond, then, otherwise) => {
+                let c = self.eval(cond, env);
+                let (can_true, can_false) = match c {
+                    Value::Bool {
+                        can_true,
+                        can_false,
+                    } => (can_true, can_false),
+                    Value::Top => (true, true),
+                    _ => return Value::Bottom,
+                };
+                let mut res = Value::Bottom;
+                for (possible, branch, truth) in
+                    [(can_true, &then, true), (can_false, &otherwise, false)]
+                {
+                    if !possible {
+                        continue;
+                    }
+                    let depth = env.len();
+                    if self.refine(cond, truth, env).is_some() {
+                        res = res.join(&self.eval(branch, env));
+                    }
+                    env.truncate(depth);
+                }
+                res
+            }
+        }
+    }
+
+    // One round over the program and every function body
+    fn round(&mut self, root: &ExprPtr) -> Value {
+        self.facts.clear();
+        self.cut_off = false;
+        let main = self.eval(root, &mut Vec::new());
+        // A function result can be called with anything
+        let mut value = main.clone();
+        for _ in 0..MAX_ESCAPING_PARAMS {
+            if !matches!(value, Value::Fun(Callable::Lambda(_, _) | Callable::Rec(_, _))) {
+                break;
+            }
+            value = self.apply(value, Value::Top);
+        }
+        let mut index = 0;
+        while index < self.functions.len() {
+            let f = &self.functions[index];
+            if f.reached {
+                let mut env: Vec<(i64, Value)> = f
+                    .free
+                    .iter()
+                    .copied()
+                    .zip(f.inputs.iter().cloned())
+                    .collect();
+                let this = if f.params.is_empty() {
+                    f.result.clone()
+                } else {
+                    Value::Fun(Callable::Rec(index, Vec::new()))
+                };
+                env.push((f.self_var, this));
+                for (x, v) in f.params.iter().zip(f.inputs[f.free.len()..].iter()) {
+                    env.push((*x, v.clone()));
+                }
+                let body = f.body.clone();
+                let value = self.eval(&body, &mut env);
+                let f = &mut self.functions[index];
+                if self.narrowing {
+                    f.next_result = f.next_result.join(&value);
+                } else {
+                    let new = f.result.join_with(&value, f.updates >= WIDEN_AFTER);
+                    if new != f.result {
+                        f.result = new;
+                        f.updates += 1;
+                        self.changed = true;
+                    }
+                }
+            }
+            index += 1;
+        }
+        main
+    }
+
+    fn run(&mut self, root: &ExprPtr) -> Value {
+        let mut stable = false;
+        for _ in 0..MAX_ROUNDS {
+            self.changed = false;
+            self.round(root);
+            if !self.changed {
+                stable = true;
+                break;
+            }
+        }
+        if !stable {
+            // Function values that keep changing, give up on the summaries
+            for f in self.functions.iter_mut() {
+                f.inputs = vec![Value::Top; f.inputs.len()];
+                f.result = Value::Top;
+            }
+            return self.round(root);
+        }
+        self.narrowing = true;
+        for _ in 0..NARROWING_ROUNDS {
+            for f in self.functions.iter_mut() {
+                f.next_inputs = vec![Value::Bottom; f.inputs.len()];
+                f.next_result = Value::Bottom;
+            }
+            self.round(root);
+            for f in self.functions.iter_mut() {
+                f.inputs = f.next_inputs.clone();
+                f.result = f.next_result.clone();
+            }
+        }
+        // Facts for the final summaries
+        self.narrowing = false;
+        self.round(root)
+    }
+
+    // The body of a call that was not followed never saw its arguments,
+    // nor did the functions it would have called. Only the nodes evaluated
+    // outside every lambda are sure to have seen all their values.
+    fn sound_facts(&mut self, root: &ExprPtr) -> HashMap<*const RefCell<Expr>, (ExprPtr, Value)> {
+        let mut facts = std::mem::take(&mut self.facts);
+        if self.cut_off {
+            let mut outside = HashSet::new();
+            outside_lambdas(root, &mut outside);
+            facts.retain(|node, _| outside.contains(node));
+        }
+        facts
+    }
+}
+
+fn outside_lambdas(expr_ptr: &ExprPtr, res: &mut HashSet<*const RefCell<Expr>>) {
+    res.insert(Rc::as_ptr(expr_ptr));
+    let e = expr_ptr.borrow();
+    if !matches!(*e, Expr::Lambda(_, _)) {
+        for c in children(&e).iter() {
+            outside_lambdas(c, res);
+        }
+    }
+}
+
+pub struct Facts {
+    pub main: Value,
+    nodes: HashMap<*const RefCell<Expr>, (ExprPtr, Value)>,
+    // Arguments and result of each extracted definition
+    definitions: HashMap<i64, (Vec<Value>, Value)>,
+}
+
+impl Facts {
+    // What `expr`, a node of the analysed program, can evaluate to
+    pub fn get(&self, expr_ptr: &ExprPtr) -> Option<&Value> {
+        self.nodes.get(&Rc::as_ptr(expr_ptr)).map(|(_, v)| v)
+    }
+
+    pub fn definition(&self, name: i64) -> Option<&(Vec<Value>, Value)> {
+        self.definitions.get(&name)
+    }
+}
+
+pub fn analyze(expr_ptr: &ExprPtr) -> Facts {
+    let mut analyzer = Analyzer::new();
+    let main = analyzer.run(expr_ptr);
+    Facts {
+        main,
+        nodes: analyzer.sound_facts(expr_ptr),
+        definitions: HashMap::new(),
+    }
+}
+
+// Facts for a program after `extract_recursion`, keyed by its nodes
+pub fn analyze_extracted(extracted: &Extracted) -> Facts {
+    let mut analyzer = Analyzer::new();
+    for def in extracted.definitions.iter() {
+        analyzer.definitions.insert(def.name, def.clone());
+    }
+    let main = analyzer.run(&extracted.main);
+    // Calls that were not followed are missing from the summaries too
+    let definitions = if analyzer.cut_off {
+        HashMap::new()
+    } else {
+        analyzer
+            .by_name
+            .iter()
+            .map(|(name, index)| {
+                let f = &analyzer.functions[*index];
+                let args = f.inputs[f.free.len()..].to_vec();
+                (*name, (args, f.result.clone()))
+            })
+            .collect()
+    };
+    Facts {
+        main,
+        nodes: analyzer.sound_facts(&extracted.main),
+        definitions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recursion::extract_recursion;
+
+    fn range(lo: i64, hi: i64) -> Interval {
+        Interval {
+            lo: Some(lo),
+            hi: Some(hi),
+        }
+    }
+
+    fn main_of(program: &str) -> String {
+        let expr = parse_into_ast(program.to_string());
+        analyze(&expr).main.to_string()
+    }
+
+    #[test]
+    fn test_intervals() {
+        assert_eq!(add(&range(1, 2), &range(-5, 10)), range(-4, 12));
+        assert_eq!(mul(&range(-2, 3), &range(-5, 4)), range(-15, 12));
+        assert_eq!(
+            mul(&range(1, 2), &range(i64::MAX - 1, i64::MAX)),
+            Interval::TOP
+        );
+        // Division truncates towards 0
+        assert_eq!(div(&range(-7, 7), &range(2, 3)), Some(range(-3, 3)));
+        assert_eq!(div(&range(7, 9), &range(-2, 2)), Some(range(-9, 9)));
+        assert_eq!(div(&range(1, 1), &range(0, 0)), None);
+        let positive = Interval {
+            lo: Some(1),
+            hi: None,
+        };
+        assert_eq!(div(&range(10, 20), &positive), Some(range(0, 20)));
+        // The remainder has the sign of the dividend
+        assert_eq!(rem(&range(-7, 7), &range(3, 3)), range(-2, 2));
+        assert_eq!(rem(&range(0, 100), &range(-10, 10)), range(0, 9));
+        assert_eq!(rem(&range(1, 2), &range(5, 9)), range(1, 2));
+        assert_eq!(range(0, 2).widen(&range(0, 3)).to_string(), "[0, +inf)");
+    }
+
+    #[test]
+    fn test_values() {
+        assert_eq!(main_of("B+ I# B* I$ I%"), "14");
+        assert_eq!(main_of("B% U# S I$"), "[0, 2]");
+        assert_eq!(main_of("B/ I# I!"), "bottom");
+        assert_eq!(main_of("B$ L! B* v! I# I%"), "8");
+        // Parity survives arithmetic on unknown values
+        assert_eq!(main_of("B$ L! B* I# B+ U# v! I\" S"), "even [2, +inf)");
+        assert_eq!(main_of("B$ L! B+ I\" B* I# U# v! S"), "odd [1, +inf)");
+        assert_eq!(main_of("B< U# S! I!"), "false");
+        // Both branches, narrowed by the condition
+        assert_eq!(main_of("B$ L! ? B< v! I+ v! I$ B+ U# S I#"), "[2, 9]");
+    }
+
+    #[test]
+    fn test_problem_4() {
+        let text = fs::read_to_string("problems/4.txt").unwrap();
+        let expr = parse_into_ast(text.trim().to_string());
+        let facts = analyze(&expr);
+        assert_eq!(facts.main.to_string(), "[1, +inf)");
+        let extracted = extract_recursion(&expr.borrow());
+        let facts = analyze_extracted(&extracted);
+        let (args, result) = facts.definition(extracted.definitions[0].name).unwrap();
+        // Widening alone would give [0, +inf) for the argument
+        assert_eq!(args[0].to_string(), "[0, 40]");
+        assert_eq!(result.to_string(), "[1, +inf)");
+        // Every node of the body is reached
+        let body = &extracted.definitions[0].body;
+        assert_eq!(
+            facts.get(body).map(|v| v.to_string()),
+            Some(result.to_string())
+        );
+    }
+
+    #[test]
+    fn test_conformance_soundness() {
+        set_debug(false);
+        let text = fs::read_to_string("tests/conformance.txt").unwrap();
+        let cases = conformance::parse_cases(&text, ".").unwrap();
+        for case in cases.iter() {
+            let value = eval_example(&case.program);
+            let main = analyze(&parse_into_ast(case.program.clone())).main;
+            let sound = match (&value, &main) {
+                (_, Value::Top) => true,
+                (Expr::Integer(x), Value::Int(interval, parity)) => {
+                    interval.contains(*x) && (*parity == Parity::Any || *parity == Parity::of(*x))
+                }
+                (Expr::Boolean(b), Value::Bool { .. }) => main.decided() != Some(!*b),
+                (Expr::String(_), Value::Str) => true,
+                (Expr::Lambda(_, _), Value::Fun(_)) => true,
+                _ => false,
+            };
+            assert!(sound, "{}: {:?} not in {}", case.name, value, main);
+        }
+    }
+}
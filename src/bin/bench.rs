@@ -0,0 +1,425 @@
+use icfpc_2024::*;
+
+use std::collections::HashMap;
+use std::fs;
+use std::time::Instant;
+
+// Timing harness for the parser and the evaluator.
+//
+// Usage: bench [--runs N] [--steps N] [--problem-steps N] [--filter S]
+//              [--out FILE] [--baseline FILE] [--save-baseline]
+//              [--threshold PCT]
+//
+// Results are written to `--out` (bench_output.txt by default) and compared
+// with `--baseline` (bench_baseline.txt) if it exists. Exits with code 1 when
+// any case got slower than the threshold allows.
+//
+// The problems blow up exponentially under substitution, so they only get a
+// few evaluation steps; the other cases run to completion within `--steps`.
+// The `parse_large` cases only measure the parser and report no eval time.
+
+const DEFAULT_OUT: &str = "bench_output.txt";
+const DEFAULT_BASELINE: &str = "bench_baseline.txt";
+
+// Differences below this are noise, no matter the ratio
+const NOISE_FLOOR_US: u128 = 200;
+
+struct Case {
+    name: String,
+    program: String,
+    // `None` only parses, the program is not evaluated
+    max_steps: Option<usize>,
+    // Parse with `stream::parse_str` instead of `parse_into_ast`
+    streaming: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Timing {
+    name: String,
+    parse_us: u128,
+    // `-` in the results for cases that only parse
+    eval_us: Option<u128>,
+    steps: usize,
+}
+
+// `B+ I" B+ I" ... I"`, nested n levels deep on the right
+fn deep_add(n: usize) -> String {
+    let mut res = "B+ I\" ".repeat(n);
+    res += "I\"";
+    res
+}
+
+// Balanced tree of additions with 2^depth leaves
+fn balanced_add(depth: usize) -> String {
+    if depth == 0 {
+        return "I\"".to_string();
+    }
+    let sub = balanced_add(depth - 1);
+    format!("B+ {} {}", sub, sub)
+}
+
+// Recursive sum 1..n through the Y combinator, same shape as the spec example
+fn recursive_sum(n: i64) -> String {
+    format!(
+        r#"B$ B$ L" B$ L# B$ v" B$ v# v# L# B$ v" B$ v# v# L" L# ? B= v# I! I! B+ v# B$ v" B- v# I" I{}"#,
+        int_to_base94_string(n)
+    )
+}
+
+// Concatenation of n copies of "test"
+fn concat_chain(n: usize) -> String {
+    let mut res = "B. S4%34 ".repeat(n);
+    res += "S4%34";
+    res
+}
+
+// n nested `B$ L<x> ... I"` bindings summed together
+fn nested_lets(n: usize) -> String {
+    let mut res = String::new();
+    for x in 1..=n {
+        res += &format!("B$ L{} ", int_to_base94_string(x as i64));
+    }
+    res += "I!";
+    for x in (1..=n).rev() {
+        res = res.replacen(
+            "I!",
+            &format!("B+ v{} I!", int_to_base94_string(x as i64)),
+            1,
+        );
+    }
+    for _ in 1..=n {
+        res += " I\"";
+    }
+    res
+}
+
+fn problem_number(path: &str) -> Option<i64> {
+    let name = path.rsplit('/').next()?;
+    name.strip_suffix(".txt")?.parse().ok()
+}
+
+fn collect_cases(max_steps: usize, problem_steps: usize) -> Vec<Case> {
+    let mut res = Vec::new();
+    res.push(Case {
+        name: "language_test".to_string(),
+        program: fs::read_to_string("language_test.txt").unwrap(),
+        max_steps: Some(max_steps),
+        streaming: false,
+    });
+
+    let mut problems: Vec<(i64, String)> = fs::read_dir("problems")
+        .unwrap()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path().to_string_lossy().to_string();
+            Some((problem_number(&path)?, path))
+        })
+        .collect();
+    problems.sort();
+    for (n, path) in problems {
+        res.push(Case {
+            name: format!("problem_{}", n),
+            program: fs::read_to_string(path).unwrap(),
+            max_steps: Some(problem_steps),
+            streaming: false,
+        });
+    }
+
+    let synthetic = [
+        ("stress_deep_add", deep_add(2000)),
+        ("stress_balanced_add", balanced_add(12)),
+        ("stress_recursive_sum", recursive_sum(30)),
+        ("stress_concat", concat_chain(1000)),
+        ("stress_nested_lets", nested_lets(100)),
+    ];
+    for (name, program) in synthetic {
+        res.push(Case {
+            name: name.to_string(),
+            program,
+            max_steps: Some(max_steps),
+            streaming: false,
+        });
+    }
+
+    // Parser throughput on a program of a few megabytes, both ways
+    let large = balanced_add(19);
+    for (name, streaming) in [("parse_large", false), ("parse_large_stream", true)] {
+        res.push(Case {
+            name: name.to_string(),
+            program: large.clone(),
+            max_steps: None,
+            streaming,
+        });
+    }
+    res
+}
+
+fn median(mut values: Vec<u128>) -> u128 {
+    values.sort();
+    values[values.len() / 2]
+}
+
+fn run_case(case: &Case, runs: usize) -> Timing {
+    let mut parse_times = Vec::new();
+    let mut eval_times = Vec::new();
+    let mut steps = 0;
+    for _ in 0..runs {
+        let start = Instant::now();
+        let expr_ptr = if case.streaming {
+            stream::parse_str(&case.program).unwrap()
+        } else {
+            parse_into_ast(case.program.trim().to_string())
+        };
+        parse_times.push(start.elapsed().as_micros());
+
+        let Some(max_steps) = case.max_steps else {
+            continue;
+        };
+        let start = Instant::now();
+        let (_, n) = eval_expr_limit(expr_ptr, max_steps);
+        eval_times.push(start.elapsed().as_micros());
+        steps = n;
+    }
+    Timing {
+        name: case.name.clone(),
+        parse_us: median(parse_times),
+        eval_us: Some(eval_times).filter(|t| !t.is_empty()).map(median),
+        steps,
+    }
+}
+
+fn format_results(timings: &[Timing]) -> String {
+    let mut res = "# name\tparse_us\teval_us\tsteps\n".to_string();
+    for t in timings {
+        res += &format!(
+            "{}\t{}\t{}\t{}\n",
+            t.name,
+            t.parse_us,
+            micros(t.eval_us),
+            t.steps
+        );
+    }
+    res
+}
+
+fn parse_results(text: &str) -> Vec<Timing> {
+    let mut res = Vec::new();
+    for line in text.lines() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() != 4 {
+            panic!("Malformed results line: '{}'", line);
+        }
+        res.push(Timing {
+            name: parts[0].to_string(),
+            parse_us: parts[1].parse().unwrap(),
+            eval_us: match parts[2] {
+                "-" => None,
+                us => Some(us.parse().unwrap()),
+            },
+            steps: parts[3].parse().unwrap(),
+        });
+    }
+    res
+}
+
+fn micros(us: Option<u128>) -> String {
+    us.map_or("-".to_string(), |us| us.to_string())
+}
+
+fn is_regression(old: u128, new: u128, threshold: f64) -> bool {
+    new > old + NOISE_FLOOR_US && new as f64 > old as f64 * (1.0 + threshold)
+}
+
+fn change(old: u128, new: u128) -> String {
+    if old == 0 {
+        return "n/a".to_string();
+    }
+    format!("{:+.1}%", (new as f64 / old as f64 - 1.0) * 100.0)
+}
+
+// Prints a comparison table and returns the names of regressed measurements
+fn compare(baseline: &[Timing], current: &[Timing], threshold: f64) -> Vec<String> {
+    let old_by_name: HashMap<&str, &Timing> =
+        baseline.iter().map(|t| (t.name.as_str(), t)).collect();
+    let mut regressions = Vec::new();
+    println!(
+        "\n{:<24} {:>10} {:>10} {:>9}   {:>10} {:>10} {:>9}",
+        "case", "parse_old", "parse_new", "change", "eval_old", "eval_new", "change"
+    );
+    for t in current {
+        let Some(old) = old_by_name.get(t.name.as_str()) else {
+            println!("{:<24} (not in baseline)", t.name);
+            continue;
+        };
+        let mut flags = Vec::new();
+        if is_regression(old.parse_us, t.parse_us, threshold) {
+            flags.push("parse slower");
+            regressions.push(format!("{} parse", t.name));
+        }
+        let eval_slower = match (old.eval_us, t.eval_us) {
+            (Some(old), Some(new)) => is_regression(old, new, threshold),
+            _ => false,
+        };
+        if eval_slower {
+            flags.push("eval slower");
+            regressions.push(format!("{} eval", t.name));
+        }
+        if old.steps != t.steps {
+            flags.push("steps changed");
+        }
+        let mark = if flags.is_empty() {
+            String::new()
+        } else {
+            format!("  <- {}", flags.join(", "))
+        };
+        println!(
+            "{:<24} {:>10} {:>10} {:>9}   {:>10} {:>10} {:>9}{}",
+            t.name,
+            old.parse_us,
+            t.parse_us,
+            change(old.parse_us, t.parse_us),
+            micros(old.eval_us),
+            micros(t.eval_us),
+            match (old.eval_us, t.eval_us) {
+                (Some(old), Some(new)) => change(old, new),
+                _ => "n/a".to_string(),
+            },
+            mark
+        );
+    }
+    regressions
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    let mut runs = 5;
+    let mut max_steps = 1000;
+    let mut problem_steps = 8;
+    let mut filter = String::new();
+    let mut out = DEFAULT_OUT.to_string();
+    let mut baseline = DEFAULT_BASELINE.to_string();
+    let mut save_baseline = false;
+    let mut threshold = 10.0;
+
+    let mut idx = 1;
+    while idx < args.len() {
+        let value = || args.get(idx + 1).expect("Missing flag value").clone();
+        match args[idx].as_str() {
+            "--runs" => runs = value().parse().unwrap(),
+            "--steps" => max_steps = value().parse().unwrap(),
+            "--problem-steps" => problem_steps = value().parse().unwrap(),
+            "--filter" => filter = value(),
+            "--out" => out = value(),
+            "--baseline" => baseline = value(),
+            "--threshold" => threshold = value().parse().unwrap(),
+            "--save-baseline" => {
+                save_baseline = true;
+                idx += 1;
+                continue;
+            }
+            other => panic!("Unknown argument: {}", other),
+        }
+        idx += 2;
+    }
+
+    // Tracing would dominate the measurements
+    set_debug(false);
+
+    let mut timings = Vec::new();
+    for case in collect_cases(max_steps, problem_steps) {
+        if !case.name.contains(&filter) {
+            continue;
+        }
+        let t = run_case(&case, runs);
+        let mb_per_s = case.program.len() as f64 / t.parse_us.max(1) as f64;
+        println!(
+            "{:<24} parse {:>8} us ({:>6.1} MB/s)   eval {:>10} us   steps {}",
+            t.name,
+            t.parse_us,
+            mb_per_s,
+            micros(t.eval_us),
+            t.steps
+        );
+        timings.push(t);
+    }
+
+    let text = format_results(&timings);
+    fs::write(&out, &text).unwrap();
+    println!("\nSaved results to {}", out);
+    if save_baseline {
+        fs::write(&baseline, &text).unwrap();
+        println!("Saved baseline to {}", baseline);
+        return;
+    }
+
+    let Ok(baseline_text) = fs::read_to_string(&baseline) else {
+        println!(
+            "No baseline at {}, run with --save-baseline to create one",
+            baseline
+        );
+        return;
+    };
+    let regressions = compare(&parse_results(&baseline_text), &timings, threshold / 100.0);
+    if !regressions.is_empty() {
+        println!(
+            "\n{} regression(s): {}",
+            regressions.len(),
+            regressions.join(", ")
+        );
+        std::process::exit(1);
+    }
+    println!("\nNo regressions above {}%", threshold);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_results_round_trip() {
+        let timings = vec![
+            Timing {
+                name: "a".to_string(),
+                parse_us: 10,
+                eval_us: Some(2000),
+                steps: 7,
+            },
+            Timing {
+                name: "b".to_string(),
+                parse_us: 0,
+                eval_us: Some(1),
+                steps: 10_000_000,
+            },
+            Timing {
+                name: "parse_only".to_string(),
+                parse_us: 300,
+                eval_us: None,
+                steps: 0,
+            },
+        ];
+        assert_eq!(parse_results(&format_results(&timings)), timings);
+    }
+
+    #[test]
+    fn test_is_regression() {
+        assert!(is_regression(1000, 1500, 0.1));
+        assert!(!is_regression(1000, 1050, 0.1));
+        // Large ratio but below the noise floor
+        assert!(!is_regression(10, 100, 0.1));
+    }
+
+    #[test]
+    fn test_synthetic_programs() {
+        set_debug(false);
+        assert_eq!(eval_example(&deep_add(10)), Expr::Integer(11));
+        assert_eq!(eval_example(&balanced_add(4)), Expr::Integer(16));
+        assert_eq!(eval_example(&recursive_sum(5)), Expr::Integer(15));
+        assert_eq!(
+            eval_example(&concat_chain(2)),
+            Expr::String("testtesttest".to_string())
+        );
+        assert_eq!(eval_example(&nested_lets(3)), Expr::Integer(3));
+    }
+}
@@ -0,0 +1,81 @@
+use icfpc_2024::*;
+
+use std::fs;
+use std::io::{self, Read};
+use std::process::exit;
+
+// Converts programs between the ICFP text format and other representations.
+//
+// Usage: convert <command> [FILE]
+//
+//   to-json    ICFP program -> JSON node table (see `json`)
+//   from-json  JSON node table -> ICFP program
+//   to-sexp    ICFP program -> S-expression (see `sexp`)
+//   from-sexp  S-expression -> ICFP program
+//   to-lambda  ICFP program -> lambda notation (see `lambda`)
+//   from-lambda  lambda notation -> ICFP program
+//
+// Reads FILE, or stdin when it is missing or `-`, and writes to stdout.
+
+const SEXP_WIDTH: usize = 80;
+
+fn read_input(path: Option<&String>) -> String {
+    let res = match path.map(|s| s.as_str()) {
+        None | Some("-") => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).map(|_| text)
+        }
+        Some(path) => fs::read_to_string(path),
+    };
+    res.unwrap_or_else(|e| fail(&format!("cannot read input: {}", e)))
+}
+
+fn parse_program(text: &str) -> ExprPtr {
+    parser::parse_checked(text.trim()).unwrap_or_else(|e| fail(&e.to_string()))
+}
+
+// Strings and variable ids from the other formats may have no ICFP text
+fn print_program(expr: &ExprPtr) {
+    let text = try_serialize_expr(&expr.borrow()).unwrap_or_else(|e| fail(&e.to_string()));
+    println!("{}", text);
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    exit(1);
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    let Some(command) = args.get(1) else {
+        fail("Usage: convert <to-json|from-json|to-sexp|from-sexp|to-lambda|from-lambda> [FILE]");
+    };
+    let input = read_input(args.get(2));
+    match command.as_str() {
+        "to-json" => {
+            let expr = parse_program(&input);
+            println!("{}", json::to_json(&expr.borrow()));
+        }
+        "from-json" => {
+            let expr = json::from_json(&input).unwrap_or_else(|e| fail(&e));
+            print_program(&expr);
+        }
+        "to-sexp" => {
+            let expr = parse_program(&input);
+            println!("{}", sexp::to_sexp_pretty(&expr.borrow(), SEXP_WIDTH));
+        }
+        "from-sexp" => {
+            let expr = sexp::parse_sexp(&input).unwrap_or_else(|e| fail(&e.to_string()));
+            print_program(&expr);
+        }
+        "to-lambda" => {
+            let expr = parse_program(&input);
+            println!("{}", lambda::to_lambda(&expr.borrow()));
+        }
+        "from-lambda" => {
+            let expr = lambda::parse_lambda(&input).unwrap_or_else(|e| fail(&e.to_string()));
+            print_program(&expr);
+        }
+        other => fail(&format!("Unknown command: {}", other)),
+    }
+}
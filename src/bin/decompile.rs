@@ -0,0 +1,26 @@
+use icfpc_2024::*;
+
+use std::fs;
+use std::io::{self, Read};
+
+// Prints a program as Python-style pseudocode, see `decompile`.
+//
+// Usage: decompile [FILE]
+//
+// Reads FILE, or stdin when it is missing, and writes to stdout.
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    let text = match args.get(1) {
+        Some(path) => fs::read_to_string(path).unwrap(),
+        None => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).unwrap();
+            text
+        }
+    };
+    let expr = parser::parse_checked(text.trim()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    print!("{}", decompile::decompile(&expr.borrow()));
+}
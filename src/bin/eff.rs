@@ -5,7 +5,6 @@ use std::{collections::HashMap, fs};
 
 use std::fs::File;
 use std::io::Write;
-use std::io::Result;
 use num_bigint::BigInt;
 
 
@@ -17,50 +16,44 @@ fn is_lambda(expr_ptr: ExprPtr) -> bool {
     return false;
 }
 
-fn decompose(expr_ptr: ExprPtr, level: usize, list: &mut Vec<ExprPtr>) {
-    if level > 1 && is_lambda(expr_ptr.clone()) {
-        let e_copy = as_ptr(expr_ptr.borrow().clone());
-        let id: usize = {
-            let mut res = list.len();
-            for (id2, other) in list.iter().enumerate() {
-                if *other.borrow() == *expr_ptr.borrow() {
-                    res = id2;
-                    break;
+// Replaces applications nested deeper than one level with `f<id>`
+// placeholders, collecting the extracted applications in `list`
+struct Decompose<'a> {
+    level: usize,
+    list: &'a mut Vec<ExprPtr>,
+}
+
+impl Visitor for Decompose<'_> {
+    fn visit_expr(&mut self, expr_ptr: &ExprPtr) {
+        if self.level > 1 && is_lambda(expr_ptr.clone()) {
+            let e_copy = as_ptr(expr_ptr.borrow().clone());
+            let id: usize = {
+                let mut res = self.list.len();
+                for (id2, other) in self.list.iter().enumerate() {
+                    if scope::alpha_eq(&other.borrow(), &expr_ptr.borrow()) {
+                        res = id2;
+                        break;
+                    }
                 }
+                res
+            };
+            *expr_ptr.borrow_mut() = Expr::String(format!("f{}", id));
+
+            if id == self.list.len() {
+                self.list.push(e_copy.clone());
+                decompose(e_copy.clone(), 0, self.list);
             }
-            res
-        };
-        {
-            let ref mut e = *expr_ptr.borrow_mut();
-            *e = Expr::String(format!("f{}", id));
+            return;
         }
 
-        if id == list.len() {
-            list.push(e_copy.clone());
-            decompose(e_copy.clone(), 0, list);
-        }
-        return;
+        self.level += 1;
+        walk_expr(self, expr_ptr);
+        self.level -= 1;
     }
+}
 
-    let ref e = *expr_ptr.borrow();
-    match e {
-        Expr::Unary(_, a) => {
-            decompose(a.clone(), level + 1, list);
-        }
-        Expr::Binary(_, a, b) => {
-            decompose(a.clone(), level + 1, list);
-            decompose(b.clone(), level + 1, list);
-        }
-        Expr::Lambda(_, a) => {
-            decompose(a.clone(), level + 1, list);
-        }
-        Expr::If(a, b, c) => {
-            decompose(a.clone(), level + 1, list);
-            decompose(b.clone(), level + 1, list);
-            decompose(c.clone(), level + 1, list);
-        }
-        _ => {}
-    }
+fn decompose(expr_ptr: ExprPtr, level: usize, list: &mut Vec<ExprPtr>) {
+    Decompose { level, list }.visit_expr(&expr_ptr);
 }
 
 fn decompose_expr(expr_ptr: ExprPtr) -> Vec<ExprPtr> {
@@ -70,36 +63,21 @@ fn decompose_expr(expr_ptr: ExprPtr) -> Vec<ExprPtr> {
     list
 }
 
-fn rewrite_expr(expr_ptr: ExprPtr) -> ExprPtr {
-    let ref e = *expr_ptr.borrow();
-    if let Expr::Binary('+', a, b) = e {
-        if a == b {
-            return as_ptr(Expr::Binary('*', a.clone(), as_ptr(Expr::Integer(2))));
-        } else {
-            println!("a and b are not equal: {:?}, {:?}", a, b);
-        }
-        expr_ptr.clone()
-    } else {
-        match e {
-            Expr::Lambda(x, a) => {
-                let new_a = rewrite_expr(a.clone());
-                return as_ptr(Expr::Lambda(*x, new_a));
-            }
-            Expr::Binary(op, a, b) => {
-                let new_a = rewrite_expr(a.clone());
-                let new_b = rewrite_expr(b.clone());
-                return as_ptr(Expr::Binary(*op, new_a, new_b));
-            }
-            _ => return expr_ptr.clone(),
-        }
+fn rewrite_with(expr_ptr: ExprPtr, rules: &[rewrite::Rule]) -> ExprPtr {
+    let res = rewrite::rewrite(&expr_ptr, rules, &rewrite::Options::default());
+    for fired in res.trace.iter() {
+        println!(
+            "{} at {:?}: {} -> {}",
+            fired.rule, fired.path, fired.before, fired.after
+        );
     }
+    res.expr
 }
 
-fn rewrite_expr_times(mut expr_ptr: ExprPtr, times: usize) -> ExprPtr {
-    for i in 0..times {
-        expr_ptr = rewrite_expr(expr_ptr.clone());
-    }
-    expr_ptr
+// Rewrites `x + x` into `x * 2`
+fn rewrite_expr(expr_ptr: ExprPtr) -> ExprPtr {
+    let rules = rewrite::parse_rules("(rule double (+ ?x ?x) (* ?x 2))").unwrap();
+    rewrite_with(expr_ptr, &rules)
 }
 
 mod tests {
@@ -118,14 +96,52 @@ mod tests {
             Token::Var(0),
         ];
         let (expr, _) = create_ast(&tokens, 0);
-        let new_expr = rewrite_expr_times(as_ptr(expr), 2);
+        let new_expr = rewrite_expr(as_ptr(expr));
         print_ast(new_expr);
     }
+
+    #[test]
+    fn test_convert_to_sat3() {
+        let example = fs::read_to_string("problems/7.txt").unwrap();
+        let sat3 = convert_to_sat3(parse_into_ast(example)).unwrap();
+        let expected: Vec<Vec<i64>> = fs::read_to_string("solutions/7-minisat.txt")
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let mut vals: Vec<i64> = line.split(' ').map(|x| x.parse().unwrap()).collect();
+                assert_eq!(vals.pop(), Some(0));
+                vals
+            })
+            .collect();
+        assert_eq!(sat3, expected);
+    }
+
+    #[test]
+    fn test_unexpected_clause() {
+        let clause = |text: &str| visit_single_sat3(parse_into_ast(text.to_string()));
+        assert_eq!(clause("B| v\" U! v#"), Ok(vec![1, -2]));
+        assert!(clause("B| v\" I\"").is_err());
+        assert!(clause("U! B| v\" v#").is_err());
+        assert!(visit_sat3(parse_into_ast("B& v\" U- v#".to_string())).is_err());
+    }
 }
 
 fn solve_eff_generic(name: String) {
     let example = fs::read_to_string(format!("problems/{}.txt", name)).unwrap();
-    let mut expr_ptr = parse_into_ast(example.clone());
+    let mut expr_ptr = match parser::parse_checked(&example) {
+        Ok(expr_ptr) => expr_ptr,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let options = types::Options { permissive: true };
+    if let Err(errors) = types::infer(&expr_ptr.borrow(), &options) {
+        for error in errors.iter() {
+            println!("Type error {}", error);
+        }
+    }
     // let (res, _) = eval_expr(expr_ptr.clone());
     // print_ast(expr_ptr.clone());
 
@@ -148,7 +164,7 @@ fn solve_eff_generic(name: String) {
 fn solve_eff1() {
     let example = fs::read_to_string("problems/1.txt").unwrap();
     let mut expr_ptr = parse_into_ast(example);
-    expr_ptr = rewrite_expr_times(expr_ptr, 2);
+    expr_ptr = rewrite_expr(expr_ptr);
     let (res, _) = eval_expr(expr_ptr);
     print_ast(as_ptr(res));
 }
@@ -158,9 +174,13 @@ fn solve_eff1() {
 // Integer(2134)
 fn solve_eff2() {
     let example = fs::read_to_string("problems/2.txt").unwrap();
-    let mut expr_ptr = parse_into_ast(example);
-    let (res, _) = eval_expr(expr_ptr);
-    print_ast(as_ptr(res));
+    let expr_ptr = parse_into_ast(example);
+    let options = partial::Options {
+        assume_total: true,
+        ..partial::Options::default()
+    };
+    let res = partial::partial_eval(&expr_ptr.borrow(), &options);
+    print_ast(res);
 }
 
 // == Eff 3 ==
@@ -356,80 +376,93 @@ fn fibo(n: i64, cache: &mut HashMap<i64, i64>) -> i64 {
 
 // == Eff 7 ==
 
-fn visit_single_sat3(expr_ptr: ExprPtr) -> Vec<i64> {
-    let e = &*expr_ptr.borrow();
-    match e {
-        Expr::Binary('|', a, b) => {
-            let mut res = Vec::new();
-            let mut res_a = visit_single_sat3(a.clone());
-            let mut res_b = visit_single_sat3(b.clone());
-            res.append(&mut res_a);
-            res.append(&mut res_b);
-            return res;
-        }
-        Expr::Unary('!', a) => {
-            let mut res = Vec::new();
-            let res_a = visit_single_sat3(a.clone());
-            assert_eq!(res_a.len(), 1);
-            for x in res_a {
-                res.push(-x);
-            }
-            return res;
+// Literals of a single clause `a | !b | ...`, negated variables come out
+// negative. Anything else makes the clause unexpected instead of panicking.
+#[derive(Default)]
+struct Clause {
+    literals: Vec<i64>,
+    unexpected: Option<String>,
+}
+
+impl Clause {
+    fn reject(&mut self, message: String) {
+        self.unexpected.get_or_insert(message);
+    }
+}
+
+impl Visitor for Clause {
+    fn visit_expr(&mut self, expr_ptr: &ExprPtr) {
+        match &*expr_ptr.borrow() {
+            Expr::Binary('|', _, _) | Expr::Unary('!', _) | Expr::Var(_) => {}
+            e => return self.reject(format!("Unexpected expression: {:?}", e)),
         }
-        Expr::Var(x) => {
-            return vec![*x];
+        walk_expr(self, expr_ptr);
+    }
+    fn visit_unary(&mut self, _op: char, a: &ExprPtr) {
+        match visit_single_sat3(a.clone()) {
+            Ok(res_a) if res_a.len() == 1 => self.literals.push(-res_a[0]),
+            Ok(res_a) => self.reject(format!("Negation of {} literals", res_a.len())),
+            Err(e) => self.reject(e),
         }
-        _ => { panic!("Unexpected expression: {:?}", e)}
+    }
+    fn visit_var(&mut self, x: i64) {
+        self.literals.push(x);
     }
 }
 
-fn visit_sat3(expr_ptr: ExprPtr, res: &mut Vec<Vec<i64>>) {
-    let e = &*expr_ptr.borrow();
-    match e {
-        Expr::Unary(_, a) => {
-            let sat = visit_single_sat3(expr_ptr.clone());
-            res.push(sat);
+fn visit_single_sat3(expr_ptr: ExprPtr) -> Result<Vec<i64>, String> {
+    let mut clause = Clause::default();
+    clause.visit_expr(&expr_ptr);
+    match clause.unexpected {
+        Some(e) => Err(e),
+        None => Ok(clause.literals),
+    }
+}
+
+// Every clause of the formula, a bare variable is a one-literal clause
+#[derive(Default)]
+struct Sat3 {
+    res: Vec<Vec<i64>>,
+    unexpected: Option<String>,
+}
+
+impl Visitor for Sat3 {
+    fn visit_expr(&mut self, expr_ptr: &ExprPtr) {
+        if self.unexpected.is_some() {
+            return;
         }
-        Expr::Binary(op, a, b) => {
-            if *op == '|' {
-                let sat = visit_single_sat3(expr_ptr.clone());
-                res.push(sat);
-            } else {
-                visit_sat3(a.clone(), res);
-                visit_sat3(b.clone(), res);    
+        let is_clause = matches!(
+            &*expr_ptr.borrow(),
+            Expr::Unary(_, _) | Expr::Binary('|', _, _) | Expr::Var(_)
+        );
+        if is_clause {
+            match visit_single_sat3(expr_ptr.clone()) {
+                Ok(clause) => self.res.push(clause),
+                Err(e) => self.unexpected = Some(e),
             }
-        }
-        Expr::Lambda(_, a) => {
-            visit_sat3(a.clone(), res);
-        }
-        Expr::If(a, b, c) => {
-            visit_sat3(a.clone(), res);
-            visit_sat3(b.clone(), res);
-            visit_sat3(c.clone(), res);
-        }
-        Expr::Var(x) => {
-            res.push(vec![*x]);
-        }
-        Expr::String(_) | Expr::Integer(_)=> {
-            // ignore temporary strings
-        }
-        _ => {
-            panic!("Unexpected expression: {:?}", e);
+        } else {
+            walk_expr(self, expr_ptr);
         }
     }
+}
 
+fn visit_sat3(expr_ptr: ExprPtr) -> Result<Vec<Vec<i64>>, String> {
+    let mut sat3 = Sat3::default();
+    sat3.visit_expr(&expr_ptr);
+    match sat3.unexpected {
+        Some(e) => Err(e),
+        None => Ok(sat3.res),
+    }
 }
 
-fn convert_to_sat3(expr_ptr: ExprPtr) -> Vec<Vec<i64>> {
-    let mut res = Vec::new();
+fn convert_to_sat3(expr_ptr: ExprPtr) -> Result<Vec<Vec<i64>>, String> {
     let list = decompose_expr(expr_ptr);
     for (id, l) in list.iter().enumerate() {
         // println!("\nid = {}", id);
         // print_ast(l.clone());
     }
     let last_expr = list[list.len() - 2].clone();
-    visit_sat3(last_expr, &mut res);
-    res
+    visit_sat3(last_expr)
 }
 
 
@@ -446,7 +479,13 @@ fn solve_eff7() {
     let example = fs::read_to_string("problems/7.txt").unwrap();
     let expr_ptr = parse_into_ast(example);
 
-    let sat3 = convert_to_sat3(expr_ptr);
+    let sat3 = match convert_to_sat3(expr_ptr) {
+        Ok(sat3) => sat3,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
     println!("{:?}", sat3);
 
 
@@ -552,7 +591,13 @@ fn solve_eff8() {
     let example = fs::read_to_string("problems/8.txt").unwrap();
     let expr_ptr = parse_into_ast(example);
 
-    let sat3 = convert_to_sat3(expr_ptr);
+    let sat3 = match convert_to_sat3(expr_ptr) {
+        Ok(sat3) => sat3,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
     println!("{:?}", sat3);
 
 
@@ -696,32 +741,17 @@ fn solve_eff13() {
     print_ast(as_ptr(expr4));
 }
 
-// very specific function for problem 13
+// very specific rules for problem 13: only string lengths matter
 fn adhoc_replace(expr_ptr: ExprPtr) -> ExprPtr {
-    println!("adhoc_replace at {:?}", expr_ptr);
-    let ref e = *expr_ptr.borrow();
-    match e {
-        Expr::Lambda(x, a) => {
-            let new_a = adhoc_replace(a.clone());
-            return as_ptr(Expr::Lambda(*x, new_a));
-        }
-        Expr::Binary(op, a, b) => {
-            let new_a = adhoc_replace(a.clone());
-            let new_b = adhoc_replace(b.clone());
-            let new_op = {
-                if *op == '.' {
-                    '+'
-                } else {
-                    *op
-                }
-            };
-            return as_ptr(Expr::Binary(new_op, new_a, new_b));
-        }
-        Expr::String(s) => {
-            return as_ptr(Expr::Integer(s.len() as i64));
-        }
-        _ => return expr_ptr.clone(),
-    }
+    let source = "(rule concat-to-add (concat ?a ?b) (+ ?a ?b))";
+    let mut rules = rewrite::parse_rules(source).unwrap();
+    rules.push(rewrite::Rule::computed("strlen", "?s:str", |b| {
+        let Expr::String(s) = &*b.exprs["s"].borrow() else {
+            return None;
+        };
+        Some(Expr::Integer(s.len() as i64))
+    }));
+    rewrite_with(expr_ptr, &rules)
 }
 
 fn main() {
@@ -0,0 +1,24 @@
+use icfpc_2024::fuzz::{fuzz, GenConfig};
+
+// Usage: fuzz [seed] [cases] [max_size]
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    let seed: u64 = args.get(1).map_or(0, |s| s.parse().unwrap());
+    let cases: usize = args.get(2).map_or(1000, |s| s.parse().unwrap());
+    let mut config = GenConfig::default();
+    if let Some(s) = args.get(3) {
+        config.max_size = s.parse().unwrap();
+    }
+
+    let report = fuzz(seed, cases, &config);
+    for failure in report.failures.iter() {
+        println!("\nseed = {}", failure.seed);
+        println!("program = {}", failure.program);
+        println!("failure = {:?}", failure.kind);
+    }
+    println!(
+        "\n{} cases, {} failures",
+        report.cases,
+        report.failures.len()
+    );
+}
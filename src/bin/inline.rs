@@ -0,0 +1,42 @@
+use icfpc_2024::*;
+
+use std::fs;
+use std::io::{self, Read};
+
+// Inlines lambda applications where that makes the program shorter and drops
+// the ones whose variable is unused, see `inline`.
+//
+// Usage: inline [--dead-only] [FILE]
+//
+// With --dead-only only the unused applications are dropped. Reads FILE, or
+// stdin when it is missing, writes the program to stdout and the size change
+// to stderr.
+fn main() {
+    let mut args = std::env::args().skip(1).collect::<Vec<String>>();
+    let dead_only = args.first().is_some_and(|arg| arg == "--dead-only");
+    if dead_only {
+        args.remove(0);
+    }
+    let text = match args.first() {
+        Some(path) => fs::read_to_string(path).unwrap(),
+        None => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).unwrap();
+            text
+        }
+    };
+    let expr = parser::parse_checked(text.trim()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let (res, report) = if dead_only {
+        inline::eliminate_dead(&expr.borrow())
+    } else {
+        inline::inline(&expr.borrow(), &inline::Options::default())
+    };
+    println!("{}", serialize_expr(&res.borrow()));
+    eprintln!(
+        "{} -> {} bytes, {} inlined, {} dropped",
+        report.before, report.after, report.inlined, report.dropped
+    );
+}
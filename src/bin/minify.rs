@@ -0,0 +1,35 @@
+use icfpc_2024::*;
+
+use std::fs;
+use std::io::{self, Read};
+
+// Renames lambda variables to the shortest ids that keep the program's
+// meaning, see `minify`.
+//
+// Usage: minify [FILE]
+//
+// Reads FILE, or stdin when it is missing, writes the program to stdout and
+// the size change to stderr.
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    let text = match args.get(1) {
+        Some(path) => fs::read_to_string(path).unwrap(),
+        None => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).unwrap();
+            text
+        }
+    };
+    let expr = parser::parse_checked(text.trim()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let (res, report) = minify::minify(&expr.borrow());
+    println!("{}", serialize_expr(&res.borrow()));
+    eprintln!(
+        "{} -> {} bytes, saved {}",
+        report.before,
+        report.after,
+        report.saved()
+    );
+}
@@ -32,9 +32,7 @@ pub async fn run_repl_loop() {
 pub async fn run_repl(input: &str) -> Result<(), Error> {
     // let send = "get language_test".to_string();
     let send = input.to_string();
-    let encoded = encode_string(send);
-    let encoded_prefix: String = encoded.iter().collect();
-    let encoded_string = "S".to_string() + &encoded_prefix;
+    let encoded_string = token_to_string(&Token::String(send));
     println!("Hello, world!");
     println!("{:?}", parse_token(encoded_string.clone()));
 
@@ -0,0 +1,41 @@
+use icfpc_2024::*;
+
+use std::fs;
+use std::io::{self, Read};
+
+// Compiles a program to a Rust module that runs it natively, see
+// `transpile`.
+//
+// Usage: transpile [FILE]
+//        transpile --cases CASES
+//
+// Reads FILE, or stdin when it is missing, and writes the module to stdout.
+// Save it as src/bin/<name>.rs and `cargo run --release --bin <name>`. With
+// --cases it writes a module per conformance case in CASES instead, the
+// checked-in tests/transpiled/cases.rs.
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if args.first().is_some_and(|arg| arg == "--cases") {
+        let path = args.get(1).expect("missing CASES");
+        let text = fs::read_to_string(path).unwrap();
+        let cases = conformance::parse_cases(&text, ".").unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        print!("{}", transpile::transpile_cases(&cases));
+        return;
+    }
+    let text = match args.first() {
+        Some(path) => fs::read_to_string(path).unwrap(),
+        None => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).unwrap();
+            text
+        }
+    };
+    let expr = parser::parse_checked(text.trim()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    print!("{}", transpile::transpile(&expr.borrow()));
+}
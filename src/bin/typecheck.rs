@@ -0,0 +1,50 @@
+use icfpc_2024::*;
+
+use std::fs;
+use std::io::{self, Read};
+
+// Infers the type of a program, or lists its ill-typed subterms, see
+// `types`.
+//
+// Usage: typecheck [--strict] [FILE]
+//
+// The fixed-point combinators are allowed unless --strict is given. Reads
+// FILE, or stdin when it is missing. Prints the type, or each error with the
+// start of the subterm to stderr and exits with 1.
+fn main() {
+    let mut args = std::env::args().skip(1).collect::<Vec<String>>();
+    let strict = args.first().is_some_and(|arg| arg == "--strict");
+    if strict {
+        args.remove(0);
+    }
+    let text = match args.first() {
+        Some(path) => fs::read_to_string(path).unwrap(),
+        None => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).unwrap();
+            text
+        }
+    };
+    let expr = parser::parse_checked(text.trim()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let options = types::Options {
+        permissive: !strict,
+    };
+    let res = types::infer(&expr.borrow(), &options);
+    match res {
+        Ok(t) => println!("{}", t),
+        Err(errors) => {
+            for error in errors.iter() {
+                let node = error
+                    .path
+                    .iter()
+                    .fold(expr.clone(), |e, i| children(&e.borrow())[*i].clone());
+                let text: String = serialize_expr(&node.borrow()).chars().take(40).collect();
+                eprintln!("{}, in {}", error, text);
+            }
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,579 @@
+use crate::symbolic::{Term, TermPtr};
+use crate::*;
+
+use std::collections::{HashMap, HashSet};
+
+// Bit-blasting: lowers a constraint from `symbolic` into CNF, the clauses
+// `convert_to_sat3` writes for problems 7 and 8. Integers are words of
+// `Options::width` bits in two's complement, least significant bit first,
+// and every operator becomes a circuit whose gates get one variable each
+// (Tseitin encoding). Variable 1 is always true, it stands for constant
+// bits, and gates on constants are folded, so `x / 8 % 2` is a shift and a
+// mask rather than a divider.
+//
+// Every operation is required not to overflow, along with the other ways
+// the evaluator fails, so a model is an input the constraint really holds
+// for. Inputs that need wider intermediate values are not found, use a
+// larger width for those.
+
+const TRUE: i64 = 1;
+const FALSE: i64 = -1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    // Bits of every integer, at most 64
+    pub width: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { width: 64 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    Width(usize),
+    // Constant that does not fit in the width
+    Constant(i64),
+    // The constraint is an integer
+    NotABoolean,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Width(w) => write!(f, "width {} is not in 2..=64", w),
+            Error::Constant(x) => write!(f, "constant {} does not fit", x),
+            Error::NotABoolean => write!(f, "constraint is not a boolean"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Clone, Debug)]
+pub struct Cnf {
+    pub clauses: Vec<Vec<i64>>,
+    pub variables: i64,
+    // Variables of the input bits, least significant first
+    pub input: Vec<i64>,
+}
+
+impl Cnf {
+    // DIMACS text for external solvers like minisat
+    pub fn dimacs(&self) -> String {
+        let mut res = format!("p cnf {} {}\n", self.variables, self.clauses.len());
+        for clause in self.clauses.iter() {
+            for literal in clause.iter() {
+                res += &format!("{} ", literal);
+            }
+            res += "0\n";
+        }
+        res
+    }
+
+    // The input of a model, given as the literals a solver prints: `v` for a
+    // true variable, `-v` for a false one
+    pub fn input_value(&self, model: &[i64]) -> i64 {
+        let true_vars: HashSet<i64> = model.iter().filter(|l| **l > 0).copied().collect();
+        let bits: Vec<bool> = self.input.iter().map(|v| true_vars.contains(v)).collect();
+        from_bits(&bits)
+    }
+}
+
+// Two's complement, sign-extended from the last bit
+fn from_bits(bits: &[bool]) -> i64 {
+    let mut res: i64 = 0;
+    for (i, bit) in bits.iter().enumerate().take(64) {
+        if *bit {
+            res |= 1 << i;
+        }
+    }
+    if bits.len() < 64 && bits.last() == Some(&true) {
+        res |= -1 << bits.len();
+    }
+    res
+}
+
+type Word = Vec<i64>;
+
+#[derive(Clone)]
+enum Encoded {
+    Bool(i64),
+    Int(Word),
+}
+
+struct Encoder {
+    width: usize,
+    clauses: Vec<Vec<i64>>,
+    variables: i64,
+    input: Word,
+    // By term, terms are shared between paths and conditions
+    terms: HashMap<*const Term, Encoded>,
+    // By gate and inputs, so equal gates share a variable
+    gates: HashMap<(char, i64, i64), i64>,
+}
+
+impl Encoder {
+    fn fresh(&mut self) -> i64 {
+        self.variables += 1;
+        self.variables
+    }
+
+    fn assert(&mut self, literal: i64) {
+        if literal != TRUE {
+            self.clauses.push(vec![literal]);
+        }
+    }
+
+    fn and(&mut self, a: i64, b: i64) -> i64 {
+        if a == FALSE || b == FALSE || a == -b {
+            return FALSE;
+        }
+        if a == TRUE || a == b {
+            return b;
+        }
+        if b == TRUE {
+            return a;
+        }
+        let key = ('&', a.min(b), a.max(b));
+        if let Some(v) = self.gates.get(&key) {
+            return *v;
+        }
+        let v = self.fresh();
+        self.clauses.push(vec![-v, a]);
+        self.clauses.push(vec![-v, b]);
+        self.clauses.push(vec![v, -a, -b]);
+        self.gates.insert(key, v);
+        v
+    }
+
+    fn or(&mut self, a: i64, b: i64) -> i64 {
+        -self.and(-a, -b)
+    }
+
+    fn xor(&mut self, a: i64, b: i64) -> i64 {
+        match (a, b) {
+            (FALSE, x) | (x, FALSE) => return x,
+            (TRUE, x) | (x, TRUE) => return -x,
+            _ if a == b => return FALSE,
+            _ if a == -b => return TRUE,
+            _ => {}
+        }
+        let key = ('^', a.min(b), a.max(b));
+        if let Some(v) = self.gates.get(&key) {
+            return *v;
+        }
+        let v = self.fresh();
+        self.clauses.push(vec![-v, a, b]);
+        self.clauses.push(vec![-v, -a, -b]);
+        self.clauses.push(vec![v, -a, b]);
+        self.clauses.push(vec![v, a, -b]);
+        self.gates.insert(key, v);
+        v
+    }
+
+    // `a` if `s` else `b`
+    fn mux(&mut self, s: i64, a: i64, b: i64) -> i64 {
+        let x = self.and(s, a);
+        let y = self.and(-s, b);
+        self.or(x, y)
+    }
+
+    fn all(&mut self, literals: &[i64]) -> i64 {
+        literals.iter().fold(TRUE, |acc, l| self.and(acc, *l))
+    }
+
+    fn constant(&self, x: i64) -> Result<Word, Error> {
+        let w = self.width;
+        if w < 64 && (x < -(1 << (w - 1)) || x >= 1 << (w - 1)) {
+            return Err(Error::Constant(x));
+        }
+        Ok((0..w)
+            .map(|i| if (x >> i) & 1 == 1 { TRUE } else { FALSE })
+            .collect())
+    }
+
+    // Sum and carry out, without an overflow check
+    fn adder(&mut self, a: &[i64], b: &[i64], carry: i64) -> (Word, i64) {
+        let mut carry = carry;
+        let mut sum = Vec::with_capacity(a.len());
+        for (x, y) in a.iter().zip(b.iter()) {
+            let half = self.xor(*x, *y);
+            sum.push(self.xor(half, carry));
+            let both = self.and(*x, *y);
+            let propagated = self.and(half, carry);
+            carry = self.or(both, propagated);
+        }
+        (sum, carry)
+    }
+
+    // `a + b`, or `a - b` with `subtract`, failing on signed overflow
+    fn add(&mut self, a: &[i64], b: &[i64], subtract: bool) -> Word {
+        let b: Word = if subtract {
+            b.iter().map(|l| -l).collect()
+        } else {
+            b.to_vec()
+        };
+        let carry = if subtract { TRUE } else { FALSE };
+        let (sum, _) = self.adder(a, &b, carry);
+        let (sa, sb, ss) = (*a.last().unwrap(), *b.last().unwrap(), *sum.last().unwrap());
+        // Operands of the same sign and a result of the other
+        let differ = self.xor(sa, sb);
+        let flipped = self.xor(sa, ss);
+        let overflow = self.and(-differ, flipped);
+        self.assert(-overflow);
+        sum
+    }
+
+    fn negate(&mut self, a: &[i64]) -> Word {
+        let zero = vec![FALSE; a.len()];
+        self.add(&zero, a, true)
+    }
+
+    // `-x` if `s` else `x`, as `(x ^ s) + s`, without an overflow check
+    fn negate_if(&mut self, x: &[i64], s: i64) -> Word {
+        let mut carry = s;
+        let mut res = Vec::with_capacity(x.len());
+        for l in x.iter() {
+            let flipped = self.xor(*l, s);
+            res.push(self.xor(flipped, carry));
+            carry = self.and(flipped, carry);
+        }
+        res
+    }
+
+    fn multiply(&mut self, a: &[i64], b: &[i64]) -> Word {
+        let w = a.len();
+        // Sign-extended to twice the width, the product then fits
+        let extend = |x: &[i64]| -> Word {
+            let mut res = x.to_vec();
+            res.resize(2 * w, *x.last().unwrap());
+            res
+        };
+        let (a, b) = (extend(a), extend(b));
+        let mut product = vec![FALSE; 2 * w];
+        for (i, bit) in b.iter().enumerate() {
+            let row: Word = (0..2 * w)
+                .map(|j| if j < i { FALSE } else { a[j - i] })
+                .map(|l| self.and(l, *bit))
+                .collect();
+            product = self.adder(&product, &row, FALSE).0;
+        }
+        // Fits when the upper half repeats the sign bit
+        let sign = product[w - 1];
+        for l in product[w..].iter().copied() {
+            let differs = self.xor(l, sign);
+            self.assert(-differs);
+        }
+        product.truncate(w);
+        product
+    }
+
+    fn constant_value(word: &[i64]) -> Option<u64> {
+        let mut res = 0;
+        for (i, l) in word.iter().enumerate() {
+            match *l {
+                TRUE => res |= 1 << i,
+                FALSE => {}
+                _ => return None,
+            }
+        }
+        Some(res)
+    }
+
+    // Unsigned quotient and remainder, `b` is not 0
+    fn divide_unsigned(&mut self, a: &[i64], b: &[i64]) -> (Word, Word) {
+        let w = a.len();
+        if let Some(d) = Encoder::constant_value(b).filter(|d| d.is_power_of_two()) {
+            let k = d.trailing_zeros() as usize;
+            let mut quotient = a[k..].to_vec();
+            quotient.resize(w, FALSE);
+            let mut remainder = a[..k].to_vec();
+            remainder.resize(w, FALSE);
+            return (quotient, remainder);
+        }
+        // Restoring division, one quotient bit per row
+        let mut remainder = vec![FALSE; w + 1];
+        let mut divisor = b.to_vec();
+        divisor.push(FALSE);
+        let negated: Word = divisor.iter().map(|l| -l).collect();
+        let mut quotient = vec![FALSE; w];
+        for i in (0..w).rev() {
+            remainder.pop();
+            remainder.insert(0, a[i]);
+            let (difference, no_borrow) = self.adder(&remainder, &negated, TRUE);
+            quotient[i] = no_borrow;
+            remainder = (0..w + 1)
+                .map(|j| self.mux(no_borrow, difference[j], remainder[j]))
+                .collect();
+        }
+        remainder.truncate(w);
+        (quotient, remainder)
+    }
+
+    // Truncating `a / b` and `a % b`, failing like the evaluator
+    fn divide(&mut self, a: &[i64], b: &[i64], op: char) -> Word {
+        let w = a.len();
+        let b_zero = self.all(&b.iter().map(|l| -l).collect::<Word>());
+        self.assert(-b_zero);
+        let mut min = vec![FALSE; w];
+        min[w - 1] = TRUE;
+        let a_min = self.equal(a, &min);
+        let b_minus_one = self.all(b);
+        let overflow = self.and(a_min, b_minus_one);
+        self.assert(-overflow);
+        let (sa, sb) = (*a.last().unwrap(), *b.last().unwrap());
+        // Magnitudes as unsigned words, `|MIN|` fits
+        let (abs_a, abs_b) = (self.negate_if(a, sa), self.negate_if(b, sb));
+        let (quotient, remainder) = self.divide_unsigned(&abs_a, &abs_b);
+        // The quotient is negative when the signs differ, the remainder has
+        // the sign of `a`
+        let (res, sign) = if op == '/' {
+            let differ = self.xor(sa, sb);
+            (quotient, differ)
+        } else {
+            (remainder, sa)
+        };
+        self.negate_if(&res, sign)
+    }
+
+    fn equal(&mut self, a: &[i64], b: &[i64]) -> i64 {
+        let same: Word = a
+            .iter()
+            .zip(b.iter())
+            .map(|(x, y)| -self.xor(*x, *y))
+            .collect();
+        self.all(&same)
+    }
+
+    fn less(&mut self, a: &[i64], b: &[i64]) -> i64 {
+        // The sign of `a - b` with one more bit, which cannot overflow
+        let mut a = a.to_vec();
+        a.push(*a.last().unwrap());
+        let mut b: Word = b.iter().map(|l| -l).collect();
+        b.push(*b.last().unwrap());
+        let (difference, _) = self.adder(&a, &b, TRUE);
+        *difference.last().unwrap()
+    }
+
+    fn int(&mut self, term: &TermPtr) -> Result<Word, Error> {
+        match self.encode(term)? {
+            Encoded::Int(word) => Ok(word),
+            Encoded::Bool(_) => Err(Error::NotABoolean),
+        }
+    }
+
+    fn boolean(&mut self, term: &TermPtr) -> Result<i64, Error> {
+        match self.encode(term)? {
+            Encoded::Bool(l) => Ok(l),
+            Encoded::Int(_) => Err(Error::NotABoolean),
+        }
+    }
+
+    fn encode(&mut self, term: &TermPtr) -> Result<Encoded, Error> {
+        let key = Rc::as_ptr(term);
+        if let Some(res) = self.terms.get(&key) {
+            return Ok(res.clone());
+        }
+        let res = match &**term {
+            Term::Input => Encoded::Int(self.input.clone()),
+            Term::Integer(x) => Encoded::Int(self.constant(*x)?),
+            Term::Boolean(b) => Encoded::Bool(if *b { TRUE } else { FALSE }),
+            Term::Unary('-', a) => {
+                let a = self.int(a)?;
+                Encoded::Int(self.negate(&a))
+            }
+            Term::Unary(_, a) => Encoded::Bool(-self.boolean(a)?),
+            Term::Binary(op @ ('|' | '&'), a, b) => {
+                let (a, b) = (self.boolean(a)?, self.boolean(b)?);
+                let res = if *op == '|' {
+                    self.or(a, b)
+                } else {
+                    self.and(a, b)
+                };
+                Encoded::Bool(res)
+            }
+            Term::Binary('=', a, b) => match (self.encode(a)?, self.encode(b)?) {
+                (Encoded::Int(a), Encoded::Int(b)) => Encoded::Bool(self.equal(&a, &b)),
+                (Encoded::Bool(a), Encoded::Bool(b)) => Encoded::Bool(-self.xor(a, b)),
+                _ => return Err(Error::NotABoolean),
+            },
+            Term::Binary(op, a, b) => {
+                let (a, b) = (self.int(a)?, self.int(b)?);
+                match op {
+                    '+' => Encoded::Int(self.add(&a, &b, false)),
+                    '-' => Encoded::Int(self.add(&a, &b, true)),
+                    '*' => Encoded::Int(self.multiply(&a, &b)),
+                    '/' | '%' => Encoded::Int(self.divide(&a, &b, *op)),
+                    '<' => Encoded::Bool(self.less(&a, &b)),
+                    '>' => Encoded::Bool(self.less(&b, &a)),
+                    _ => panic!("Unexpected op: {}", op),
+                }
+            }
+        };
+        self.terms.insert(key, res.clone());
+        Ok(res)
+    }
+}
+
+// Clauses that hold exactly for the inputs `constraint` is true for, within
+// the width
+pub fn bitblast(constraint: &TermPtr, options: &Options) -> Result<Cnf, Error> {
+    if !(2..=64).contains(&options.width) {
+        return Err(Error::Width(options.width));
+    }
+    let mut encoder = Encoder {
+        width: options.width,
+        clauses: vec![vec![TRUE]],
+        variables: 1,
+        input: Vec::new(),
+        terms: HashMap::new(),
+        gates: HashMap::new(),
+    };
+    encoder.input = (0..options.width).map(|_| encoder.fresh()).collect();
+    let res = encoder.boolean(constraint)?;
+    encoder.assert(res);
+    Ok(Cnf {
+        clauses: encoder.clauses,
+        variables: encoder.variables,
+        input: encoder.input,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recursion::extract_recursion;
+    use crate::symbolic::{conjunction, execute, Output};
+
+    // Whether the clauses hold for `input`. Every other variable is a gate,
+    // so unit propagation decides it.
+    fn holds(cnf: &Cnf, input: i64) -> bool {
+        let mut values: HashMap<i64, bool> = HashMap::new();
+        for (i, v) in cnf.input.iter().enumerate() {
+            values.insert(*v, (input >> i) & 1 == 1);
+        }
+        loop {
+            let mut changed = false;
+            for clause in cnf.clauses.iter() {
+                let value = |l: &i64| values.get(&l.abs()).map(|v| *v == (*l > 0));
+                if clause.iter().any(|l| value(l) == Some(true)) {
+                    continue;
+                }
+                let open: Vec<i64> = clause
+                    .iter()
+                    .filter(|l| value(l).is_none())
+                    .copied()
+                    .collect();
+                match open.len() {
+                    0 => return false,
+                    1 => {
+                        values.insert(open[0].abs(), open[0] > 0);
+                        changed = true;
+                    }
+                    _ => {}
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        assert_eq!(values.len() as i64, cnf.variables);
+        true
+    }
+
+    // Constraint for `program` returning true
+    fn constraint(program: &str) -> TermPtr {
+        let expr = parse_into_ast(program.to_string());
+        let paths = execute(&expr.borrow(), 0, &symbolic::Options::default()).unwrap();
+        // Any of the paths
+        paths
+            .iter()
+            .filter_map(|path| path.constraint(&Expr::Boolean(true)))
+            .reduce(|a, b| Rc::new(Term::Binary('|', a, b)))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_operators() {
+        let programs = [
+            "L! B< B* v! v! I?",
+            "L! B= B/ v! I$ U- I#",
+            "L! B= B% v! I& U- I\"",
+            "L! B= B% B/ v! I% I# I\"",
+            "L! B= B% v! U- I% I\"",
+            "L! B> U- v! I(",
+            "L! B= B- v! I* B* I$ v!",
+            "L! B| B= v! I$ U! B< v! I!",
+            "L! ? B< v! I! B= B/ I7 B- v! I\" U- I& B> v! I?",
+        ];
+        let options = Options { width: 16 };
+        for program in programs.iter() {
+            let term = constraint(program);
+            let cnf = bitblast(&term, &options).unwrap();
+            for x in -32..32 {
+                let expected = symbolic::evaluate(&term, x) == Some(Expr::Boolean(true));
+                assert_eq!(holds(&cnf, x), expected, "{} at {}", program, x);
+            }
+        }
+    }
+
+    #[test]
+    fn test_overflow() {
+        // `x + 1` overflows for the largest 4-bit number
+        let term = constraint("L! B> B+ v! I\" v!");
+        let cnf = bitblast(&term, &Options { width: 4 }).unwrap();
+        let sat: Vec<i64> = (-8..8).filter(|x| holds(&cnf, *x)).collect();
+        assert_eq!(sat, (-8..7).collect::<Vec<i64>>());
+        // ... and so do `-x` and `x / -1` for the smallest
+        for program in ["L! B> U- v! I$", "L! B< B/ v! U- I\" I$"] {
+            let cnf = bitblast(&constraint(program), &Options { width: 4 }).unwrap();
+            assert!(!holds(&cnf, -8), "{}", program);
+        }
+        let term = constraint("L! B< v! I*");
+        assert_eq!(
+            bitblast(&term, &Options { width: 4 }).unwrap_err(),
+            Error::Constant(9)
+        );
+        assert_eq!(
+            bitblast(&term, &Options { width: 65 }).unwrap_err(),
+            Error::Width(65)
+        );
+    }
+
+    #[test]
+    fn test_input_value() {
+        let term = constraint("L! B< v! I!");
+        let cnf = bitblast(&term, &Options { width: 4 }).unwrap();
+        let model = |bits: [bool; 4]| -> Vec<i64> {
+            let mut res = vec![1];
+            for (v, bit) in cnf.input.iter().zip(bits.iter()) {
+                res.push(if *bit { *v } else { -*v });
+            }
+            res
+        };
+        assert_eq!(cnf.input_value(&model([true, false, true, false])), 5);
+        assert_eq!(cnf.input_value(&model([true, false, false, true])), -7);
+        let header = format!("p cnf {} {}\n", cnf.variables, cnf.clauses.len());
+        assert!(cnf.dimacs().starts_with(&header));
+        assert!(cnf.dimacs().ends_with(" 0\n"));
+    }
+
+    #[test]
+    fn test_problem_7() {
+        // Bit extraction by constant powers of two needs no divider
+        let text = fs::read_to_string("problems/7.txt").unwrap();
+        let extracted = extract_recursion(&parse_into_ast(text.trim().to_string()).borrow());
+        let def = &extracted.definitions[0];
+        let validator = Expr::Lambda(def.params[0], def.body.clone());
+        let paths = execute(&validator, 0, &symbolic::Options::default()).unwrap();
+        assert_eq!(paths[0].output, Output::Term(Rc::new(Term::Input)));
+        let term = conjunction(&paths[0].conditions);
+        let cnf = bitblast(&term, &Options::default()).unwrap();
+        assert!(cnf.clauses.len() < 100_000, "{}", cnf.clauses.len());
+        assert!(holds(&cnf, 584302217761));
+        assert!(!holds(&cnf, 584302217760));
+    }
+}
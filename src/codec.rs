@@ -0,0 +1,357 @@
+use crate::*;
+
+use num_bigint::{BigInt, Sign};
+
+// Fallible conversions between token text and values. Integers are base-94
+// digits `!`..`~` (most significant first), strings map every character in
+// `!`..`~` through `TRANSLATION_TABLE`. The language has no negative
+// literals: a negative number is written as `U-` applied to its absolute
+// value (see `serialize_expr`), so encoding one directly is an error.
+
+pub const UNARY_OPS: &str = "-!#$";
+pub const BINARY_OPS: &str = "+-*/%<>=|&.TD$";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CodecError {
+    EmptyToken,
+    MissingDigits,
+    InvalidDigit(char),
+    // Digits of a number that does not fit in i64
+    Overflow(String),
+    // Number that has no literal form
+    Negative(String),
+    // Character of an encoded string outside `!`..`~`
+    InvalidStringChar(char),
+    // Character that is not in `TRANSLATION_TABLE`
+    Unencodable(char),
+    UnknownIndicator(char),
+    // Body after an indicator that takes none, like `TT`
+    TrailingBody(char, String),
+    MissingOperator(&'static str),
+    UnknownOperator(&'static str, char),
+    LongOperator(&'static str, String),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodecError::EmptyToken => write!(f, "empty token"),
+            CodecError::MissingDigits => write!(f, "missing base-94 digits"),
+            CodecError::InvalidDigit(c) => write!(f, "invalid base-94 digit {:?}", c),
+            CodecError::Overflow(digits) => {
+                write!(f, "number '{}' overflows i64", digits)
+            }
+            CodecError::Negative(x) => {
+                write!(f, "negative number {} has no literal form", x)
+            }
+            CodecError::InvalidStringChar(c) => write!(f, "invalid string character {:?}", c),
+            CodecError::Unencodable(c) => write!(f, "character {:?} cannot be encoded", c),
+            CodecError::UnknownIndicator(c) => write!(f, "unknown token indicator '{}'", c),
+            CodecError::TrailingBody(c, body) => {
+                write!(f, "unexpected '{}' after '{}'", body, c)
+            }
+            CodecError::MissingOperator(kind) => write!(f, "missing {} operator", kind),
+            CodecError::UnknownOperator(kind, op) => {
+                write!(f, "unknown {} operator '{}'", kind, op)
+            }
+            CodecError::LongOperator(kind, body) => {
+                write!(f, "{} operator must be one character, got '{}'", kind, body)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+fn digit(c: char) -> Result<u8, CodecError> {
+    if ('!'..='~').contains(&c) {
+        Ok(c as u8 - b'!')
+    } else {
+        Err(CodecError::InvalidDigit(c))
+    }
+}
+
+fn digit_char(d: u8) -> char {
+    (d + b'!') as char
+}
+
+pub fn encode_int(x: i64) -> Result<String, CodecError> {
+    if x < 0 {
+        return Err(CodecError::Negative(x.to_string()));
+    }
+    if x == 0 {
+        return Ok("!".to_string());
+    }
+    let mut res = Vec::new();
+    let mut x = x;
+    while x > 0 {
+        res.push(digit_char((x % 94) as u8));
+        x /= 94;
+    }
+    Ok(res.iter().rev().collect())
+}
+
+pub fn decode_int(digits: &str) -> Result<i64, CodecError> {
+    if digits.is_empty() {
+        return Err(CodecError::MissingDigits);
+    }
+    let mut res: i64 = 0;
+    for c in digits.chars() {
+        let d = digit(c)?;
+        res = res
+            .checked_mul(94)
+            .and_then(|x| x.checked_add(d as i64))
+            .ok_or_else(|| CodecError::Overflow(digits.to_string()))?;
+    }
+    Ok(res)
+}
+
+// `I` token for base-94 digits, a bignum where they overflow i64
+pub fn decode_integer(digits: &str) -> Result<Token, CodecError> {
+    match decode_int(digits) {
+        Err(CodecError::Overflow(_)) => decode_bigint(digits).map(Token::BigInteger),
+        res => res.map(Token::Integer),
+    }
+}
+
+pub fn encode_bigint(x: &BigInt) -> Result<String, CodecError> {
+    if x.sign() == Sign::Minus {
+        return Err(CodecError::Negative(x.to_string()));
+    }
+    // Zero comes out as a single 0 digit
+    Ok(x.to_radix_be(94).1.into_iter().map(digit_char).collect())
+}
+
+pub fn decode_bigint(digits: &str) -> Result<BigInt, CodecError> {
+    if digits.is_empty() {
+        return Err(CodecError::MissingDigits);
+    }
+    let values = digits.chars().map(digit).collect::<Result<Vec<u8>, _>>()?;
+    Ok(BigInt::from_radix_be(Sign::Plus, &values, 94).unwrap())
+}
+
+pub fn is_encodable(s: &str) -> bool {
+    s.chars()
+        .all(|c| TRANSLATION_TABLE_REVERSE.contains_key(&c))
+}
+
+pub fn encode_str(s: &str) -> Result<String, CodecError> {
+    s.chars()
+        .map(|c| match TRANSLATION_TABLE_REVERSE.get(&c) {
+            Some(idx) => Ok(digit_char(*idx as u8)),
+            None => Err(CodecError::Unencodable(c)),
+        })
+        .collect()
+}
+
+pub fn decode_str(body: &str) -> Result<String, CodecError> {
+    body.chars()
+        .map(|c| match digit(c) {
+            Ok(d) => Ok(TRANSLATION_TABLE[d as usize]),
+            Err(_) => Err(CodecError::InvalidStringChar(c)),
+        })
+        .collect()
+}
+
+fn check_operator(op: char, kind: &'static str, valid: &str) -> Result<char, CodecError> {
+    if valid.contains(op) {
+        Ok(op)
+    } else {
+        Err(CodecError::UnknownOperator(kind, op))
+    }
+}
+
+fn decode_operator(body: &str, kind: &'static str, valid: &str) -> Result<char, CodecError> {
+    let mut chars = body.chars();
+    match (chars.next(), chars.next()) {
+        (None, _) => Err(CodecError::MissingOperator(kind)),
+        (Some(op), None) => check_operator(op, kind, valid),
+        (Some(_), Some(_)) => Err(CodecError::LongOperator(kind, body.to_string())),
+    }
+}
+
+pub fn encode_token(token: &Token) -> Result<String, CodecError> {
+    let res = match token {
+        Token::Boolean(true) => "T".to_string(),
+        Token::Boolean(false) => "F".to_string(),
+        Token::Integer(x) => format!("I{}", encode_int(*x)?),
+        Token::BigInteger(x) => format!("I{}", encode_bigint(x)?),
+        Token::String(s) => format!("S{}", encode_str(s)?),
+        Token::Unary(op) => format!("U{}", check_operator(*op, "unary", UNARY_OPS)?),
+        Token::Binary(op) => format!("B{}", check_operator(*op, "binary", BINARY_OPS)?),
+        Token::If => "?".to_string(),
+        Token::Lambda(x) => format!("L{}", encode_int(*x)?),
+        Token::Var(x) => format!("v{}", encode_int(*x)?),
+    };
+    Ok(res)
+}
+
+// Strict inverse of `encode_token`: the whole of `s` must be one token
+pub fn decode_token(s: &str) -> Result<Token, CodecError> {
+    let mut chars = s.chars();
+    let indicator = chars.next().ok_or(CodecError::EmptyToken)?;
+    let body = chars.as_str();
+    let no_body = |token: Token| {
+        if body.is_empty() {
+            Ok(token)
+        } else {
+            Err(CodecError::TrailingBody(indicator, body.to_string()))
+        }
+    };
+    match indicator {
+        'T' => no_body(Token::Boolean(true)),
+        'F' => no_body(Token::Boolean(false)),
+        '?' => no_body(Token::If),
+        'I' => decode_integer(body),
+        'S' => decode_str(body).map(Token::String),
+        'U' => decode_operator(body, "unary", UNARY_OPS).map(Token::Unary),
+        'B' => decode_operator(body, "binary", BINARY_OPS).map(Token::Binary),
+        'L' => decode_int(body).map(Token::Lambda),
+        'v' => decode_int(body).map(Token::Var),
+        _ => Err(CodecError::UnknownIndicator(indicator)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz::Rng;
+
+    const CASES: u64 = 2000;
+
+    // Canonical digits: no leading zero digit unless the number is zero
+    fn random_digits(rng: &mut Rng, max_len: u64) -> String {
+        let len = 1 + rng.below(max_len);
+        let mut res: String = (0..len).map(|_| digit_char(rng.below(94) as u8)).collect();
+        while res.len() > 1 && res.starts_with('!') {
+            res.remove(0);
+        }
+        res
+    }
+
+    fn random_text(rng: &mut Rng, max_len: u64) -> String {
+        let len = rng.below(max_len + 1);
+        (0..len)
+            .map(|_| TRANSLATION_TABLE[rng.below(TRANSLATION_TABLE.len() as u64) as usize])
+            .collect()
+    }
+
+    fn random_token(rng: &mut Rng) -> Token {
+        let small = |rng: &mut Rng| (rng.next_u64() >> rng.below(64)) as i64 & i64::MAX;
+        match rng.below(8) {
+            0 => Token::Boolean(rng.chance(1, 2)),
+            1 => Token::Integer(small(rng)),
+            2 => Token::String(random_text(rng, 10)),
+            3 => Token::Unary(UNARY_OPS.chars().nth(rng.below(4) as usize).unwrap()),
+            4 => Token::Binary(BINARY_OPS.chars().nth(rng.below(14) as usize).unwrap()),
+            5 => Token::If,
+            6 => Token::Lambda(small(rng)),
+            _ => Token::Var(small(rng)),
+        }
+    }
+
+    #[test]
+    fn test_known_values() {
+        assert_eq!(encode_int(0), Ok("!".to_string()));
+        assert_eq!(encode_int(1337), Ok("/6".to_string()));
+        assert_eq!(decode_int("/6"), Ok(1337));
+        assert_eq!(encode_str("Hello World!"), Ok("B%,,/}Q/2,$_".to_string()));
+        assert_eq!(decode_str("B%,,/}Q/2,$_"), Ok("Hello World!".to_string()));
+        assert_eq!(encode_bigint(&BigInt::from(0)), Ok("!".to_string()));
+
+        assert_eq!(encode_int(-1), Err(CodecError::Negative("-1".to_string())));
+        assert!(encode_bigint(&BigInt::from(-5)).is_err());
+        assert_eq!(decode_int(""), Err(CodecError::MissingDigits));
+        assert_eq!(decode_int("a b"), Err(CodecError::InvalidDigit(' ')));
+        assert!(matches!(
+            decode_int("~~~~~~~~~~~"),
+            Err(CodecError::Overflow(_))
+        ));
+        assert_eq!(decode_str("ab\n"), Err(CodecError::InvalidStringChar('\n')));
+        assert_eq!(encode_str("tab\t"), Err(CodecError::Unencodable('\t')));
+        assert!(!is_encodable("é"));
+        assert!(is_encodable("hello world\n"));
+
+        assert_eq!(
+            decode_token("U+"),
+            Err(CodecError::UnknownOperator("unary", '+'))
+        );
+        assert_eq!(
+            encode_token(&Token::Binary('?')),
+            Err(CodecError::UnknownOperator("binary", '?'))
+        );
+        assert!(decode_token("").is_err());
+        assert!(decode_token("B..").is_err());
+        assert!(decode_token("F!").is_err());
+    }
+
+    #[test]
+    fn test_int_round_trip() {
+        let mut rng = Rng::new(1);
+        for _ in 0..CASES {
+            let x = (rng.next_u64() >> rng.below(64)) as i64 & i64::MAX;
+            let digits = encode_int(x).unwrap();
+            assert_eq!(decode_int(&digits), Ok(x));
+            assert_eq!(encode_bigint(&BigInt::from(x)), Ok(digits));
+        }
+        assert_eq!(decode_int(&encode_int(i64::MAX).unwrap()), Ok(i64::MAX));
+    }
+
+    #[test]
+    fn test_digits_round_trip() {
+        let mut rng = Rng::new(2);
+        for _ in 0..CASES {
+            let digits = random_digits(&mut rng, 30);
+            let big = decode_bigint(&digits).unwrap();
+            assert_eq!(encode_bigint(&big), Ok(digits.clone()));
+            // The i64 decoder agrees with the bignum one wherever it fits
+            match decode_int(&digits) {
+                Ok(x) => assert_eq!(BigInt::from(x), big),
+                Err(e) => {
+                    assert_eq!(e, CodecError::Overflow(digits.clone()));
+                    assert!(big > BigInt::from(i64::MAX));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_string_round_trip() {
+        let mut rng = Rng::new(3);
+        for _ in 0..CASES {
+            let text = random_text(&mut rng, 20);
+            assert!(is_encodable(&text));
+            let body = encode_str(&text).unwrap();
+            assert_eq!(body.len(), text.len());
+            assert_eq!(decode_str(&body), Ok(text));
+
+            // Every body over `!`..`~` decodes and encodes back to itself
+            let len = rng.below(20);
+            let body: String = (0..len).map(|_| digit_char(rng.below(94) as u8)).collect();
+            assert_eq!(encode_str(&decode_str(&body).unwrap()), Ok(body));
+        }
+    }
+
+    #[test]
+    fn test_encodable_matches_encode() {
+        let mut rng = Rng::new(4);
+        for _ in 0..CASES {
+            let len = rng.below(6);
+            let text: String = (0..len)
+                .map(|_| char::from_u32(rng.below(0x200) as u32).unwrap())
+                .collect();
+            assert_eq!(is_encodable(&text), encode_str(&text).is_ok(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_token_round_trip() {
+        let mut rng = Rng::new(5);
+        for _ in 0..CASES {
+            let token = random_token(&mut rng);
+            let text = encode_token(&token).unwrap();
+            assert!(!text.contains(char::is_whitespace), "{:?}", text);
+            assert_eq!(decode_token(&text), Ok(token));
+        }
+    }
+}
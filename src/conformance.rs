@@ -0,0 +1,138 @@
+use crate::*;
+
+use crate::fuzz::panic_message;
+use std::panic::{self, AssertUnwindSafe};
+
+// Runner for data-driven conformance cases, see tests/conformance.txt for the
+// file format.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Case {
+    pub name: String,
+    pub program: String,
+    pub expected: Expr,
+}
+
+fn parse_value(s: &str) -> Result<Expr, String> {
+    match s {
+        "true" => return Ok(Expr::Boolean(true)),
+        "false" => return Ok(Expr::Boolean(false)),
+        _ => {}
+    }
+    if s.starts_with('"') {
+        return serde_json::from_str::<String>(s)
+            .map(Expr::String)
+            .map_err(|e| format!("bad string value {}: {}", s, e));
+    }
+    s.parse::<i64>()
+        .map(Expr::Integer)
+        .map_err(|_| format!("bad value: '{}'", s))
+}
+
+// `base_dir` is used to resolve `@<path>` programs
+pub fn parse_cases(text: &str, base_dir: &str) -> Result<Vec<Case>, String> {
+    let mut res = Vec::new();
+    let mut name: Option<String> = None;
+    let mut program = Vec::new();
+
+    for (idx, line) in text.lines().enumerate() {
+        let line_no = idx + 1;
+        if let Some(rest) = line.strip_prefix("== ") {
+            if let Some(prev) = name {
+                return Err(format!("line {}: case '{}' has no expect", line_no, prev));
+            }
+            name = Some(rest.trim().to_string());
+            program.clear();
+        } else if let Some(rest) = line.strip_prefix("expect:") {
+            let case_name = name
+                .take()
+                .ok_or(format!("line {}: expect outside of a case", line_no))?;
+            let expected =
+                parse_value(rest.trim()).map_err(|e| format!("line {}: {}", line_no, e))?;
+            let mut text = program.join(" ");
+            if let Some(path) = text.strip_prefix('@') {
+                let full_path = format!("{}/{}", base_dir, path);
+                text =
+                    fs::read_to_string(&full_path).map_err(|e| format!("{}: {}", full_path, e))?;
+            }
+            res.push(Case {
+                name: case_name,
+                program: text.trim().to_string(),
+                expected,
+            });
+        } else if name.is_some() {
+            if !line.trim().is_empty() {
+                program.push(line.trim().to_string());
+            }
+        } else if !line.trim().is_empty() && !line.starts_with('#') {
+            return Err(format!(
+                "line {}: unexpected text outside of a case",
+                line_no
+            ));
+        }
+    }
+    if let Some(prev) = name {
+        return Err(format!("case '{}' has no expect", prev));
+    }
+    Ok(res)
+}
+
+// Returns a description of the failure, if any
+pub fn run_case(case: &Case) -> Result<(), String> {
+    let program = case.program.clone();
+    match panic::catch_unwind(AssertUnwindSafe(|| eval_example(&program))) {
+        Ok(res) if res == case.expected => Ok(()),
+        Ok(res) => Err(format!(
+            "expected {:?}, got {}",
+            case.expected,
+            short_str(&res)
+        )),
+        Err(err) => Err(format!("panicked: {}", panic_message(err))),
+    }
+}
+
+// Runs every case and returns `(name, failure)` for the ones that failed
+pub fn run_cases(cases: &[Case]) -> Vec<(String, String)> {
+    let mut failures = Vec::new();
+    for case in cases {
+        if let Err(e) = run_case(case) {
+            failures.push((case.name.clone(), e));
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cases() {
+        let text = "# comment\n== a\nB+ I#\n  I$\nexpect: 5\n\n== b\nS\nexpect: \"x\\n\"\n";
+        let cases = parse_cases(text, ".").unwrap();
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].program, "B+ I# I$");
+        assert_eq!(cases[0].expected, Expr::Integer(5));
+        assert_eq!(cases[1].expected, Expr::String("x\n".to_string()));
+
+        assert!(parse_cases("== a\nT\n", ".").is_err());
+        assert!(parse_cases("T\nexpect: true\n", ".").is_err());
+        assert!(parse_cases("== a\nT\nexpect: maybe\n", ".").is_err());
+    }
+
+    #[test]
+    fn test_conformance_suite() {
+        let text = fs::read_to_string("tests/conformance.txt").unwrap();
+        let cases = parse_cases(&text, ".").unwrap();
+        let failures = run_cases(&cases);
+        for (name, failure) in failures.iter() {
+            println!("FAILED {}: {}", name, failure);
+        }
+        assert!(
+            failures.is_empty(),
+            "{} of {} cases failed",
+            failures.len(),
+            cases.len()
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use crate::scope::free_vars;
+use crate::*;
+
+use std::collections::HashSet;
+
+// Common-subexpression extraction, the reverse of inlining. A subterm `t`
+// that occurs several times is bound once at the lowest common ancestor `p`
+// of its occurrences:
+//
+//   p  =>  B$ L<n> p' t
+//
+// where `p'` is `p` with every occurrence of `t` replaced by `v<n>`. Terms
+// are compared structurally, as text.
+//
+// This keeps the meaning under call-by-name as long as every occurrence sees
+// the same binders for the free variables of `t`, so no lambda between `p`
+// and an occurrence may bind one of them. `n` is an id used nowhere in the
+// program, so `v<n>` cannot be captured and `t` cannot capture anything.
+//
+// The pass is greedy: it extracts the candidate that promises the largest
+// saving and actually shrinks the program, then looks again.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CseReport {
+    // Size of the serialised program, in bytes
+    pub before: usize,
+    pub after: usize,
+    pub extracted: usize,
+}
+
+impl CseReport {
+    pub fn saved(&self) -> usize {
+        self.before - self.after
+    }
+}
+
+// Occurrences of each subterm, by serialised text
+type Occurrences = HashMap<String, Vec<Vec<usize>>>;
+
+fn head_token(expr: &Expr) -> String {
+    match expr {
+        Expr::Unary(op, _) => token_to_string(&Token::Unary(*op)),
+        Expr::Binary(op, _, _) => token_to_string(&Token::Binary(*op)),
+        Expr::If(_, _, _) => token_to_string(&Token::If),
+        Expr::Lambda(x, _) => token_to_string(&Token::Lambda(*x)),
+        _ => serialize_expr(expr),
+    }
+}
+
+// Returns the serialised `expr`, built from the children's text so every
+// node is serialised once
+fn collect(expr: &Expr, path: &mut Vec<usize>, res: &mut Occurrences) -> String {
+    let mut text = head_token(expr);
+    for (i, c) in children(expr).iter().enumerate() {
+        path.push(i);
+        text.push(' ');
+        text.push_str(&collect(&c.borrow(), path, res));
+        path.pop();
+    }
+    // Replacing a variable by a variable saves nothing
+    if !path.is_empty() && !matches!(expr, Expr::Var(_)) {
+        res.entry(text.clone()).or_default().push(path.clone());
+    }
+    text
+}
+
+fn node_at(root: &ExprPtr, path: &[usize]) -> ExprPtr {
+    path.iter()
+        .fold(root.clone(), |e, i| children(&e.borrow())[*i].clone())
+}
+
+fn common_prefix(paths: &[Vec<usize>]) -> Vec<usize> {
+    let mut res = paths[0].clone();
+    for path in paths[1..].iter() {
+        let n = res
+            .iter()
+            .zip(path.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        res.truncate(n);
+    }
+    res
+}
+
+// Whether a lambda from `lca` down to, but not including, an occurrence binds
+// one of `free`
+fn is_shadowed(root: &ExprPtr, lca: &[usize], paths: &[Vec<usize>], free: &HashSet<i64>) -> bool {
+    paths.iter().any(|path| {
+        (lca.len()..path.len()).any(|depth| {
+            let node = node_at(root, &path[..depth]);
+            let e = node.borrow();
+            matches!(*e, Expr::Lambda(x, _) if free.contains(&x))
+        })
+    })
+}
+
+fn used_ids(expr: &Expr, res: &mut HashSet<i64>) {
+    if let Expr::Var(x) | Expr::Lambda(x, _) = expr {
+        res.insert(*x);
+    }
+    for c in children(expr).iter() {
+        used_ids(&c.borrow(), res);
+    }
+}
+
+fn replace_all(expr_ptr: &ExprPtr, term: &Expr, var: i64) -> ExprPtr {
+    if *expr_ptr.borrow() == *term {
+        return as_ptr(Expr::Var(var));
+    }
+    let e = expr_ptr.borrow().clone();
+    let res = match &e {
+        Expr::Unary(op, a) => Expr::Unary(*op, replace_all(a, term, var)),
+        Expr::Binary(op, a, b) => {
+            Expr::Binary(*op, replace_all(a, term, var), replace_all(b, term, var))
+        }
+        Expr::If(a, b, c) => Expr::If(
+            replace_all(a, term, var),
+            replace_all(b, term, var),
+            replace_all(c, term, var),
+        ),
+        Expr::Lambda(x, a) => Expr::Lambda(*x, replace_all(a, term, var)),
+        _ => return expr_ptr.clone(),
+    };
+    as_ptr(res)
+}
+
+fn replace_at(expr_ptr: &ExprPtr, path: &[usize], new: ExprPtr) -> ExprPtr {
+    let Some((first, rest)) = path.split_first() else {
+        return new;
+    };
+    let e = expr_ptr.borrow().clone();
+    let sub = |a: &ExprPtr, i: usize| {
+        if i == *first {
+            replace_at(a, rest, new.clone())
+        } else {
+            a.clone()
+        }
+    };
+    let res = match &e {
+        Expr::Unary(op, a) => Expr::Unary(*op, sub(a, 0)),
+        Expr::Binary(op, a, b) => Expr::Binary(*op, sub(a, 0), sub(b, 1)),
+        Expr::If(a, b, c) => Expr::If(sub(a, 0), sub(b, 1), sub(c, 2)),
+        Expr::Lambda(x, a) => Expr::Lambda(*x, sub(a, 0)),
+        _ => unreachable!("path goes below a leaf"),
+    };
+    as_ptr(res)
+}
+
+// Extracts one repeated subterm, if any extraction makes `root` smaller
+fn extract_one(root: &ExprPtr) -> Option<ExprPtr> {
+    let mut occurrences = Occurrences::new();
+    collect(&root.borrow(), &mut Vec::new(), &mut occurrences);
+    let mut candidates: Vec<(String, Vec<Vec<usize>>)> = occurrences
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .collect();
+    // Largest estimated saving first, ties by text so the result is stable
+    candidates.sort_by_key(|(text, paths)| {
+        (
+            std::cmp::Reverse((paths.len() - 1) * text.len()),
+            text.clone(),
+        )
+    });
+
+    let mut ids = HashSet::new();
+    used_ids(&root.borrow(), &mut ids);
+    let var = (0..).find(|id| !ids.contains(id)).unwrap();
+    let size = serialize_expr(&root.borrow()).len();
+
+    for (_, paths) in candidates.iter() {
+        let term = node_at(root, &paths[0]);
+        let lca = common_prefix(paths);
+        if is_shadowed(root, &lca, paths, &free_vars(&term.borrow())) {
+            continue;
+        }
+        let scope = replace_all(&node_at(root, &lca), &term.borrow(), var);
+        let binding = Expr::Binary('$', as_ptr(Expr::Lambda(var, scope)), term);
+        let res = replace_at(root, &lca, as_ptr(binding));
+        if serialize_expr(&res.borrow()).len() < size {
+            return Some(res);
+        }
+    }
+    None
+}
+
+pub fn extract_common(expr: &Expr) -> (ExprPtr, CseReport) {
+    let mut res = as_ptr(expr.clone());
+    let mut extracted = 0;
+    while let Some(next) = extract_one(&res) {
+        res = next;
+        extracted += 1;
+    }
+    let report = CseReport {
+        before: serialize_expr(expr).len(),
+        after: serialize_expr(&res.borrow()).len(),
+        extracted,
+    };
+    (res, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extracted(program: &str) -> String {
+        let expr = parse_into_ast(program.to_string());
+        let (res, report) = extract_common(&expr.borrow());
+        let text = serialize_expr(&res.borrow());
+        assert_eq!(report.after, text.len());
+        text
+    }
+
+    #[test]
+    fn test_extracts_repeated_terms() {
+        let program = "B. B. SFFFFFFFFFF SFFFFFFFFFF SFFFFFFFFFF";
+        assert_eq!(extracted(program), "B$ L! B. B. v! v! v! SFFFFFFFFFF");
+        let expr = parse_into_ast(program.to_string());
+        let (_, report) = extract_common(&expr.borrow());
+        assert_eq!(report.extracted, 1);
+        assert_eq!(report.saved(), 9);
+    }
+
+    #[test]
+    fn test_only_when_smaller() {
+        let program = "B+ I\" I\"";
+        assert_eq!(extracted(program), program);
+    }
+
+    #[test]
+    fn test_binds_at_common_ancestor() {
+        // The shared term uses `v!`, so it has to stay inside `L!`
+        assert_eq!(
+            extracted("L! B* B+ B* v! v! I\" B+ B* v! v! I\""),
+            "L! B$ L\" B* v\" v\" B+ B* v! v! I\""
+        );
+    }
+
+    #[test]
+    fn test_respects_scoping() {
+        // `B* v" v"` means different things in the two branches
+        let program = "L! ? v! L\" B+ B* v\" v\" I\" L\" B- B* v\" v\" I\"";
+        assert_eq!(extracted(program), program);
+        // The fresh variable must not collide with a binder in between
+        let s = format!("S{}", "~".repeat(19));
+        assert_eq!(
+            extracted(&format!("L! L\" B. B. {} {} v\"", s, s)),
+            format!("L! L\" B. B$ L# B. v# v# {} v\"", s)
+        );
+    }
+
+    #[test]
+    fn test_preserves_semantics() {
+        set_debug(false);
+        let text = fs::read_to_string("tests/conformance.txt").unwrap();
+        let cases = conformance::parse_cases(&text, ".").unwrap();
+        for case in cases.iter() {
+            let Ok(expr) = parser::parse_checked(&case.program) else {
+                continue;
+            };
+            let (res, report) = extract_common(&expr.borrow());
+            assert!(report.after <= report.before);
+            let case = conformance::Case {
+                program: serialize_expr(&res.borrow()),
+                ..case.clone()
+            };
+            assert_eq!(conformance::run_case(&case), Ok(()), "{}", case.name);
+        }
+    }
+}
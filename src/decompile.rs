@@ -0,0 +1,461 @@
+use crate::absint::{analyze_extracted, Facts, Interval, Value};
+use crate::recursion::{extract_recursion, top_level, Definition};
+use crate::types::{infer_extracted, Type, Typing};
+use crate::*;
+
+use num_bigint::Sign;
+
+// Decompiles a program into Python-style pseudocode:
+//
+//   def f0(x4):
+//       if x4 < 2:
+//           return 1
+//       else:
+//           return f0(x4 - 1) + f0(x4 - 2)
+//
+//   def main():
+//       return f0(40)
+//
+// Recursion through a fixed-point combinator becomes a named function, see
+// `recursion`. Functions that only use their parameters and other top-level
+// functions are written at the top level, the rest where they were defined.
+// In statement position `if` becomes a block and `B$ (L x body) arg` an
+// assignment to `x` followed by `body`; inside expressions they stay
+// `a if c else b` and `(lambda x: body)(arg)`. A lambda whose body needs a
+// nested function is written as a `def` of its own.
+//
+// Functions and assignments are annotated with their types, see `types`,
+// unless the program has a type error. Recursive functions also get a
+// comment with the integer ranges of their parameters and result that
+// `absint` could bound, `# x4 in [0, 40], returns [1, +inf)`.
+//
+// The output is meant to be read, not run: `/` and `%` truncate towards
+// zero, `+` also concatenates strings, and curried calls `f(a)(b)` are
+// written `f(a, b)`.
+
+const TOP: u8 = 0;
+const COND: u8 = 1;
+const OR: u8 = 2;
+const NOT: u8 = 4;
+const CMP: u8 = 5;
+const NEG: u8 = 8;
+const CALL: u8 = 9;
+const ATOM: u8 = 10;
+
+const BINARY_OPS: [(char, &str, u8); 11] = [
+    ('|', "or", OR),
+    ('&', "and", 3),
+    ('=', "==", CMP),
+    ('<', "<", CMP),
+    ('>', ">", CMP),
+    ('+', "+", 6),
+    ('-', "-", 6),
+    ('.', "+", 6),
+    ('*', "*", 7),
+    ('/', "/", 7),
+    ('%', "%", 7),
+];
+
+const INDENT: &str = "    ";
+
+fn binary_op(op: char) -> (&'static str, u8) {
+    let (_, name, level) = BINARY_OPS.iter().find(|(c, _, _)| *c == op).unwrap();
+    (name, *level)
+}
+
+fn var_name(x: i64) -> String {
+    format!("x{}", x)
+}
+
+// `(x1: a, x2: b) -> c`, or just the names when the type is not known
+fn signature(params: &[i64], ty: Option<&Type>) -> String {
+    let split = ty.and_then(|t| t.split(params.len()));
+    let names = params.iter().map(|p| var_name(*p));
+    match split {
+        Some((args, res)) => {
+            let params: Vec<String> = names
+                .zip(args.iter())
+                .map(|(name, t)| format!("{}: {}", name, t))
+                .collect();
+            format!("({}) -> {}", params.join(", "), res)
+        }
+        None => format!("({})", names.collect::<Vec<String>>().join(", ")),
+    }
+}
+
+// `  # x1 in [0, 9], returns [1, +inf)` for the bounded integers, if any
+fn ranges(params: &[i64], facts: Option<&(Vec<Value>, Value)>) -> String {
+    let Some((args, res)) = facts else {
+        return String::new();
+    };
+    let bounded = |v: &Value| v.interval().filter(|i| *i != Interval::TOP);
+    let mut parts: Vec<String> = params
+        .iter()
+        .zip(args.iter())
+        .filter_map(|(p, v)| Some(format!("{} in {}", var_name(*p), bounded(v)?)))
+        .collect();
+    if let Some(i) = bounded(res) {
+        parts.push(format!("returns {}", i));
+    }
+    if parts.is_empty() {
+        return String::new();
+    }
+    format!("  # {}", parts.join(", "))
+}
+
+// Splits `L x1. ... L xn. body` into the parameters and `body`
+fn params(expr_ptr: &ExprPtr) -> (Vec<i64>, ExprPtr) {
+    let mut res = Vec::new();
+    let mut body = expr_ptr.clone();
+    loop {
+        let inner = match &*body.borrow() {
+            Expr::Lambda(x, inner) => {
+                res.push(*x);
+                inner.clone()
+            }
+            _ => break,
+        };
+        body = inner;
+    }
+    (res, body)
+}
+
+struct Decompiler {
+    // Definitions not written yet, by variable
+    pending: HashMap<i64, Definition>,
+    names: HashMap<i64, String>,
+    // Parameters of every function written as a `def`, by variable
+    arity: HashMap<i64, usize>,
+    types: Option<Typing>,
+    facts: Facts,
+    functions: usize,
+    lambdas: usize,
+    lines: Vec<String>,
+}
+
+impl Decompiler {
+    fn line(&mut self, indent: usize, text: String) {
+        self.lines
+            .push(format!("{}{}", INDENT.repeat(indent), text));
+    }
+
+    fn name(&self, x: i64) -> String {
+        self.names.get(&x).cloned().unwrap_or_else(|| var_name(x))
+    }
+
+    // Whether writing `expr` would write a pending definition first
+    fn needs_definition(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Var(x) => self.pending.contains_key(x),
+            _ => children(expr)
+                .iter()
+                .any(|c| self.needs_definition(&c.borrow())),
+        }
+    }
+
+    fn define(&mut self, x: i64, indent: usize) {
+        let Some(def) = self.pending.remove(&x) else {
+            return;
+        };
+        let name = format!("f{}", self.functions);
+        self.functions += 1;
+        self.names.insert(x, name.clone());
+        let ty = self.types.as_ref().and_then(|t| t.definition(x));
+        let signature = signature(&def.params, ty);
+        let ranges = ranges(&def.params, self.facts.definition(x));
+        self.line(indent, format!("def {}{}:{}", name, signature, ranges));
+        self.block(&def.body, indent + 1);
+    }
+
+    fn lambda_type(&self, lambda: &ExprPtr) -> Option<&Type> {
+        self.types.as_ref().and_then(|t| t.lambda(lambda))
+    }
+
+    fn function(&mut self, name: String, lambda: &ExprPtr, indent: usize) {
+        let (params, body) = params(lambda);
+        let signature = signature(&params, self.lambda_type(lambda));
+        self.line(indent, format!("def {}{}:", name, signature));
+        self.block(&body, indent + 1);
+    }
+
+    fn block(&mut self, expr_ptr: &ExprPtr, indent: usize) {
+        let e = expr_ptr.borrow().clone();
+        match &e {
+            Expr::If(cond, then, otherwise) => {
+                let cond = self.expr(cond, TOP, indent);
+                self.line(indent, format!("if {}:", cond));
+                self.block(then, indent + 1);
+                let mut otherwise = otherwise.clone();
+                loop {
+                    let next = otherwise.borrow().clone();
+                    match &next {
+                        // `elif` has no room for the definitions its
+                        // condition might need
+                        Expr::If(cond, then, next) if !self.needs_definition(&cond.borrow()) => {
+                            let cond = self.expr(cond, TOP, indent);
+                            self.line(indent, format!("elif {}:", cond));
+                            self.block(then, indent + 1);
+                            otherwise = next.clone();
+                        }
+                        _ => break,
+                    }
+                }
+                self.line(indent, "else:".to_string());
+                self.block(&otherwise, indent + 1);
+            }
+            Expr::Binary('$', f, arg) if matches!(*f.borrow(), Expr::Lambda(_, _)) => {
+                let (x, body) = match &*f.borrow() {
+                    Expr::Lambda(x, body) => (*x, body.clone()),
+                    _ => unreachable!(),
+                };
+                if matches!(*arg.borrow(), Expr::Lambda(_, _)) {
+                    self.arity.insert(x, params(arg).0.len());
+                    self.function(var_name(x), arg, indent);
+                } else {
+                    let annotation = match self.lambda_type(f).and_then(|t| t.split(1)) {
+                        Some((args, _)) => format!(": {}", args[0]),
+                        None => String::new(),
+                    };
+                    let value = self.expr(arg, TOP, indent);
+                    self.line(indent, format!("{}{} = {}", var_name(x), annotation, value));
+                }
+                self.block(&body, indent);
+            }
+            _ => {
+                let value = self.expr(expr_ptr, TOP, indent);
+                self.line(indent, format!("return {}", value));
+            }
+        }
+    }
+
+    // Definitions the expression needs are written at `indent` first
+    fn expr(&mut self, expr_ptr: &ExprPtr, min_level: u8, indent: usize) -> String {
+        let e = expr_ptr.borrow().clone();
+        let (text, level) = match &e {
+            Expr::Boolean(b) => ((if *b { "True" } else { "False" }).to_string(), ATOM),
+            Expr::Integer(x) => (x.to_string(), if *x < 0 { NEG } else { ATOM }),
+            Expr::BigInteger(x) => (
+                x.to_string(),
+                if x.sign() == Sign::Minus { NEG } else { ATOM },
+            ),
+            Expr::String(s) => (serde_json::to_string(s).unwrap(), ATOM),
+            Expr::Var(x) => {
+                self.define(*x, indent);
+                (self.name(*x), ATOM)
+            }
+            Expr::Lambda(x, body) => {
+                if self.needs_definition(&body.borrow()) {
+                    let name = format!("lambda_{}", self.lambdas);
+                    self.lambdas += 1;
+                    self.function(name.clone(), expr_ptr, indent);
+                    (name, ATOM)
+                } else {
+                    let body = self.expr(body, TOP, indent);
+                    (format!("lambda {}: {}", var_name(*x), body), TOP)
+                }
+            }
+            Expr::If(cond, then, otherwise) => {
+                let then = self.expr(then, OR, indent);
+                let cond = self.expr(cond, OR, indent);
+                let otherwise = self.expr(otherwise, COND, indent);
+                (format!("{} if {} else {}", then, cond, otherwise), COND)
+            }
+            Expr::Unary('-', a) => (format!("-{}", self.expr(a, NEG, indent)), NEG),
+            Expr::Unary('!', a) => (format!("not {}", self.expr(a, NOT, indent)), NOT),
+            Expr::Unary(op, a) => {
+                let name = if *op == '#' {
+                    "str_to_int"
+                } else {
+                    "int_to_str"
+                };
+                (format!("{}({})", name, self.expr(a, TOP, indent)), CALL)
+            }
+            Expr::Binary('$', _, _) => {
+                let mut args = Vec::new();
+                let mut head = expr_ptr.clone();
+                loop {
+                    let f = match &*head.borrow() {
+                        Expr::Binary('$', f, arg) => {
+                            args.push(arg.clone());
+                            f.clone()
+                        }
+                        _ => break,
+                    };
+                    head = f;
+                }
+                // A `def` takes its parameters in one call, Python lambdas
+                // and unknown functions one argument at a time
+                let arity = match &*head.borrow() {
+                    Expr::Var(x) => self.arity.get(x).copied().unwrap_or(0),
+                    Expr::Lambda(_, body) if self.needs_definition(&body.borrow()) => {
+                        params(&head).0.len()
+                    }
+                    _ => 0,
+                };
+                let head = self.expr(&head, CALL, indent);
+                let args: Vec<String> = args
+                    .iter()
+                    .rev()
+                    .map(|arg| self.expr(arg, TOP, indent))
+                    .collect();
+                let (together, rest) = args.split_at(arity.clamp(1, args.len()));
+                let mut text = format!("{}({})", head, together.join(", "));
+                for arg in rest {
+                    text += &format!("({})", arg);
+                }
+                (text, CALL)
+            }
+            Expr::Binary(op @ ('T' | 'D'), n, s) => {
+                let s = self.expr(s, CALL, indent);
+                let n = self.expr(n, TOP, indent);
+                let text = if *op == 'T' {
+                    format!("{}[:{}]", s, n)
+                } else {
+                    format!("{}[{}:]", s, n)
+                };
+                (text, CALL)
+            }
+            Expr::Binary(op, a, b) => {
+                let (name, level) = binary_op(*op);
+                // Python comparisons chain, so neither side may be one
+                let left_level = if level == CMP { level + 1 } else { level };
+                let a = self.expr(a, left_level, indent);
+                let b = self.expr(b, level + 1, indent);
+                (format!("{} {} {}", a, name, b), level)
+            }
+        };
+        if level < min_level {
+            format!("({})", text)
+        } else {
+            text
+        }
+    }
+}
+
+pub fn decompile(expr: &Expr) -> String {
+    let extracted = extract_recursion(expr);
+    let typing = infer_extracted(&extracted);
+    let main_type = typing.main.clone();
+    let types = Some(typing).filter(|t| t.errors.is_empty());
+    let facts = analyze_extracted(&extracted);
+    let top = top_level(&extracted.definitions);
+    let order: Vec<i64> = extracted.definitions.iter().map(|d| d.name).collect();
+    let arity = extracted
+        .definitions
+        .iter()
+        .map(|d| (d.name, d.params.len()))
+        .collect();
+    let mut decompiler = Decompiler {
+        pending: extracted
+            .definitions
+            .into_iter()
+            .map(|d| (d.name, d))
+            .collect(),
+        names: HashMap::new(),
+        arity,
+        types,
+        facts,
+        functions: 0,
+        lambdas: 0,
+        lines: Vec::new(),
+    };
+    for x in order.iter().filter(|x| top.contains(x)) {
+        decompiler.define(*x, 0);
+        decompiler.lines.push(String::new());
+    }
+    let signature = signature(&[], decompiler.types.as_ref().map(|_| &main_type));
+    decompiler.line(0, format!("def main{}:", signature));
+    decompiler.block(&extracted.main, 1);
+    decompiler.lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decompiled(program: &str) -> String {
+        decompile(&parse_into_ast(program.to_string()).borrow())
+    }
+
+    #[test]
+    fn test_expressions() {
+        assert_eq!(
+            decompiled("B* B+ I\" U- I# B- I$ B- I% I&"),
+            "def main() -> int:\n    return (1 + -2) * (3 - (4 - 5))\n"
+        );
+        assert_eq!(
+            decompiled("B| U! B= I\" I# B& T B< B. S4 S5 SB"),
+            "def main():\n    return not 1 == 2 or True and \"t\" + \"u\" < \"H\"\n"
+        );
+        assert_eq!(
+            decompiled("BT I# BD I\" U$ U# S4"),
+            "def main() -> str:\n    return int_to_str(str_to_int(\"t\"))[1:][:2]\n"
+        );
+        // Ill-typed, `2` is not a condition, so not annotated
+        assert_eq!(
+            decompiled("B$ B$ L# L$ ? v# v$ I\" I# I$"),
+            "def main():\n    return (lambda x2: lambda x3: x3 if x2 else 1)(2)(3)\n"
+        );
+    }
+
+    #[test]
+    fn test_statements() {
+        let text = decompiled("B$ L# ? B= v# I! I\" ? B< v# I! I# I$ B+ I\" I\"");
+        let expected = [
+            "def main() -> int:",
+            "    x2: int = 1 + 1",
+            "    if x2 == 0:",
+            "        return 1",
+            "    elif x2 < 0:",
+            "        return 2",
+            "    else:",
+            "        return 3",
+            "",
+        ];
+        assert_eq!(text, expected.join("\n"));
+    }
+
+    #[test]
+    fn test_problem_4() {
+        let text = fs::read_to_string("problems/4.txt").unwrap();
+        let expected = [
+            "def f0(x4: int) -> int:  # x4 in [0, 40], returns [1, +inf)",
+            "    if x4 < 2:",
+            "        return 1",
+            "    else:",
+            "        return f0(x4 - 1) + f0(x4 - 2)",
+            "",
+            "def main() -> int:",
+            "    return f0(40)",
+            "",
+        ];
+        assert_eq!(decompiled(text.trim()), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_nested_functions() {
+        // The helper uses `v&`, so it is defined inside `x6`
+        let text = fs::read_to_string("problems/5.txt").unwrap();
+        let text = decompiled(text.trim());
+        assert!(
+            text.contains("\n    def x6(x5: int) -> bool:\n        def f1(x4: int) -> bool:"),
+            "{}",
+            text
+        );
+        assert!(text.starts_with("def f0(x4: int) -> bool:\n"), "{}", text);
+
+        let text = fs::read_to_string("problems/13.txt").unwrap();
+        let text = decompiled(text.trim());
+        assert!(text.contains("return 0\n    else:\n        return 1 + f0(x4[1:])"));
+
+        // Python lambdas are curried, only `def`s take several arguments
+        let text = fs::read_to_string("problems/12.txt").unwrap();
+        let text = decompiled(text.trim());
+        assert!(
+            text.contains("x2: x1 if x1 < x2 else x2)(x4)(1 + (f1(2, x4) if"),
+            "{}",
+            text
+        );
+        assert!(text.contains("return f1(x6 + 1, "), "{}", text);
+    }
+}
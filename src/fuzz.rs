@@ -0,0 +1,419 @@
+use crate::*;
+
+use std::panic::{self, AssertUnwindSafe};
+
+// Small splitmix64 generator, so runs are reproducible from a single seed
+// without pulling in an extra crate.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform value in 0..n, n must be positive
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    pub fn chance(&mut self, num: u64, den: u64) -> bool {
+        self.below(den) < num
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Type {
+    Int,
+    Bool,
+    Str,
+}
+
+const TYPES: [Type; 3] = [Type::Int, Type::Bool, Type::Str];
+
+#[derive(Clone, Debug)]
+pub struct GenConfig {
+    // Upper bound on the number of AST nodes in a generated expression
+    pub max_size: usize,
+    // Integer literals are drawn from 0..=max_int
+    pub max_int: i64,
+    // Variables are drawn from 1..=max_var, a small pool makes shadowing common
+    pub max_var: i64,
+    pub max_str_len: usize,
+    // Allow `/`, `%`, `T` and `D`, which are undefined for some arguments
+    pub partial_ops: bool,
+}
+
+impl Default for GenConfig {
+    fn default() -> Self {
+        GenConfig {
+            max_size: 40,
+            max_int: 200,
+            max_var: 4,
+            max_str_len: 4,
+            partial_ops: false,
+        }
+    }
+}
+
+pub struct Generator {
+    rng: Rng,
+    config: GenConfig,
+    // Variables bound by enclosing lambdas, innermost last
+    scope: Vec<(i64, Type)>,
+}
+
+impl Generator {
+    pub fn new(seed: u64, config: GenConfig) -> Generator {
+        Generator {
+            rng: Rng::new(seed),
+            config,
+            scope: Vec::new(),
+        }
+    }
+
+    pub fn random_type(&mut self) -> Type {
+        TYPES[self.rng.below(TYPES.len() as u64) as usize]
+    }
+
+    // Closed expression of the given type with at most `max_size` nodes
+    pub fn expr(&mut self, ty: Type) -> Expr {
+        self.scope.clear();
+        let budget = self.config.max_size;
+        self.gen(ty, budget)
+    }
+
+    fn gen(&mut self, ty: Type, budget: usize) -> Expr {
+        if budget < 3 || self.rng.chance(1, 5) {
+            return self.leaf(ty);
+        }
+        // Every compound form below spends one node on itself
+        let rest = budget - 1;
+        let choice = self.rng.below(6);
+        if choice == 0 && budget >= 4 {
+            return self.gen_if(ty, rest);
+        }
+        if choice == 1 && budget >= 4 {
+            return self.gen_let(ty, rest);
+        }
+        match ty {
+            Type::Int => self.gen_int(rest),
+            Type::Bool => self.gen_bool(rest),
+            Type::Str => self.gen_str(rest),
+        }
+    }
+
+    fn gen_int(&mut self, rest: usize) -> Expr {
+        let mut ops = vec!['+', '-', '*'];
+        if self.config.partial_ops {
+            ops.push('/');
+            ops.push('%');
+        }
+        match self.rng.below(4) {
+            0 => Expr::Unary('-', self.child(Type::Int, rest)),
+            1 => Expr::Unary('#', self.child(Type::Str, rest)),
+            _ => {
+                let op = ops[self.rng.below(ops.len() as u64) as usize];
+                self.binary(op, Type::Int, Type::Int, rest)
+            }
+        }
+    }
+
+    fn gen_bool(&mut self, rest: usize) -> Expr {
+        match self.rng.below(5) {
+            0 => Expr::Unary('!', self.child(Type::Bool, rest)),
+            1 => {
+                let op = if self.rng.chance(1, 2) { '|' } else { '&' };
+                self.binary(op, Type::Bool, Type::Bool, rest)
+            }
+            2 => {
+                let ty = self.random_type();
+                self.binary('=', ty, ty, rest)
+            }
+            _ => {
+                let op = if self.rng.chance(1, 2) { '<' } else { '>' };
+                self.binary(op, Type::Int, Type::Int, rest)
+            }
+        }
+    }
+
+    fn gen_str(&mut self, rest: usize) -> Expr {
+        let choice = self.rng.below(if self.config.partial_ops { 4 } else { 2 });
+        match choice {
+            0 => Expr::Unary('$', self.child(Type::Int, rest)),
+            1 => self.binary('.', Type::Str, Type::Str, rest),
+            2 => self.binary('T', Type::Int, Type::Str, rest),
+            _ => self.binary('D', Type::Int, Type::Str, rest),
+        }
+    }
+
+    fn gen_if(&mut self, ty: Type, rest: usize) -> Expr {
+        let (a, b, c) = self.split3(rest);
+        Expr::If(
+            self.child(Type::Bool, a),
+            self.child(ty, b),
+            self.child(ty, c),
+        )
+    }
+
+    // `B$ L<x> body arg`, binding x to a value of a random type
+    fn gen_let(&mut self, ty: Type, rest: usize) -> Expr {
+        // One node goes to the lambda itself
+        let (body_budget, arg_budget) = self.split2(rest - 1);
+        let x = self.rng.below(self.config.max_var as u64) as i64 + 1;
+        let arg_ty = self.random_type();
+        let arg = self.child(arg_ty, arg_budget);
+        self.scope.push((x, arg_ty));
+        let body = self.child(ty, body_budget);
+        self.scope.pop();
+        Expr::Binary('$', as_ptr(Expr::Lambda(x, body)), arg)
+    }
+
+    fn binary(&mut self, op: char, ty_a: Type, ty_b: Type, rest: usize) -> Expr {
+        let (a, b) = self.split2(rest);
+        Expr::Binary(op, self.child(ty_a, a), self.child(ty_b, b))
+    }
+
+    fn child(&mut self, ty: Type, budget: usize) -> ExprPtr {
+        as_ptr(self.gen(ty, budget))
+    }
+
+    fn split2(&mut self, budget: usize) -> (usize, usize) {
+        let a = 1 + self.rng.below((budget - 1) as u64) as usize;
+        (a, budget - a)
+    }
+
+    fn split3(&mut self, budget: usize) -> (usize, usize, usize) {
+        let (a, rest) = self.split2(budget - 1);
+        let (b, c) = self.split2(rest + 1);
+        (a, b, c)
+    }
+
+    fn leaf(&mut self, ty: Type) -> Expr {
+        let visible = self.visible_vars(ty);
+        if !visible.is_empty() && self.rng.chance(1, 2) {
+            let x = visible[self.rng.below(visible.len() as u64) as usize];
+            return Expr::Var(x);
+        }
+        match ty {
+            Type::Int => Expr::Integer(self.rng.below(self.config.max_int as u64 + 1) as i64),
+            Type::Bool => Expr::Boolean(self.rng.chance(1, 2)),
+            Type::Str => {
+                let len = self.rng.below(self.config.max_str_len as u64 + 1);
+                let s = (0..len)
+                    .map(|_| {
+                        TRANSLATION_TABLE[self.rng.below(TRANSLATION_TABLE.len() as u64) as usize]
+                    })
+                    .collect();
+                Expr::String(s)
+            }
+        }
+    }
+
+    // Variables of type `ty` that are not shadowed by an inner binding
+    fn visible_vars(&self, ty: Type) -> Vec<i64> {
+        let mut seen = Vec::new();
+        let mut res = Vec::new();
+        for (x, x_ty) in self.scope.iter().rev() {
+            if seen.contains(x) {
+                continue;
+            }
+            seen.push(*x);
+            if *x_ty == ty {
+                res.push(*x);
+            }
+        }
+        res
+    }
+}
+
+pub fn expr_size(expr: &Expr) -> usize {
+    match expr {
+        Expr::Unary(_, a) | Expr::Lambda(_, a) => 1 + expr_size(&a.borrow()),
+        Expr::Binary(_, a, b) => 1 + expr_size(&a.borrow()) + expr_size(&b.borrow()),
+        Expr::If(a, b, c) => {
+            1 + expr_size(&a.borrow()) + expr_size(&b.borrow()) + expr_size(&c.borrow())
+        }
+        _ => 1,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    // Parsing the token text gave back a different expression
+    RoundTrip(Expr),
+    ParsePanic(String),
+    EvalPanic(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct FuzzFailure {
+    // Seed that reproduces the case with `Generator::new`
+    pub seed: u64,
+    pub program: String,
+    pub kind: FailureKind,
+}
+
+#[derive(Debug, Default)]
+pub struct FuzzReport {
+    pub cases: usize,
+    pub failures: Vec<FuzzFailure>,
+}
+
+pub(crate) fn panic_message(err: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(s) = err.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = err.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
+
+// Checks a single generated program, returns None if it passed
+pub fn check_case(seed: u64, config: &GenConfig) -> Option<FuzzFailure> {
+    let mut gen = Generator::new(seed, config.clone());
+    let ty = gen.random_type();
+    let expr = gen.expr(ty);
+    let program = serialize_expr(&expr);
+
+    let failure = |kind| {
+        Some(FuzzFailure {
+            seed,
+            program: program.clone(),
+            kind,
+        })
+    };
+
+    let parsed = match panic::catch_unwind(|| parse_into_ast(program.clone())) {
+        Ok(parsed) => parsed.borrow().clone(),
+        Err(err) => return failure(FailureKind::ParsePanic(panic_message(err))),
+    };
+    if parsed != expr {
+        return failure(FailureKind::RoundTrip(parsed));
+    }
+
+    let evaled = panic::catch_unwind(AssertUnwindSafe(|| eval_expr(as_ptr(expr.clone()))));
+    if let Err(err) = evaled {
+        return failure(FailureKind::EvalPanic(panic_message(err)));
+    }
+    None
+}
+
+// Runs `cases` generated programs, case i uses seed `seed + i`. Tracing is
+// off meanwhile, it would bury the report.
+pub fn fuzz(seed: u64, cases: usize, config: &GenConfig) -> FuzzReport {
+    let was_debug = debug_enabled();
+    set_debug(false);
+    let mut report = FuzzReport::default();
+    for i in 0..cases {
+        report.cases += 1;
+        if let Some(failure) = check_case(seed.wrapping_add(i as u64), config) {
+            report.failures.push(failure);
+        }
+    }
+    set_debug(was_debug);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scope::free_vars;
+
+    // i64 arithmetic panics on overflow in a debug build
+    const OVERFLOWS: [&str; 4] = [
+        "attempt to add with overflow",
+        "attempt to subtract with overflow",
+        "attempt to multiply with overflow",
+        "attempt to negate with overflow",
+    ];
+
+    // `partial_ops` on arguments they are undefined for: a zero divisor,
+    // `i64::MIN / -1`, and `T` or `D` past either end of the string
+    const UNDEFINED: [&str; 6] = [
+        "attempt to divide by zero",
+        "attempt to calculate the remainder with a divisor of zero",
+        "attempt to divide with overflow",
+        "attempt to calculate the remainder with overflow",
+        "range start index",
+        "range end index",
+    ];
+
+    fn expected(failure: &FuzzFailure, classes: &[&str]) -> bool {
+        match &failure.kind {
+            FailureKind::EvalPanic(msg) => classes.iter().any(|c| msg.starts_with(c)),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_generator_is_seeded() {
+        let config = GenConfig::default();
+        for seed in 0..20 {
+            let a = Generator::new(seed, config.clone()).expr(Type::Int);
+            let b = Generator::new(seed, config.clone()).expr(Type::Int);
+            assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn test_generator_closed_and_bounded() {
+        let config = GenConfig::default();
+        for seed in 0..500 {
+            let mut gen = Generator::new(seed, config.clone());
+            let ty = gen.random_type();
+            let expr = gen.expr(ty);
+            assert!(expr_size(&expr) <= config.max_size, "seed = {}", seed);
+            assert!(free_vars(&expr).is_empty(), "seed = {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_fuzz_round_trip() {
+        let config = GenConfig {
+            partial_ops: true,
+            ..GenConfig::default()
+        };
+        let report = fuzz(0, 300, &config);
+        let classes: Vec<&str> = OVERFLOWS.iter().chain(UNDEFINED.iter()).copied().collect();
+        for failure in report.failures.iter() {
+            assert!(expected(failure, &classes), "{:?}", failure);
+        }
+    }
+
+    #[test]
+    fn test_fuzz_eval_no_panics() {
+        // Large products can still overflow i64, anything else is an
+        // evaluator bug
+        let report = fuzz(1000, 300, &GenConfig::default());
+        for failure in report.failures.iter() {
+            assert!(expected(failure, &OVERFLOWS), "{:?}", failure);
+        }
+    }
+
+    #[test]
+    fn test_tracing_per_thread() {
+        set_debug(true);
+        let other = std::thread::spawn(|| {
+            set_debug(false);
+            fuzz(0, 5, &GenConfig::default());
+            debug_enabled()
+        });
+        assert!(!other.join().unwrap());
+        assert!(debug_enabled());
+        fuzz(0, 5, &GenConfig::default());
+        assert!(debug_enabled());
+        set_debug(false);
+    }
+}
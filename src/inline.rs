@@ -0,0 +1,235 @@
+use crate::partial::{count_uses, substitute};
+use crate::scope::free_vars;
+use crate::*;
+
+use std::collections::HashSet;
+
+// Inlining and dead-binding elimination. Programs only bind names through
+// `B$ (L x body) arg`, so both passes work on such applications.
+//
+// Dead-binding elimination replaces the application with `body` when `x` is
+// not used in it. Evaluation is call-by-name, so `arg` would never have been
+// evaluated and dropping it cannot change the result.
+//
+// Inlining substitutes `arg` for `x` in `body` when that does not make the
+// serialised program longer than `Options::max_growth` allows. There is no
+// renaming: a binder in `body` that would capture a free variable of `arg`
+// blocks the substitution. Inlining can create new applications of lambdas,
+// so passes repeat until nothing changes.
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    // Bytes a single inlining may add. With 0 it must not make the program
+    // longer, inlinings that keep the size can repeat up to `max_passes`.
+    pub max_growth: usize,
+    pub max_passes: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            max_growth: 0,
+            max_passes: 100,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InlineReport {
+    // Size of the serialised program, in bytes
+    pub before: usize,
+    pub after: usize,
+    pub inlined: usize,
+    // Applications dropped because their variable was unused
+    pub dropped: usize,
+}
+
+// Whether substituting for `x` in `expr` would put a value with free
+// variables `free` under a binder of one of them
+fn captures(expr: &Expr, x: i64, free: &HashSet<i64>) -> bool {
+    match expr {
+        Expr::Lambda(y, _) if *y == x => false,
+        Expr::Lambda(y, body) => {
+            let body = body.borrow();
+            (free.contains(y) && count_uses(&body, x) > 0) || captures(&body, x, free)
+        }
+        _ => children(expr)
+            .iter()
+            .any(|c| captures(&c.borrow(), x, free)),
+    }
+}
+
+struct Pass<'a> {
+    options: &'a Options,
+    inline: bool,
+    inlined: usize,
+    dropped: usize,
+}
+
+impl Pass<'_> {
+    fn reduce(&mut self, x: i64, body: &ExprPtr, arg: &ExprPtr) -> Option<ExprPtr> {
+        let uses = count_uses(&body.borrow(), x);
+        if uses == 0 {
+            self.dropped += 1;
+            return Some(body.clone());
+        }
+        if !self.inline || captures(&body.borrow(), x, &free_vars(&arg.borrow())) {
+            return None;
+        }
+        let lambda = as_ptr(Expr::Lambda(x, body.clone()));
+        let before = serialize_expr(&Expr::Binary('$', lambda, arg.clone())).len();
+        let res = substitute(body, x, arg);
+        let after = serialize_expr(&res.borrow()).len();
+        if after <= before + self.options.max_growth {
+            self.inlined += 1;
+            return Some(res);
+        }
+        None
+    }
+}
+
+impl Fold for Pass<'_> {
+    fn fold_binary(&mut self, op: char, a: &ExprPtr, b: &ExprPtr) -> Expr {
+        let a = self.fold_expr(a);
+        let b = self.fold_expr(b);
+        if op == '$' {
+            if let Expr::Lambda(x, body) = &*a.borrow() {
+                if let Some(res) = self.reduce(*x, body, &b) {
+                    return res.borrow().clone();
+                }
+            }
+        }
+        Expr::Binary(op, a, b)
+    }
+}
+
+fn run(expr: &Expr, options: &Options, inline: bool) -> (ExprPtr, InlineReport) {
+    let mut pass = Pass {
+        options,
+        inline,
+        inlined: 0,
+        dropped: 0,
+    };
+    let mut res = as_ptr(expr.clone());
+    for _ in 0..options.max_passes {
+        let changes = pass.inlined + pass.dropped;
+        res = pass.fold_expr(&res);
+        if pass.inlined + pass.dropped == changes {
+            break;
+        }
+    }
+    let report = InlineReport {
+        before: serialize_expr(expr).len(),
+        after: serialize_expr(&res.borrow()).len(),
+        inlined: pass.inlined,
+        dropped: pass.dropped,
+    };
+    (res, report)
+}
+
+// Inlines applications where that pays off, dropping dead ones on the way
+pub fn inline(expr: &Expr, options: &Options) -> (ExprPtr, InlineReport) {
+    run(expr, options, true)
+}
+
+// Only drops applications whose variable is unused
+pub fn eliminate_dead(expr: &Expr) -> (ExprPtr, InlineReport) {
+    run(expr, &Options::default(), false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inlined(program: &str, max_growth: usize) -> String {
+        let expr = parse_into_ast(program.to_string());
+        let options = Options {
+            max_growth,
+            ..Options::default()
+        };
+        let (res, report) = inline(&expr.borrow(), &options);
+        let text = serialize_expr(&res.borrow());
+        assert_eq!(report.after, text.len());
+        text
+    }
+
+    #[test]
+    fn test_eliminate_dead() {
+        let expr = parse_into_ast("L! B$ L\" B+ v! I\" B$ L# v# I$".to_string());
+        let (res, report) = eliminate_dead(&expr.borrow());
+        assert_eq!(serialize_expr(&res.borrow()), "L! B+ v! I\"");
+        assert_eq!(report.dropped, 1);
+        // Used once, only `inline` removes it
+        let expr = parse_into_ast("B$ L# v# I$".to_string());
+        let (res, report) = eliminate_dead(&expr.borrow());
+        assert_eq!(serialize_expr(&res.borrow()), "B$ L# v# I$");
+        assert_eq!(
+            report,
+            InlineReport {
+                before: 11,
+                after: 11,
+                inlined: 0,
+                dropped: 0
+            }
+        );
+        // Shadowed, so unused
+        let expr = parse_into_ast("B$ L! L! v! I\"".to_string());
+        let (res, _) = eliminate_dead(&expr.borrow());
+        assert_eq!(serialize_expr(&res.borrow()), "L! v!");
+    }
+
+    #[test]
+    fn test_cost_model() {
+        // Single use
+        assert_eq!(
+            inlined("L! B$ L\" B+ v\" I\" B* v! v!", 0),
+            "L! B+ B* v! v! I\""
+        );
+        // A short argument used twice
+        assert_eq!(inlined("L! B$ L\" B+ v\" v\" v!", 0), "L! B+ v! v!");
+        // A long argument used twice grows the program ...
+        let program = "L! B$ L\" B+ v\" v\" B* v! B* v! v!";
+        assert_eq!(inlined(program, 0), program);
+        // ... unless growth is allowed, by 3 bytes at least
+        let grown = "L! B+ B* v! B* v! v! B* v! B* v! v!";
+        assert_eq!(inlined(program, 10), grown);
+        assert_eq!(inlined(program, 3), grown);
+        assert_eq!(inlined(program, 2), program);
+        // Keeping the size is no growth
+        let program = "B$ L\" B. v\" v\" Sabcdefghij";
+        assert_eq!(inlined(program, 0), "B. Sabcdefghij Sabcdefghij");
+    }
+
+    #[test]
+    fn test_no_capture() {
+        // Inlining `v!` under `L!` would capture it
+        let program = "L! B$ L\" L! B+ v\" v! v!";
+        assert_eq!(inlined(program, 0), program);
+        assert_eq!(inlined("L! B$ L\" L# B+ v\" v# v!", 0), "L! L# B+ v! v#");
+    }
+
+    #[test]
+    fn test_repeats_passes() {
+        // Inlining the function creates a new application to inline
+        assert_eq!(inlined("B$ L! B$ v! I# L\" B+ v\" I\"", 0), "B+ I# I\"");
+    }
+
+    #[test]
+    fn test_preserves_semantics() {
+        set_debug(false);
+        let text = fs::read_to_string("tests/conformance.txt").unwrap();
+        let cases = conformance::parse_cases(&text, ".").unwrap();
+        for case in cases.iter() {
+            let Ok(expr) = parser::parse_checked(&case.program) else {
+                continue;
+            };
+            let (res, report) = inline(&expr.borrow(), &Options::default());
+            assert!(report.after <= report.before);
+            let case = conformance::Case {
+                program: serialize_expr(&res.borrow()),
+                ..case.clone()
+            };
+            assert_eq!(conformance::run_case(&case), Ok(()), "{}", case.name);
+        }
+    }
+}
@@ -0,0 +1,458 @@
+use crate::bitblast::bitblast;
+use crate::recursion::extract_recursion;
+use crate::sat::{solve, Solution};
+use crate::symbolic::{evaluate, execute, Path, Sort, Term, TermPtr};
+use crate::*;
+
+use std::ops::RangeInclusive;
+
+// Inputs for validators: given a program `L x. body` and a target value,
+// finds an integer `x` in a search space with `program(x) == target`. Three
+// strategies are tried in order:
+//
+// - enumeration: evaluates the program on every input, when there are few
+//   enough of them
+// - SAT: the constraint of every path that returns the target, see
+//   `symbolic`, goes through `bitblast` into `sat`
+// - constraints: solves equalities that are linear in the input and
+//   enumerates the range comparisons leave, on each path's constraint
+//
+// Whatever a strategy finds is checked by evaluating the original program,
+// so a mismatch between `symbolic` and `eval` makes that strategy fail
+// instead of giving a wrong input. Any input is found, not the smallest.
+//
+// Problems 7 and 8 search for the input a large condition holds for,
+// `loop_condition` turns their loop into a validator. The other search
+// loops call recursive functions `symbolic` does not follow, and a sudoku
+// grid does not fit in an integer.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    Enumeration,
+    Sat,
+    Constraints,
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Strategy::Enumeration => write!(f, "enumeration"),
+            Strategy::Sat => write!(f, "SAT"),
+            Strategy::Constraints => write!(f, "constraints"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Found {
+    pub input: i64,
+    pub strategy: Strategy,
+}
+
+// Why a strategy found nothing
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attempt {
+    pub strategy: Strategy,
+    pub reason: String,
+}
+
+impl fmt::Display for Attempt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.strategy, self.reason)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    // Largest search space, or range left by the constraints, to enumerate
+    pub max_enumeration: u64,
+    // Per evaluation of the program
+    pub eval_steps: usize,
+    pub symbolic: symbolic::Options,
+    pub bitblast: bitblast::Options,
+    pub max_conflicts: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            max_enumeration: 1000,
+            eval_steps: 100_000,
+            symbolic: symbolic::Options::default(),
+            bitblast: bitblast::Options::default(),
+            max_conflicts: 100_000,
+        }
+    }
+}
+
+// Whether `program(x)` evaluates to `target`
+fn check(program: &Expr, target: &Expr, x: i64, options: &Options) -> bool {
+    let call = Expr::Binary('$', as_ptr(program.clone()), as_ptr(Expr::Integer(x)));
+    try_eval(as_ptr(call), options.eval_steps).as_ref() == Some(target)
+}
+
+// Saturates, every i64 is one more than a u64 holds
+fn size(range: &RangeInclusive<i64>) -> u64 {
+    if range.is_empty() {
+        return 0;
+    }
+    let n = *range.end() as i128 - *range.start() as i128 + 1;
+    u64::try_from(n).unwrap_or(u64::MAX)
+}
+
+fn enumerate(
+    program: &Expr,
+    target: &Expr,
+    search_space: &RangeInclusive<i64>,
+    options: &Options,
+) -> Result<i64, String> {
+    if size(search_space) > options.max_enumeration {
+        return Err(format!("more than {} inputs", options.max_enumeration));
+    }
+    search_space
+        .clone()
+        .find(|x| check(program, target, *x, options))
+        .ok_or_else(|| "no input works".to_string())
+}
+
+// Constraints of the paths that can return `target`
+fn constraints(paths: &[Path], target: &Expr) -> Vec<TermPtr> {
+    paths
+        .iter()
+        .filter_map(|path| path.constraint(target))
+        .collect()
+}
+
+fn within(term: TermPtr, search_space: &RangeInclusive<i64>) -> TermPtr {
+    let input = Rc::new(Term::Input);
+    let bound = |op: char, x: i64| {
+        let cmp = Rc::new(Term::Binary(op, input.clone(), Rc::new(Term::Integer(x))));
+        Rc::new(Term::Unary('!', cmp))
+    };
+    let lower = bound('<', *search_space.start());
+    let upper = bound('>', *search_space.end());
+    symbolic::conjunction(&[term, lower, upper])
+}
+
+fn sat(
+    paths: &[Path],
+    target: &Expr,
+    search_space: &RangeInclusive<i64>,
+    options: &Options,
+) -> Result<i64, String> {
+    let any = constraints(paths, target)
+        .into_iter()
+        .reduce(|a, b| Rc::new(Term::Binary('|', a, b)))
+        .ok_or_else(|| "no path returns the target".to_string())?;
+    let cnf = bitblast(&within(any, search_space), &options.bitblast).map_err(|e| e.to_string())?;
+    match solve(&cnf.clauses, cnf.variables, options.max_conflicts) {
+        Solution::Sat(model) => Ok(cnf.input_value(&model)),
+        Solution::Unsat => Err(format!("no input fits in {} bits", options.bitblast.width)),
+        Solution::Unknown => Err(format!(
+            "undecided after {} conflicts",
+            options.max_conflicts
+        )),
+    }
+}
+
+// `(a, b)` with `term = a * x + b`, if it is linear
+fn linear(term: &Term) -> Option<(i64, i64)> {
+    match term {
+        Term::Input => Some((1, 0)),
+        Term::Integer(c) => Some((0, *c)),
+        Term::Unary('-', t) => {
+            let (a, b) = linear(t)?;
+            Some((a.checked_neg()?, b.checked_neg()?))
+        }
+        Term::Binary(op @ ('+' | '-'), s, t) => {
+            let (a, b) = linear(s)?;
+            let (c, d) = linear(t)?;
+            if *op == '+' {
+                Some((a.checked_add(c)?, b.checked_add(d)?))
+            } else {
+                Some((a.checked_sub(c)?, b.checked_sub(d)?))
+            }
+        }
+        Term::Binary('*', s, t) => match (linear(s)?, linear(t)?) {
+            ((0, k), (a, b)) | ((a, b), (0, k)) => Some((a.checked_mul(k)?, b.checked_mul(k)?)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The conjuncts of `term`
+fn conjuncts(term: &TermPtr, res: &mut Vec<TermPtr>) {
+    match &**term {
+        Term::Binary('&', a, b) => {
+            conjuncts(a, res);
+            conjuncts(b, res);
+        }
+        _ => res.push(term.clone()),
+    }
+}
+
+// `term` as an atom and whether it is negated, through `!` and the
+// `= true` of a boolean goal
+fn literal(term: &TermPtr) -> (bool, TermPtr) {
+    match &**term {
+        Term::Unary('!', t) => {
+            let (negated, atom) = literal(t);
+            (!negated, atom)
+        }
+        Term::Binary('=', t, b) if t.sort() == Sort::Bool => match **b {
+            Term::Boolean(b) => {
+                let (negated, atom) = literal(t);
+                (negated == b, atom)
+            }
+            _ => (false, term.clone()),
+        },
+        _ => (false, term.clone()),
+    }
+}
+
+// Inputs that solve an equality, and the range left by comparisons
+fn narrow(term: &TermPtr, range: &mut RangeInclusive<i64>, candidates: &mut Vec<i64>) {
+    let (negated, atom) = literal(term);
+    let Term::Binary(op, s, t) = &*atom else {
+        return;
+    };
+    let (Some((a, b)), Some((c, d))) = (linear(s), linear(t)) else {
+        return;
+    };
+    // `k * x op m`
+    let (Some(k), Some(m)) = (a.checked_sub(c), d.checked_sub(b)) else {
+        return;
+    };
+    if k == 0 {
+        return;
+    }
+    match (op, negated) {
+        // No i64 solves it when the division overflows
+        ('=', false) if m.checked_rem(k) == Some(0) => candidates.extend(m.checked_div(k)),
+        ('<' | '>', _) => {
+            // Divide by a positive `k`, the range is left alone if negating
+            // overflows
+            let (k, m, op) = if k < 0 {
+                let (Some(k), Some(m)) = (k.checked_neg(), m.checked_neg()) else {
+                    return;
+                };
+                (k, m, if *op == '<' { '>' } else { '<' })
+            } else {
+                (k, m, *op)
+            };
+            let (q, exact) = (m.div_euclid(k), m.rem_euclid(k) == 0);
+            let (lo, hi) = (*range.start(), *range.end());
+            // x < m / k, x >= m / k, x > m / k, x <= m / k. A bound past
+            // i64 leaves nothing.
+            let narrowed = match (op, negated) {
+                ('<', false) => q.checked_sub(i64::from(exact)).map(|b| lo..=hi.min(b)),
+                ('<', true) => q.checked_add(i64::from(!exact)).map(|b| lo.max(b)..=hi),
+                ('>', false) => q.checked_add(1).map(|b| lo.max(b)..=hi),
+                _ => Some(lo..=hi.min(q)),
+            };
+            *range = narrowed.unwrap_or(RangeInclusive::new(1, 0));
+        }
+        _ => {}
+    }
+}
+
+fn solve_constraints(
+    paths: &[Path],
+    target: &Expr,
+    search_space: &RangeInclusive<i64>,
+    options: &Options,
+) -> Result<Vec<i64>, String> {
+    let terms = constraints(paths, target);
+    if terms.is_empty() {
+        return Err("no path returns the target".to_string());
+    }
+    let mut res = Vec::new();
+    for term in terms.iter() {
+        let mut parts = Vec::new();
+        conjuncts(term, &mut parts);
+        let mut range = search_space.clone();
+        let mut candidates = Vec::new();
+        for part in parts.iter() {
+            narrow(part, &mut range, &mut candidates);
+        }
+        if size(&range) <= options.max_enumeration {
+            candidates.extend(range.clone());
+        }
+        let holds = |x: &i64| range.contains(x) && evaluate(term, *x) == Some(Expr::Boolean(true));
+        res.extend(candidates.into_iter().filter(holds));
+    }
+    if res.is_empty() {
+        return Err("no equality or small range solves a path".to_string());
+    }
+    Ok(res)
+}
+
+// `find_input` with custom budgets
+pub fn find_input_with(
+    program: &Expr,
+    target: &Expr,
+    search_space: &RangeInclusive<i64>,
+    options: &Options,
+) -> Result<Found, Vec<Attempt>> {
+    let mut attempts = Vec::new();
+    let mut fail = |strategy: Strategy, reason: String| {
+        attempts.push(Attempt { strategy, reason });
+    };
+    let found = |input: i64, strategy: Strategy| Ok(Found { input, strategy });
+    match enumerate(program, target, search_space, options) {
+        Ok(x) => return found(x, Strategy::Enumeration),
+        Err(reason) => fail(Strategy::Enumeration, reason),
+    }
+    let paths = match execute(program, 0, &options.symbolic) {
+        Ok(paths) => paths,
+        Err(e) => {
+            fail(Strategy::Sat, e.to_string());
+            fail(Strategy::Constraints, e.to_string());
+            return Err(attempts);
+        }
+    };
+    let unverified = |x: i64| format!("{} does not evaluate to the target", x);
+    match sat(&paths, target, search_space, options) {
+        Ok(x) if check(program, target, x, options) => return found(x, Strategy::Sat),
+        Ok(x) => fail(Strategy::Sat, unverified(x)),
+        Err(reason) => fail(Strategy::Sat, reason),
+    }
+    match solve_constraints(&paths, target, search_space, options) {
+        Ok(xs) => match xs.iter().find(|x| check(program, target, **x, options)) {
+            Some(x) => return found(*x, Strategy::Constraints),
+            None => fail(Strategy::Constraints, unverified(xs[0])),
+        },
+        Err(reason) => fail(Strategy::Constraints, reason),
+    }
+    Err(attempts)
+}
+
+// An input in `search_space` that `program` maps to `target`, with the
+// strategy that found it, or why each strategy failed
+pub fn find_input(
+    program: &Expr,
+    target: &Expr,
+    search_space: &RangeInclusive<i64>,
+) -> Result<Found, Vec<Attempt>> {
+    find_input_with(program, target, search_space, &Options::default())
+}
+
+// `L x. cond` for the first recursive definition of `program`, when it is
+// a search loop `f x = ... if cond then ... else ...` with the definitions
+// before the `if` kept
+pub fn loop_condition(program: &Expr) -> Option<Expr> {
+    let extracted = extract_recursion(program);
+    let def = extracted.definitions.first()?;
+    let [x] = def.params[..] else {
+        return None;
+    };
+    let mut lets = Vec::new();
+    let mut body = def.body.clone();
+    let cond = loop {
+        let next = match &*body.borrow() {
+            Expr::Binary('$', f, arg) => match &*f.borrow() {
+                Expr::Lambda(y, inner) => {
+                    lets.push((*y, arg.clone()));
+                    inner.clone()
+                }
+                _ => return None,
+            },
+            Expr::If(cond, _, _) => break cond.clone(),
+            _ => return None,
+        };
+        body = next;
+    };
+    let body = lets.into_iter().rev().fold(cond, |body, (y, arg)| {
+        as_ptr(Expr::Binary('$', as_ptr(Expr::Lambda(y, body)), arg))
+    });
+    Some(Expr::Lambda(x, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn parse(s: &str) -> Expr {
+        parse_into_ast(s.to_string()).borrow().clone()
+    }
+
+    #[test]
+    fn test_enumeration() {
+        // x * x = 49
+        let program = parse(&format!("L# B= B* v# v# I{}", int_to_base94_string(49)));
+        let found = find_input(&program, &Expr::Boolean(true), &(-10..=10)).unwrap();
+        assert_eq!(found.input, -7);
+        assert_eq!(found.strategy, Strategy::Enumeration);
+    }
+
+    #[test]
+    fn test_search_loops() {
+        for problem in [7, 8] {
+            let text = fs::read_to_string(format!("problems/{}.txt", problem)).unwrap();
+            let validator = loop_condition(&parse(text.trim())).unwrap();
+            let target = Expr::Boolean(true);
+            let found = find_input(&validator, &target, &(1..=i64::MAX)).unwrap();
+            assert_eq!(found.strategy, Strategy::Sat, "problem {}", problem);
+            assert!(check(&validator, &target, found.input, &Options::default()));
+        }
+        assert_eq!(loop_condition(&parse("L# B+ v# I\"")), None);
+    }
+
+    #[test]
+    fn test_constraints() {
+        // 3 * x + 5 = 3000000005 does not fit in 8 bits
+        let program = parse(&format!(
+            "L# B= B+ B* I$ v# I& I{}",
+            int_to_base94_string(3_000_000_005)
+        ));
+        let target = Expr::Boolean(true);
+        let options = Options {
+            bitblast: bitblast::Options { width: 8 },
+            ..Options::default()
+        };
+        let found = find_input_with(&program, &target, &(0..=i64::MAX), &options).unwrap();
+        assert_eq!(found.input, 1_000_000_000);
+        assert_eq!(found.strategy, Strategy::Constraints);
+        // ... and 3 * x + 5 < 50 leaves a range to enumerate
+        let program = parse(&format!(
+            "L# B< B+ B* I$ v# I& I{}",
+            int_to_base94_string(50)
+        ));
+        let found = find_input_with(&program, &target, &(10..=i64::MAX), &options).unwrap();
+        assert_eq!(found.input, 10);
+        assert_eq!(found.strategy, Strategy::Constraints);
+    }
+
+    #[test]
+    fn test_overflow() {
+        assert_eq!(size(&(i64::MIN..=i64::MAX)), u64::MAX);
+        let target = Expr::Boolean(true);
+        // Too many inputs to enumerate, however many there are
+        let found = find_input(&parse("L# B= v# I$"), &target, &(i64::MIN..=i64::MAX)).unwrap();
+        assert_eq!(found.input, 3);
+        // x > i64::MAX and x < i64::MIN hold for no input
+        for program in ["L# B> v# I{}", "L# B< v# B- U- I{} I\""] {
+            let program = program.replace("{}", &int_to_base94_string(i64::MAX));
+            let search_space = i64::MIN..=i64::MAX;
+            let attempts = find_input(&parse(&program), &target, &search_space).unwrap_err();
+            assert_eq!(attempts.len(), 3, "{}", program);
+        }
+    }
+
+    #[test]
+    fn test_failure() {
+        let program = parse("L# B< v# v#");
+        let attempts = find_input(&program, &Expr::Boolean(true), &(0..=1 << 20)).unwrap_err();
+        let strategies: Vec<Strategy> = attempts.iter().map(|a| a.strategy).collect();
+        assert_eq!(
+            strategies,
+            vec![Strategy::Enumeration, Strategy::Sat, Strategy::Constraints]
+        );
+        assert_eq!(
+            attempts[0].to_string(),
+            "enumeration: more than 1000 inputs"
+        );
+    }
+}
@@ -0,0 +1,366 @@
+use crate::*;
+
+use num_bigint::BigInt;
+
+use serde::de::{self, Deserializer};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+// JSON form of an AST. Subtrees can be shared between several parents (the
+// evaluator substitutes the same `ExprPtr` in many places), so instead of a
+// nested tree the expression is stored as a table of nodes that refer to
+// their children by index. `B+ v# I"` becomes
+//
+//   {
+//     "version": 1,
+//     "root": 2,
+//     "nodes": [
+//       {"kind": "var", "var": 2},
+//       {"kind": "integer", "value": 1},
+//       {"kind": "binary", "op": "+", "lhs": 0, "rhs": 1}
+//     ]
+//   }
+//
+// Children always come before their parents. A shared subtree is stored
+// once and referenced by every parent, and reading the document back shares
+// it the same way. Node kinds and their fields:
+//
+//   boolean {value}          integer {value}        string {value}
+//   biginteger {value}
+//   unary {op, arg}          binary {op, lhs, rhs}  if {cond, then, else}
+//   lambda {var, body}       var {var}
+//
+// `op` is the one-character operator as in the ICFP text, strings are
+// decoded. Tokens serialise as `{"kind": "...", "value": ...}` with the same
+// kind names, see `Token`.
+
+pub const VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Node {
+    Boolean {
+        value: bool,
+    },
+    Integer {
+        value: i64,
+    },
+    // Beyond i64, the value is a decimal string
+    #[serde(rename = "biginteger")]
+    BigInteger {
+        #[serde(with = "crate::decimal")]
+        value: BigInt,
+    },
+    String {
+        value: String,
+    },
+    Unary {
+        op: char,
+        arg: usize,
+    },
+    Binary {
+        op: char,
+        lhs: usize,
+        rhs: usize,
+    },
+    If {
+        cond: usize,
+        then: usize,
+        #[serde(rename = "else")]
+        otherwise: usize,
+    },
+    Lambda {
+        var: i64,
+        body: usize,
+    },
+    Var {
+        var: i64,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Document {
+    pub version: u32,
+    pub root: usize,
+    pub nodes: Vec<Node>,
+}
+
+// `ids` holds the node index of every child, in order
+fn to_node(expr: &Expr, ids: &[usize]) -> Node {
+    match expr {
+        Expr::Boolean(value) => Node::Boolean { value: *value },
+        Expr::Integer(value) => Node::Integer { value: *value },
+        Expr::BigInteger(value) => Node::BigInteger {
+            value: value.clone(),
+        },
+        Expr::String(value) => Node::String {
+            value: value.clone(),
+        },
+        Expr::Unary(op, _) => Node::Unary {
+            op: *op,
+            arg: ids[0],
+        },
+        Expr::Binary(op, _, _) => Node::Binary {
+            op: *op,
+            lhs: ids[0],
+            rhs: ids[1],
+        },
+        Expr::If(_, _, _) => Node::If {
+            cond: ids[0],
+            then: ids[1],
+            otherwise: ids[2],
+        },
+        Expr::Lambda(var, _) => Node::Lambda {
+            var: *var,
+            body: ids[0],
+        },
+        Expr::Var(var) => Node::Var { var: *var },
+    }
+}
+
+// Walks the children of `expr` iteratively, deep programs would overflow the
+// call stack otherwise. Returns the node index of every child.
+fn add_children(
+    expr: &Expr,
+    nodes: &mut Vec<Node>,
+    seen: &mut HashMap<*const RefCell<Expr>, usize>,
+) -> Vec<usize> {
+    // (node, whether its children are done)
+    let mut stack: Vec<(ExprPtr, bool)> = children(expr)
+        .into_iter()
+        .rev()
+        .map(|c| (c, false))
+        .collect();
+    while let Some((ptr, expanded)) = stack.pop() {
+        let key = Rc::as_ptr(&ptr);
+        if seen.contains_key(&key) {
+            continue;
+        }
+        let e = ptr.borrow();
+        let kids = children(&e);
+        if expanded {
+            let ids: Vec<usize> = kids.iter().map(|c| seen[&Rc::as_ptr(c)]).collect();
+            seen.insert(key, nodes.len());
+            nodes.push(to_node(&e, &ids));
+        } else {
+            drop(e);
+            stack.push((ptr.clone(), true));
+            for c in kids.into_iter().rev() {
+                stack.push((c, false));
+            }
+        }
+    }
+    children(expr)
+        .iter()
+        .map(|c| seen[&Rc::as_ptr(c)])
+        .collect()
+}
+
+pub fn to_document(expr: &Expr) -> Document {
+    let mut nodes = Vec::new();
+    let mut seen = HashMap::new();
+    let ids = add_children(expr, &mut nodes, &mut seen);
+    nodes.push(to_node(expr, &ids));
+    Document {
+        version: VERSION,
+        root: nodes.len() - 1,
+        nodes,
+    }
+}
+
+fn operator(idx: usize, op: char, kind: &str, valid: &str) -> Result<char, String> {
+    if valid.contains(op) {
+        Ok(op)
+    } else {
+        Err(format!(
+            "node {} has unknown {} operator {:?}",
+            idx, kind, op
+        ))
+    }
+}
+
+// Variable ids are base-94 numbers in the ICFP text, so never negative
+fn variable(idx: usize, var: i64) -> Result<i64, String> {
+    if var >= 0 {
+        Ok(var)
+    } else {
+        Err(format!("node {} has negative variable {}", idx, var))
+    }
+}
+
+pub fn from_document(doc: &Document) -> Result<ExprPtr, String> {
+    if doc.version != VERSION {
+        return Err(format!("unsupported version {}", doc.version));
+    }
+    let mut built: Vec<ExprPtr> = Vec::with_capacity(doc.nodes.len());
+    for (idx, node) in doc.nodes.iter().enumerate() {
+        // Only earlier nodes can be referenced, which also rules out cycles
+        let child = |id: usize| {
+            built.get(id).cloned().ok_or(format!(
+                "node {} refers to node {}, not defined before it",
+                idx, id
+            ))
+        };
+        let expr = match node {
+            Node::Boolean { value } => Expr::Boolean(*value),
+            Node::Integer { value } => Expr::Integer(*value),
+            Node::BigInteger { value } => big_integer(value.clone()),
+            Node::String { value } if !codec::is_encodable(value) => {
+                return Err(format!("node {} has a string that cannot be encoded", idx));
+            }
+            Node::String { value } => Expr::String(value.clone()),
+            Node::Unary { op, arg } => {
+                Expr::Unary(operator(idx, *op, "unary", codec::UNARY_OPS)?, child(*arg)?)
+            }
+            Node::Binary { op, lhs, rhs } => Expr::Binary(
+                operator(idx, *op, "binary", codec::BINARY_OPS)?,
+                child(*lhs)?,
+                child(*rhs)?,
+            ),
+            Node::If {
+                cond,
+                then,
+                otherwise,
+            } => Expr::If(child(*cond)?, child(*then)?, child(*otherwise)?),
+            Node::Lambda { var, body } => Expr::Lambda(variable(idx, *var)?, child(*body)?),
+            Node::Var { var } => Expr::Var(variable(idx, *var)?),
+        };
+        built.push(as_ptr(expr));
+    }
+    built
+        .get(doc.root)
+        .cloned()
+        .ok_or(format!("root {} is not a node", doc.root))
+}
+
+impl Serialize for Expr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        to_document(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Expr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Expr, D::Error> {
+        let doc = Document::deserialize(deserializer)?;
+        let root = from_document(&doc).map_err(de::Error::custom)?;
+        let res = root.borrow().clone();
+        Ok(res)
+    }
+}
+
+pub fn to_json(expr: &Expr) -> String {
+    serde_json::to_string_pretty(expr).unwrap()
+}
+
+pub fn from_json(text: &str) -> Result<ExprPtr, String> {
+    let doc: Document = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    from_document(&doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_shape() {
+        let expr = parse_into_ast("B+ v# I\"".to_string());
+        let value: serde_json::Value = serde_json::from_str(&to_json(&expr.borrow())).unwrap();
+        let expected = serde_json::json!({
+            "version": 1,
+            "root": 2,
+            "nodes": [
+                {"kind": "var", "var": 2},
+                {"kind": "integer", "value": 1},
+                {"kind": "binary", "op": "+", "lhs": 0, "rhs": 1},
+            ]
+        });
+        assert_eq!(value, expected);
+
+        let token = serde_json::to_value(Token::Binary('$')).unwrap();
+        assert_eq!(token, serde_json::json!({"kind": "binary", "value": "$"}));
+        let token = serde_json::to_value(Token::If).unwrap();
+        assert_eq!(token, serde_json::json!({"kind": "if"}));
+        let back: Token = serde_json::from_value(token).unwrap();
+        assert_eq!(back, Token::If);
+
+        let big: BigInt = "12345678901234567890".parse().unwrap();
+        let value = serde_json::json!({"kind": "biginteger", "value": "12345678901234567890"});
+        let node = serde_json::to_value(Node::BigInteger { value: big.clone() }).unwrap();
+        assert_eq!(node, value);
+        let token = serde_json::to_value(Token::BigInteger(big)).unwrap();
+        assert_eq!(token, value);
+    }
+
+    #[test]
+    fn test_shared_subtree() {
+        let shared = as_ptr(Expr::Unary('-', as_ptr(Expr::Integer(3))));
+        let expr = Expr::If(
+            as_ptr(Expr::Boolean(true)),
+            shared.clone(),
+            as_ptr(Expr::Binary('*', shared.clone(), shared)),
+        );
+        let doc = to_document(&expr);
+        // integer, unary, boolean, binary and the root; the unary node once
+        assert_eq!(doc.nodes.len(), 5);
+
+        let back = from_json(&to_json(&expr)).unwrap();
+        assert_eq!(*back.borrow(), expr);
+        let Expr::If(_, then, otherwise) = &*back.borrow() else {
+            panic!("expected if");
+        };
+        let Expr::Binary(_, lhs, rhs) = &*otherwise.borrow() else {
+            panic!("expected binary");
+        };
+        assert!(Rc::ptr_eq(then, lhs) && Rc::ptr_eq(lhs, rhs));
+    }
+
+    #[test]
+    fn test_round_trip_programs() {
+        // Problem 10 has literals beyond i64
+        for path in ["language_test.txt", "problems/10.txt"] {
+            let text = fs::read_to_string(path).unwrap();
+            let expr = parse_into_ast(text.trim().to_string());
+            let back = from_json(&to_json(&expr.borrow())).unwrap();
+            assert_eq!(serialize_expr(&back.borrow()), text.trim(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_bad_documents() {
+        let forward = r#"{"version": 1, "root": 1, "nodes": [
+            {"kind": "unary", "op": "-", "arg": 1},
+            {"kind": "integer", "value": 1}]}"#;
+        assert!(from_json(forward)
+            .unwrap_err()
+            .contains("not defined before"));
+        let root = r#"{"version": 1, "root": 5, "nodes": [{"kind": "var", "var": 1}]}"#;
+        assert!(from_json(root).is_err());
+        let version = r#"{"version": 7, "root": 0, "nodes": [{"kind": "var", "var": 1}]}"#;
+        assert!(from_json(version).is_err());
+        assert!(from_json(r#"{"version": 1, "root": 0, "nodes": [{"kind": "nope"}]}"#).is_err());
+        let unary = r#"{"version": 1, "root": 1, "nodes": [
+            {"kind": "integer", "value": 1},
+            {"kind": "unary", "op": "+", "arg": 0}]}"#;
+        assert!(from_json(unary)
+            .unwrap_err()
+            .contains("unknown unary operator '+'"));
+        let binary = r#"{"version": 1, "root": 1, "nodes": [
+            {"kind": "integer", "value": 1},
+            {"kind": "binary", "op": "!", "lhs": 0, "rhs": 0}]}"#;
+        assert!(from_json(binary)
+            .unwrap_err()
+            .contains("unknown binary operator '!'"));
+        let string = r#"{"version": 1, "root": 0, "nodes": [{"kind": "string", "value": "é"}]}"#;
+        assert!(from_json(string)
+            .unwrap_err()
+            .contains("node 0 has a string that cannot be encoded"));
+        let lambda = r#"{"version": 1, "root": 1, "nodes": [
+            {"kind": "integer", "value": 1},
+            {"kind": "lambda", "var": -1, "body": 0}]}"#;
+        assert!(from_json(lambda)
+            .unwrap_err()
+            .contains("node 1 has negative variable -1"));
+        let var = r#"{"version": 1, "root": 0, "nodes": [{"kind": "var", "var": -2}]}"#;
+        assert!(from_json(var).is_err());
+    }
+}
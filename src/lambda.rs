@@ -0,0 +1,510 @@
+use crate::parser::{error_at, error_at_end, ParseError, Span};
+use crate::*;
+
+use num_bigint::{BigInt, Sign};
+
+// Lambda notation for design notes and tests:
+//
+//   λx1. λx2. if x2 = 0 then 1 else 1 + x1 (x2 - 1)
+//
+// Variables are `x<N>`, `\` can be typed instead of `λ`. From loosest to
+// tightest binding:
+//
+//   λx. body, if c then a else b    extend as far right as possible
+//   ||                              left-assoc
+//   &&                              left-assoc
+//   =  <  >                         non-associative
+//   +  -  ++                        left-assoc, `++` is string concatenation
+//   *  /  %                         left-assoc
+//   -x  !x                          prefix
+//   f x, take n s, drop n s,        application, left-assoc
+//   str_to_int s, int_to_str n
+//   x1, 42, -42, true, "json string", ( ... )
+//
+// A `-` directly followed by a digit is a negative literal unless it comes
+// right after an operand, so `x1 -1` is a subtraction and `f (-1)` passes a
+// negative number. The printer only adds parentheses where the parser needs
+// them.
+
+const TOP: u8 = 0;
+const PREFIX: u8 = 6;
+const APP: u8 = 7;
+const ATOM: u8 = 8;
+
+const BINARY_OPS: [(&str, char, u8); 11] = [
+    ("||", '|', 1),
+    ("&&", '&', 2),
+    ("=", '=', 3),
+    ("<", '<', 3),
+    (">", '>', 3),
+    ("+", '+', 4),
+    ("-", '-', 4),
+    ("++", '.', 4),
+    ("*", '*', 5),
+    ("/", '/', 5),
+    ("%", '%', 5),
+];
+
+// Comparisons do not chain
+const NON_ASSOC: u8 = 3;
+
+// Builtins are written like functions: name, operator and operand count
+const BUILTINS: [(&str, char, usize); 4] = [
+    ("take", 'T', 2),
+    ("drop", 'D', 2),
+    ("str_to_int", '#', 1),
+    ("int_to_str", '$', 1),
+];
+
+fn binary_op(op: char) -> (&'static str, u8) {
+    let (name, _, level) = BINARY_OPS.iter().find(|(_, c, _)| *c == op).unwrap();
+    (name, *level)
+}
+
+fn builtin_name(op: char) -> &'static str {
+    BUILTINS.iter().find(|(_, c, _)| *c == op).unwrap().0
+}
+
+fn level(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Lambda(_, _) | Expr::If(_, _, _) => TOP,
+        Expr::Binary('$' | 'T' | 'D', _, _) => APP,
+        Expr::Binary(op, _, _) => binary_op(*op).1,
+        Expr::Unary('#' | '$', _) => APP,
+        Expr::Unary(_, _) => PREFIX,
+        Expr::Integer(x) if *x < 0 => PREFIX,
+        Expr::BigInteger(x) if x.sign() == Sign::Minus => PREFIX,
+        _ => ATOM,
+    }
+}
+
+fn write(expr: &Expr, min_level: u8, res: &mut String) {
+    let paren = level(expr) < min_level;
+    if paren {
+        res.push('(');
+    }
+    match expr {
+        Expr::Boolean(b) => res.push_str(&b.to_string()),
+        Expr::Integer(x) => res.push_str(&x.to_string()),
+        Expr::BigInteger(x) => res.push_str(&x.to_string()),
+        Expr::String(s) => res.push_str(&serde_json::to_string(s).unwrap()),
+        Expr::Var(x) => res.push_str(&format!("x{}", x)),
+        Expr::Lambda(x, body) => {
+            res.push_str(&format!("λx{}. ", x));
+            write(&body.borrow(), TOP, res);
+        }
+        Expr::If(cond, then, otherwise) => {
+            res.push_str("if ");
+            write(&cond.borrow(), TOP, res);
+            res.push_str(" then ");
+            write(&then.borrow(), TOP, res);
+            res.push_str(" else ");
+            write(&otherwise.borrow(), TOP, res);
+        }
+        Expr::Unary(op @ ('-' | '!'), a) => {
+            res.push(*op);
+            let a = a.borrow();
+            // `-5` would read back as a negative literal
+            let min_level = if matches!(*a, Expr::Integer(_)) {
+                ATOM + 1
+            } else {
+                PREFIX
+            };
+            write(&a, min_level, res);
+        }
+        Expr::Unary(op, a) => {
+            res.push_str(builtin_name(*op));
+            res.push(' ');
+            write(&a.borrow(), ATOM, res);
+        }
+        Expr::Binary('$', f, x) => {
+            write(&f.borrow(), APP, res);
+            res.push(' ');
+            write(&x.borrow(), ATOM, res);
+        }
+        Expr::Binary(op @ ('T' | 'D'), a, b) => {
+            res.push_str(builtin_name(*op));
+            res.push(' ');
+            write(&a.borrow(), ATOM, res);
+            res.push(' ');
+            write(&b.borrow(), ATOM, res);
+        }
+        Expr::Binary(op, a, b) => {
+            let (name, level) = binary_op(*op);
+            let left_level = if level == NON_ASSOC { level + 1 } else { level };
+            write(&a.borrow(), left_level, res);
+            res.push_str(&format!(" {} ", name));
+            write(&b.borrow(), level + 1, res);
+        }
+    }
+    if paren {
+        res.push(')');
+    }
+}
+
+pub fn to_lambda(expr: &Expr) -> String {
+    let mut res = String::new();
+    write(expr, TOP, &mut res);
+    res
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Lexeme {
+    Lambda,
+    Dot,
+    Open,
+    Close,
+    Op(&'static str),
+    Var(i64),
+    Int(i64),
+    BigInt(BigInt),
+    Str(String),
+    Word(String),
+}
+
+impl Lexeme {
+    fn ends_operand(&self) -> bool {
+        match self {
+            Lexeme::Close | Lexeme::Var(_) | Lexeme::Int(_) | Lexeme::BigInt(_) => true,
+            Lexeme::Str(_) => true,
+            Lexeme::Word(w) => w == "true" || w == "false",
+            _ => false,
+        }
+    }
+}
+
+const OPERATORS: [&str; 12] = [
+    "||", "&&", "++", "=", "<", ">", "+", "-", "*", "/", "%", "!",
+];
+
+fn lex(source: &str) -> Result<Vec<(Lexeme, Span)>, ParseError> {
+    let mut res: Vec<(Lexeme, Span)> = Vec::new();
+    let mut pos = 0;
+    while let Some(c) = source[pos..].chars().next() {
+        let rest = &source[pos..];
+        let start = pos;
+        let span = |len: usize, index: usize| Span {
+            offset: start,
+            len,
+            index,
+        };
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+            continue;
+        }
+        let after_operand = res.last().is_some_and(|(l, _)| l.ends_operand());
+        let negative =
+            c == '-' && !after_operand && rest[1..].starts_with(|c: char| c.is_ascii_digit());
+        let word_len = |skip: usize| {
+            skip + rest[skip..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len() - skip)
+        };
+
+        let (lexeme, len) = if c == 'λ' || c == '\\' {
+            (Lexeme::Lambda, c.len_utf8())
+        } else if c == '.' {
+            (Lexeme::Dot, 1)
+        } else if c == '(' {
+            (Lexeme::Open, 1)
+        } else if c == ')' {
+            (Lexeme::Close, 1)
+        } else if c.is_ascii_digit() || negative {
+            let len = word_len(if negative { 1 } else { 0 });
+            let text = &rest[..len];
+            let lexeme = match (text.parse::<i64>(), text.parse::<BigInt>()) {
+                (Ok(x), _) => Lexeme::Int(x),
+                (_, Ok(x)) => Lexeme::BigInt(x),
+                _ => {
+                    let message = format!("bad number '{}'", text);
+                    return Err(error_at(source, span(len, res.len()), message));
+                }
+            };
+            (lexeme, len)
+        } else if c == '"' {
+            let mut len = 1;
+            let bytes = rest.as_bytes();
+            while len < bytes.len() && bytes[len] != b'"' {
+                len += if bytes[len] == b'\\' { 2 } else { 1 };
+            }
+            if len >= bytes.len() {
+                let s = span(rest.len(), res.len());
+                return Err(error_at(source, s, "unterminated string".to_string()));
+            }
+            len += 1;
+            let value = serde_json::from_str::<String>(&rest[..len]).map_err(|e| {
+                let s = span(len, res.len());
+                error_at(source, s, format!("bad string literal: {}", e))
+            })?;
+            (Lexeme::Str(value), len)
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = word_len(0);
+            let word = &rest[..len];
+            let var = word
+                .strip_prefix('x')
+                .filter(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+            match var {
+                Some(n) => {
+                    let value = n.parse::<i64>().map_err(|_| {
+                        let s = span(len, res.len());
+                        error_at(source, s, format!("bad variable '{}'", word))
+                    })?;
+                    (Lexeme::Var(value), len)
+                }
+                None => (Lexeme::Word(word.to_string()), len),
+            }
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            (Lexeme::Op(op), op.len())
+        } else {
+            let s = span(c.len_utf8(), res.len());
+            return Err(error_at(source, s, format!("unexpected character {:?}", c)));
+        };
+        res.push((lexeme, span(len, res.len())));
+        pos += len;
+    }
+    Ok(res)
+}
+
+struct Reader<'a> {
+    source: &'a str,
+    lexemes: Vec<(Lexeme, Span)>,
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.pos).map(|(l, _)| l)
+    }
+
+    fn next(&mut self, what: &str) -> Result<(Lexeme, Span), ParseError> {
+        let res =
+            self.lexemes.get(self.pos).cloned().ok_or_else(|| {
+                error_at_end(self.source, format!("expected {}, input ended", what))
+            })?;
+        self.pos += 1;
+        Ok(res)
+    }
+
+    fn error(&self, span: Span, message: String) -> ParseError {
+        error_at(self.source, span, message)
+    }
+
+    fn expect(&mut self, expected: Lexeme, what: &str) -> Result<(), ParseError> {
+        let (lexeme, span) = self.next(what)?;
+        if lexeme != expected {
+            return Err(self.error(span, format!("expected {}", what)));
+        }
+        Ok(())
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), ParseError> {
+        self.expect(Lexeme::Word(word.to_string()), &format!("'{}'", word))
+    }
+
+    fn expr(&mut self) -> Result<ExprPtr, ParseError> {
+        let res = match self.peek() {
+            Some(Lexeme::Lambda) => {
+                self.pos += 1;
+                let (lexeme, span) = self.next("a variable")?;
+                let Lexeme::Var(x) = lexeme else {
+                    return Err(self.error(span, "expected a variable".to_string()));
+                };
+                self.expect(Lexeme::Dot, "'.'")?;
+                Expr::Lambda(x, self.expr()?)
+            }
+            Some(Lexeme::Word(w)) if w == "if" => {
+                self.pos += 1;
+                let cond = self.expr()?;
+                self.expect_word("then")?;
+                let then = self.expr()?;
+                self.expect_word("else")?;
+                Expr::If(cond, then, self.expr()?)
+            }
+            _ => return self.binary(1),
+        };
+        Ok(as_ptr(res))
+    }
+
+    fn binary(&mut self, level: u8) -> Result<ExprPtr, ParseError> {
+        if level == PREFIX {
+            return self.prefix();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Some(Lexeme::Op(name)) => BINARY_OPS
+                    .iter()
+                    .find(|(n, _, l)| n == name && *l == level)
+                    .map(|(_, op, _)| *op),
+                _ => None,
+            };
+            let Some(op) = op else {
+                return Ok(lhs);
+            };
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = as_ptr(Expr::Binary(op, lhs, rhs));
+            if level == NON_ASSOC {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn prefix(&mut self) -> Result<ExprPtr, ParseError> {
+        match self.peek() {
+            Some(Lexeme::Op(op @ ("-" | "!"))) => {
+                let op = op.chars().next().unwrap();
+                self.pos += 1;
+                Ok(as_ptr(Expr::Unary(op, self.prefix()?)))
+            }
+            _ => self.application(),
+        }
+    }
+
+    fn starts_atom(&self) -> bool {
+        match self.peek() {
+            Some(Lexeme::Var(_) | Lexeme::Int(_) | Lexeme::BigInt(_)) => true,
+            Some(Lexeme::Str(_) | Lexeme::Open) => true,
+            Some(Lexeme::Word(w)) => w == "true" || w == "false",
+            _ => false,
+        }
+    }
+
+    fn application(&mut self) -> Result<ExprPtr, ParseError> {
+        let builtin = match self.peek() {
+            Some(Lexeme::Word(w)) => BUILTINS.iter().find(|(name, _, _)| name == w),
+            _ => None,
+        };
+        let mut res = match builtin {
+            Some((_, op, 2)) => {
+                self.pos += 1;
+                as_ptr(Expr::Binary(*op, self.atom()?, self.atom()?))
+            }
+            Some((_, op, _)) => {
+                self.pos += 1;
+                as_ptr(Expr::Unary(*op, self.atom()?))
+            }
+            None => self.atom()?,
+        };
+        while self.starts_atom() {
+            res = as_ptr(Expr::Binary('$', res, self.atom()?));
+        }
+        Ok(res)
+    }
+
+    fn atom(&mut self) -> Result<ExprPtr, ParseError> {
+        let (lexeme, span) = self.next("an expression")?;
+        let res = match lexeme {
+            Lexeme::Var(x) => Expr::Var(x),
+            Lexeme::Int(x) => Expr::Integer(x),
+            Lexeme::BigInt(x) => Expr::BigInteger(x),
+            Lexeme::Str(s) if !codec::is_encodable(&s) => {
+                let message = "string cannot be encoded in the ICFP text".to_string();
+                return Err(self.error(span, message));
+            }
+            Lexeme::Str(s) => Expr::String(s),
+            Lexeme::Word(w) if w == "true" => Expr::Boolean(true),
+            Lexeme::Word(w) if w == "false" => Expr::Boolean(false),
+            Lexeme::Open => {
+                let res = self.expr()?;
+                self.expect(Lexeme::Close, "')'")?;
+                return Ok(res);
+            }
+            Lexeme::Word(w) => return Err(self.error(span, format!("unexpected '{}'", w))),
+            _ => {
+                let text = &self.source[span.offset..span.offset + span.len];
+                return Err(self.error(span, format!("unexpected '{}'", text)));
+            }
+        };
+        Ok(as_ptr(res))
+    }
+}
+
+pub fn parse_lambda(source: &str) -> Result<ExprPtr, ParseError> {
+    let mut reader = Reader {
+        source,
+        lexemes: lex(source)?,
+        pos: 0,
+    };
+    let res = reader.expr()?;
+    if let Some((_, span)) = reader.lexemes.get(reader.pos) {
+        return Err(reader.error(*span, "unexpected text after the expression".to_string()));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(text: &str) {
+        let expr = parse_lambda(text).unwrap();
+        assert_eq!(to_lambda(&expr.borrow()), text);
+    }
+
+    #[test]
+    fn test_example() {
+        let text = "λx1. λx2. if x2 = 0 then 1 else 1 + x1 (x2 - 1)";
+        let expr = parse_lambda(text).unwrap();
+        let icfp = "L\" L# ? B= v# I! I\" B+ I\" B$ v\" B- v# I\"";
+        assert_eq!(serialize_expr(&expr.borrow()), icfp);
+        assert_eq!(to_lambda(&parse_into_ast(icfp.to_string()).borrow()), text);
+        // ASCII lambdas and extra parentheses are accepted
+        let loose = parse_lambda("\\x1.(\\x2. (if (x2 = 0) then 1 else (1 + (x1 (x2 - 1)))))");
+        assert_eq!(*loose.unwrap().borrow(), *expr.borrow());
+    }
+
+    #[test]
+    fn test_precedence() {
+        round_trip("x1 + x2 * x3");
+        round_trip("(x1 + x2) * x3");
+        round_trip("x1 - x2 - x3");
+        round_trip("x1 - (x2 - x3)");
+        round_trip("(x1 = x2) = x3");
+        round_trip("x1 || x2 && !x3");
+        round_trip("-x1 (x2 x3) + -x4");
+        round_trip("x1 x2 x3 (x4 x5)");
+        round_trip("(λx1. x1) (if true then 1 else 2)");
+        round_trip("x1 ++ take 3 \"ab\\ncd\" ++ int_to_str (drop x2 x3)");
+        round_trip("str_to_int x1 x2");
+    }
+
+    #[test]
+    fn test_negative_numbers() {
+        round_trip("x1 - -5");
+        round_trip("x1 (-5)");
+        round_trip("-(5)");
+        round_trip("-(-5)");
+        let expr = parse_lambda("x1 -5").unwrap();
+        assert_eq!(serialize_expr(&expr.borrow()), "B- v\" I&");
+        assert_eq!(*parse_lambda("-5").unwrap().borrow(), Expr::Integer(-5));
+    }
+
+    #[test]
+    fn test_round_trip_programs() {
+        for entry in fs::read_dir("problems").unwrap() {
+            let path = entry.unwrap().path();
+            let text = fs::read_to_string(&path).unwrap();
+            let expr = parser::parse_checked(text.trim()).unwrap();
+            let back = parse_lambda(&to_lambda(&expr.borrow())).unwrap();
+            assert_eq!(*back.borrow(), *expr.borrow(), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = parse_lambda("λx1 x1").unwrap_err();
+        assert!(err.message.contains("expected '.'"), "{}", err);
+        let err = parse_lambda("if x1 then 2").unwrap_err();
+        assert!(err.message.contains("expected 'else'"), "{}", err);
+        let err = parse_lambda("x1 = x2 = x3").unwrap_err();
+        assert_eq!(err.span.unwrap().offset, 8);
+        let err = parse_lambda("x1 ++ \"é\"").unwrap_err();
+        assert!(err.message.contains("cannot be encoded"), "{}", err);
+        assert_eq!(err.span.unwrap().offset, 6);
+        assert!(parse_lambda("y + 1").is_err());
+        assert!(parse_lambda("(x1").is_err());
+        assert!(parse_lambda("x1 @ x2").is_err());
+        assert!(parse_lambda("").is_err());
+    }
+}
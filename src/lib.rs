@@ -1,19 +1,52 @@
+use num_bigint::BigInt;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
 use once_cell::sync::Lazy;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
 use std::fs;
 use std::io::{self, Write};
 use std::{fmt, rc::Rc};
 
 
+pub mod absint;
+pub mod bitblast;
+pub mod codec;
+pub mod conformance;
+pub mod cse;
+pub mod decompile;
+pub mod fuzz;
+pub mod inline;
+pub mod inverse;
+pub mod json;
+pub mod lambda;
+pub mod minify;
+pub mod native;
+pub mod parser;
+pub mod partial;
+pub mod recursion;
+pub mod rewrite;
+pub mod sat;
+pub mod scope;
+pub mod sexp;
+pub mod stream;
 pub mod sudoku;
+pub mod symbolic;
+pub mod transpile;
+pub mod types;
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+// Serialises as `{"kind": "integer", "value": 5}`, `{"kind": "if"}` and so on,
+// see `json` for the expression format
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "lowercase")]
 pub enum Token {
     Boolean(bool),
     Integer(i64),
+    // Literal beyond i64, like the powers of 9 in the sudoku problems
+    #[serde(with = "decimal")]
+    BigInteger(BigInt),
     String(String),
     Unary(char),
     Binary(char),
@@ -22,6 +55,22 @@ pub enum Token {
     Var(i64),
 }
 
+// Bignums in JSON as decimal strings, numbers that long lose digits in
+// most readers
+pub(crate) mod decimal {
+    use num_bigint::BigInt;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(x: &BigInt, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&x.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BigInt, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
 pub type ExprPtr = Rc<RefCell<Expr>>;
 
 pub fn as_ptr(e: Expr) -> ExprPtr {
@@ -32,6 +81,8 @@ pub fn as_ptr(e: Expr) -> ExprPtr {
 pub enum Expr {
     Boolean(bool),
     Integer(i64),
+    // Only for values outside i64, see `big_integer`
+    BigInteger(BigInt),
     String(String),
     Unary(char, ExprPtr),
     Binary(char, ExprPtr, ExprPtr),
@@ -40,6 +91,52 @@ pub enum Expr {
     Var(i64),
 }
 
+// Dropping a deep expression recursively would overflow the stack, so the
+// contents of the children nothing else refers to are moved out and dropped
+// from a worklist instead
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        detach_children(self, &mut stack);
+        while let Some(mut expr) = stack.pop() {
+            detach_children(&mut expr, &mut stack);
+        }
+    }
+}
+
+fn detach_children(expr: &mut Expr, stack: &mut Vec<Expr>) {
+    let mut detach = |child: &ExprPtr| {
+        if Rc::strong_count(child) == 1 && !is_leaf(&child.borrow()) {
+            stack.push(std::mem::replace(&mut *child.borrow_mut(), Expr::Boolean(false)));
+        }
+    };
+    match expr {
+        Expr::Unary(_, a) | Expr::Lambda(_, a) => detach(a),
+        Expr::Binary(_, a, b) => {
+            detach(a);
+            detach(b);
+        }
+        Expr::If(cond, then, otherwise) => {
+            detach(cond);
+            detach(then);
+            detach(otherwise);
+        }
+        _ => {}
+    }
+}
+
+fn is_leaf(expr: &Expr) -> bool {
+    !matches!(expr, Expr::Unary(..) | Expr::Binary(..) | Expr::If(..) | Expr::Lambda(..))
+}
+
+// `Expr::Integer` where `x` fits in i64, `Expr::BigInteger` otherwise
+pub fn big_integer(x: BigInt) -> Expr {
+    match i64::try_from(&x) {
+        Ok(x) => Expr::Integer(x),
+        Err(_) => Expr::BigInteger(x),
+    }
+}
+
 pub fn short_str(expr: &Expr) -> String {
     if is_basic(expr) {
         return format!("{:?}", expr);
@@ -84,6 +181,37 @@ pub fn unwrap_i64(e: &Expr) -> i64 {
     panic!("Expected Expr::Integer from expression, got {:?}", e);
 }
 
+fn unwrap_big(e: &Expr) -> BigInt {
+    match e {
+        Expr::Integer(x) => BigInt::from(*x),
+        Expr::BigInteger(x) => x.clone(),
+        _ => panic!("Expected an integer from expression, got {:?}", e),
+    }
+}
+
+// Integer operators once either operand is beyond i64, `None` for the rest
+fn eval_big(op: char, a: &Expr, b: &Expr) -> Option<Expr> {
+    if !matches!(a, Expr::BigInteger(_)) && !matches!(b, Expr::BigInteger(_)) {
+        return None;
+    }
+    if !"+-*/%<>=".contains(op) {
+        return None;
+    }
+    let (a, b) = (unwrap_big(a), unwrap_big(b));
+    let res = match op {
+        '+' => big_integer(a + b),
+        '-' => big_integer(a - b),
+        '*' => big_integer(a * b),
+        // Both truncate towards zero, like i64
+        '/' => big_integer(a / b),
+        '%' => big_integer(a % b),
+        '<' => Expr::Boolean(a < b),
+        '>' => Expr::Boolean(a > b),
+        _ => Expr::Boolean(a == b),
+    };
+    Some(res)
+}
+
 pub fn unwrap_string(e: &Expr) -> String {
     if let Expr::String(x) = e {
         return x.clone();
@@ -101,23 +229,26 @@ fn to_string(s: &[char]) -> String {
 
 fn is_basic(e: &Expr) -> bool {
     match e {
-        Expr::Boolean(_) | Expr::Integer(_) | Expr::String(_) => true,
+        Expr::Boolean(_) | Expr::Integer(_) | Expr::BigInteger(_) | Expr::String(_) => true,
         _ => false,
     }
 }
 
 pub fn apply(expr_ptr: ExprPtr, target_x: i64, value: ExprPtr) -> ExprPtr {
-    println!("Apply Impl, f={}, x{}, v={}", short_str(&expr_ptr.borrow()), target_x, short_str(&value.clone().borrow()));
+    if debug_enabled() {
+        println!("Apply Impl, f={}, x{}, v={}", short_str(&expr_ptr.borrow()), target_x, short_str(&value.clone().borrow()));
+    }
     // print_ast(value.clone());
     {
-        let mut expr = expr_ptr.as_ref().borrow_mut();
+        // Shared borrow only, `value` may be a subtree of `expr_ptr` itself
+        let expr = expr_ptr.as_ref().borrow();
         if is_basic(&*expr) {
             return expr_ptr.clone();
         } else if let Expr::Var(x) = *expr {
             if x == target_x {
                 return value;
             }
-        } else if let Expr::Unary(op, ref mut a) = expr.clone() {
+        } else if let Expr::Unary(op, ref a) = expr.clone() {
             let new_a = apply(a.clone(), target_x, value);
             // *a = new_a;
             // println!("updated: {:?}", expr);
@@ -130,7 +261,7 @@ pub fn apply(expr_ptr: ExprPtr, target_x: i64, value: ExprPtr) -> ExprPtr {
             // *b = new_b;
             return as_ptr(Expr::Binary(op, new_a, new_b));
             // println!("updated: {:?}", expr);
-        } else if let Expr::If(ref mut a, ref mut b, ref mut c) = expr.clone() {
+        } else if let Expr::If(ref a, ref b, ref c) = expr.clone() {
             let new_a = apply(a.clone(), target_x, value.clone());
             let new_b = apply(b.clone(), target_x, value.clone());
             let new_c = apply(c.clone(), target_x, value.clone());
@@ -139,7 +270,7 @@ pub fn apply(expr_ptr: ExprPtr, target_x: i64, value: ExprPtr) -> ExprPtr {
             // *b = new_b;
             // *c = new_c;
             return as_ptr(Expr::If(new_a, new_b, new_c));
-        } else if let Expr::Lambda(x, ref mut a) = *expr {
+        } else if let Expr::Lambda(x, ref a) = *expr {
             if x == target_x {
                 // Don't go further if lambda captures the same variable
                 return expr_ptr.clone();
@@ -165,22 +296,39 @@ pub fn eval(expr_ptr: ExprPtr) -> ExprPtr {
         Expr::Unary(op, expr_a) => {
             let res = {
                 let a_ptr = eval(expr_a.clone());
+                if !is_basic(&a_ptr.borrow()) {
+                    // Argument needs more steps, same as for binary operators
+                    return as_ptr(Expr::Unary(*op, a_ptr));
+                }
                 let ref a = *a_ptr.borrow();
                 match op {
+                    '-' if matches!(a, Expr::BigInteger(_)) => big_integer(-unwrap_big(a)),
                     '-' => Expr::Integer(-unwrap_i64(a)),
                     '!' => Expr::Boolean(!unwrap_bool(a)),
                     '#' => {
                         let s = unwrap_string(a);
                         let chars = encode_string(s);
-                        let x = base94_string_to_int(&chars);
-                        Expr::Integer(x)
+                        create_ast(&[integer_token(&chars)], 0).0
+                    }
+                    '$' if matches!(a, Expr::BigInteger(_)) => {
+                        let x = unwrap_big(a);
+                        // Negative numbers come out empty, as for i64
+                        match codec::encode_bigint(&x) {
+                            Ok(digits) => Expr::String(decode_string(&to_chars(digits))),
+                            Err(_) => Expr::String(String::new()),
+                        }
                     }
                     '$' => {
                         let x = unwrap_i64(a);
-                        let s = int_to_base94_string(x);
-                        let s_chars: Vec<char> = s.chars().collect();
-                        let s_decoded = decode_string(&s_chars);
-                        Expr::String(s_decoded)
+                        if x < 0 {
+                            // No base-94 form, this always came out empty
+                            Expr::String(String::new())
+                        } else {
+                            let s = int_to_base94_string(x);
+                            let s_chars: Vec<char> = s.chars().collect();
+                            let s_decoded = decode_string(&s_chars);
+                            Expr::String(s_decoded)
+                        }
                     }
                     _ => panic!("Unexpected op: {}", op),
                 }
@@ -210,7 +358,9 @@ pub fn eval(expr_ptr: ExprPtr) -> ExprPtr {
 
                 let ref a = *a_ptr.borrow();
                 let ref b = *b_ptr.borrow();
-                if is_basic(a) && is_basic(b) {
+                if let Some(res) = eval_big(*op, a, b) {
+                    res
+                } else if is_basic(a) && is_basic(b) {
                     match op {
                         '+' => Expr::Integer(unwrap_i64(&a) + unwrap_i64(&b)),
                         '-' => Expr::Integer(unwrap_i64(&a) - unwrap_i64(&b)),
@@ -280,26 +430,27 @@ pub fn eval(expr_ptr: ExprPtr) -> ExprPtr {
     }
 }
 
+// Infallible wrappers around `codec`, for trusted input
 pub fn base94_string_to_int(chars: &[char]) -> i64 {
-    let mult = 94;
-    let mut res = 0;
-    for c in chars.iter() {
-        res *= mult;
-        let d = (*c as u8 - '!' as u8) as i64;
-        res += d;
-        // println!("d = {} -> {}", *c, d);
+    // An empty digit string is zero, `U# S` relies on this
+    if chars.is_empty() {
+        return 0;
     }
-    res
+    let digits: String = chars.iter().collect();
+    codec::decode_int(&digits).unwrap_or_else(|e| panic!("[base94_string_to_int] {}", e))
 }
-pub fn int_to_base94_string(mut x: i64) -> String {
-    let mut res = Vec::new();
-    while x > 0 {
-        let d = x % 94;
-        let c = (d as u8 + '!' as u8) as char;
-        res.push(c);
-        x = x / 94;
+// `I` token of base-94 digits, a bignum where they overflow i64
+fn integer_token(chars: &[char]) -> Token {
+    // An empty digit string is zero, `U# S` relies on this
+    if chars.is_empty() {
+        return Token::Integer(0);
     }
-    res.iter().rev().collect()
+    let digits: String = chars.iter().collect();
+    codec::decode_integer(&digits).unwrap_or_else(|e| panic!("[integer_token] {}", e))
+}
+
+pub fn int_to_base94_string(x: i64) -> String {
+    codec::encode_int(x).unwrap_or_else(|e| panic!("[int_to_base94_string] {}", e))
 }
 
 static TRANSLATION_TABLE: Lazy<Vec<char>> = Lazy::new(|| {
@@ -315,26 +466,15 @@ static TRANSLATION_TABLE_REVERSE: Lazy<HashMap<char, usize>> = Lazy::new(|| {
 });
 
 pub fn decode_string(chars: &[char]) -> String {
-    let mut res = Vec::new();
-    for c in chars.iter() {
-        let code = *c as u8;
-        if code < 33 || code > 126 {
-            panic!("Invalid code: {} ('{}')", code, *c);
-        }
-        let index = (*c as u8 - 33) as usize;
-        res.push(TRANSLATION_TABLE[index]);
-    }
-    res.iter().collect()
+    let body: String = chars.iter().collect();
+    codec::decode_str(&body).unwrap_or_else(|e| panic!("[decode_string] {}", e))
 }
 
 pub fn encode_string(s: String) -> Vec<char> {
-    let mut res = Vec::new();
-    for c in s.chars() {
-        let idx = TRANSLATION_TABLE_REVERSE.get(&c).unwrap();
-        let code = (*idx as u8 + 33) as char;
-        res.push(code);
-    }
-    res
+    codec::encode_str(&s)
+        .unwrap_or_else(|e| panic!("[encode_string] {}", e))
+        .chars()
+        .collect()
 }
 
 pub fn parse_token(s: String) -> Token {
@@ -343,10 +483,7 @@ pub fn parse_token(s: String) -> Token {
     match indicator {
         'T' => Token::Boolean(true),
         'F' => Token::Boolean(false),
-        'I' => {
-            let value = base94_string_to_int(&chars[1..]);
-            Token::Integer(value)
-        }
+        'I' => integer_token(&chars[1..]),
         'S' => {
             let decoded = decode_string(&chars[1..]);
             return Token::String(decoded);
@@ -408,6 +545,7 @@ pub fn create_ast(tokens: &[Token], idx: usize) -> (Expr, usize) {
     match token {
         Token::Boolean(b) => (Expr::Boolean(*b), idx + 1),
         Token::Integer(x) => (Expr::Integer(*x), idx + 1),
+        Token::BigInteger(x) => (Expr::BigInteger(x.clone()), idx + 1),
         Token::String(s) => (Expr::String(s.clone()), idx + 1),
         Token::Unary(op) => {
             let (expr, next_idx) = create_ast(&tokens, idx + 1);
@@ -445,6 +583,214 @@ pub fn parse_into_ast(s: String) -> ExprPtr {
     as_ptr(expr)
 }
 
+pub fn token_to_string(token: &Token) -> String {
+    codec::encode_token(token).unwrap_or_else(|e| panic!("[token_to_string] {}", e))
+}
+
+fn push_integer(x: i64, res: &mut Vec<Token>) {
+    if x >= 0 {
+        res.push(Token::Integer(x));
+    } else if x == i64::MIN {
+        // -2^63 has no positive counterpart, so emit -(2^63 - 1) - 1
+        res.push(Token::Binary('-'));
+        res.push(Token::Unary('-'));
+        res.push(Token::Integer(i64::MAX));
+        res.push(Token::Integer(1));
+    } else {
+        // There are no negative literals, negate the absolute value instead
+        res.push(Token::Unary('-'));
+        res.push(Token::Integer(-x));
+    }
+}
+
+fn expr_to_tokens_impl(expr: &Expr, res: &mut Vec<Token>) {
+    match expr {
+        Expr::Boolean(b) => res.push(Token::Boolean(*b)),
+        Expr::Integer(x) => push_integer(*x, res),
+        Expr::BigInteger(x) if *x < BigInt::from(0) => {
+            res.push(Token::Unary('-'));
+            res.push(Token::BigInteger(-x));
+        }
+        Expr::BigInteger(x) => res.push(Token::BigInteger(x.clone())),
+        Expr::String(s) => res.push(Token::String(s.clone())),
+        Expr::Unary(op, a) => {
+            res.push(Token::Unary(*op));
+            expr_to_tokens_impl(&a.borrow(), res);
+        }
+        Expr::Binary(op, a, b) => {
+            res.push(Token::Binary(*op));
+            expr_to_tokens_impl(&a.borrow(), res);
+            expr_to_tokens_impl(&b.borrow(), res);
+        }
+        Expr::If(a, b, c) => {
+            res.push(Token::If);
+            expr_to_tokens_impl(&a.borrow(), res);
+            expr_to_tokens_impl(&b.borrow(), res);
+            expr_to_tokens_impl(&c.borrow(), res);
+        }
+        Expr::Lambda(x, a) => {
+            res.push(Token::Lambda(*x));
+            expr_to_tokens_impl(&a.borrow(), res);
+        }
+        Expr::Var(x) => res.push(Token::Var(*x)),
+    }
+}
+
+// Prefix-order tokens, the inverse of `create_ast`
+pub fn expr_to_tokens(expr: &Expr) -> Vec<Token> {
+    let mut res = Vec::new();
+    expr_to_tokens_impl(expr, &mut res);
+    res
+}
+
+// ICFP token text for an expression, the inverse of `parse_into_ast`.
+// Shared subtrees are written out once per use. Fails on strings outside the
+// translation table and negative variable ids, which have no token text.
+pub fn try_serialize_expr(expr: &Expr) -> Result<String, codec::CodecError> {
+    let tokens = expr_to_tokens(expr);
+    let parts = tokens
+        .iter()
+        .map(codec::encode_token)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(parts.join(" "))
+}
+
+// Infallible wrapper, for expressions built by the program itself
+pub fn serialize_expr(expr: &Expr) -> String {
+    try_serialize_expr(expr).unwrap_or_else(|e| panic!("[serialize_expr] {}", e))
+}
+
+// Direct subexpressions, in the same order as in the variant
+pub fn children(expr: &Expr) -> Vec<ExprPtr> {
+    match expr {
+        Expr::Unary(_, a) | Expr::Lambda(_, a) => vec![a.clone()],
+        Expr::Binary(_, a, b) => vec![a.clone(), b.clone()],
+        Expr::If(a, b, c) => vec![a.clone(), b.clone(), c.clone()],
+        _ => vec![],
+    }
+}
+
+fn is_closed_impl(expr: &Expr, bound: &mut Vec<i64>) -> bool {
+    match expr {
+        Expr::Var(x) => bound.contains(x),
+        Expr::Lambda(x, body) => {
+            bound.push(*x);
+            let res = is_closed_impl(&body.borrow(), bound);
+            bound.pop();
+            res
+        }
+        _ => children(expr).iter().all(|c| is_closed_impl(&c.borrow(), bound)),
+    }
+}
+
+// Whether `expr` has no free variables
+pub fn is_closed(expr: &Expr) -> bool {
+    is_closed_impl(expr, &mut Vec::new())
+}
+
+// Read-only traversal. Every variant has a method, the defaults visit all
+// children, so an implementation only overrides the cases it cares about.
+// Overriding `visit_expr` gives access to the node pointer itself, call
+// `walk_expr` from it to keep descending.
+pub trait Visitor {
+    fn visit_expr(&mut self, expr_ptr: &ExprPtr) {
+        walk_expr(self, expr_ptr);
+    }
+    fn visit_boolean(&mut self, _b: bool) {}
+    fn visit_integer(&mut self, _x: i64) {}
+    fn visit_big_integer(&mut self, _x: &BigInt) {}
+    fn visit_string(&mut self, _s: &str) {}
+    fn visit_var(&mut self, _x: i64) {}
+    fn visit_unary(&mut self, _op: char, a: &ExprPtr) {
+        self.visit_expr(a);
+    }
+    fn visit_binary(&mut self, _op: char, a: &ExprPtr, b: &ExprPtr) {
+        self.visit_expr(a);
+        self.visit_expr(b);
+    }
+    fn visit_if(&mut self, cond: &ExprPtr, then: &ExprPtr, otherwise: &ExprPtr) {
+        self.visit_expr(cond);
+        self.visit_expr(then);
+        self.visit_expr(otherwise);
+    }
+    fn visit_lambda(&mut self, _x: i64, body: &ExprPtr) {
+        self.visit_expr(body);
+    }
+}
+
+// Dispatches to the method for the variant of `expr_ptr`
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr_ptr: &ExprPtr) {
+    // Children are shared pointers, so this copy is shallow. It also lets
+    // visitors mutate the nodes they visit.
+    let e = expr_ptr.borrow().clone();
+    match &e {
+        Expr::Boolean(b) => visitor.visit_boolean(*b),
+        Expr::Integer(x) => visitor.visit_integer(*x),
+        Expr::BigInteger(x) => visitor.visit_big_integer(x),
+        Expr::String(s) => visitor.visit_string(s),
+        Expr::Var(x) => visitor.visit_var(*x),
+        Expr::Unary(op, a) => visitor.visit_unary(*op, a),
+        Expr::Binary(op, a, b) => visitor.visit_binary(*op, a, b),
+        Expr::If(a, b, c) => visitor.visit_if(a, b, c),
+        Expr::Lambda(x, a) => visitor.visit_lambda(*x, a),
+    }
+}
+
+// Tree-rebuilding traversal, the counterpart of `Visitor`. The defaults copy
+// the node with folded children; the input tree is left untouched.
+pub trait Fold {
+    fn fold_expr(&mut self, expr_ptr: &ExprPtr) -> ExprPtr {
+        walk_fold(self, expr_ptr)
+    }
+    fn fold_boolean(&mut self, b: bool) -> Expr {
+        Expr::Boolean(b)
+    }
+    fn fold_integer(&mut self, x: i64) -> Expr {
+        Expr::Integer(x)
+    }
+    fn fold_big_integer(&mut self, x: &BigInt) -> Expr {
+        Expr::BigInteger(x.clone())
+    }
+    fn fold_string(&mut self, s: &str) -> Expr {
+        Expr::String(s.to_string())
+    }
+    fn fold_var(&mut self, x: i64) -> Expr {
+        Expr::Var(x)
+    }
+    fn fold_unary(&mut self, op: char, a: &ExprPtr) -> Expr {
+        Expr::Unary(op, self.fold_expr(a))
+    }
+    fn fold_binary(&mut self, op: char, a: &ExprPtr, b: &ExprPtr) -> Expr {
+        let a = self.fold_expr(a);
+        Expr::Binary(op, a, self.fold_expr(b))
+    }
+    fn fold_if(&mut self, cond: &ExprPtr, then: &ExprPtr, otherwise: &ExprPtr) -> Expr {
+        let cond = self.fold_expr(cond);
+        let then = self.fold_expr(then);
+        Expr::If(cond, then, self.fold_expr(otherwise))
+    }
+    fn fold_lambda(&mut self, x: i64, body: &ExprPtr) -> Expr {
+        Expr::Lambda(x, self.fold_expr(body))
+    }
+}
+
+// Dispatches to the method for the variant of `expr_ptr`
+pub fn walk_fold<F: Fold + ?Sized>(folder: &mut F, expr_ptr: &ExprPtr) -> ExprPtr {
+    let e = expr_ptr.borrow().clone();
+    let res = match &e {
+        Expr::Boolean(b) => folder.fold_boolean(*b),
+        Expr::Integer(x) => folder.fold_integer(*x),
+        Expr::BigInteger(x) => folder.fold_big_integer(x),
+        Expr::String(s) => folder.fold_string(s),
+        Expr::Var(x) => folder.fold_var(*x),
+        Expr::Unary(op, a) => folder.fold_unary(*op, a),
+        Expr::Binary(op, a, b) => folder.fold_binary(*op, a, b),
+        Expr::If(a, b, c) => folder.fold_if(a, b, c),
+        Expr::Lambda(x, a) => folder.fold_lambda(*x, a),
+    };
+    as_ptr(res)
+}
+
 pub fn print_ast(e_ptr: ExprPtr) {
     let mut p = Printer::new();
     p.print_ast_impl(e_ptr, 0, p.counter);
@@ -597,6 +943,25 @@ mod tests {
         test("BD I$ S4%34", Expr::String("t".to_string()));
     }
 
+    #[test]
+    fn test_big_integers() {
+        // 94^11 - 1 and 94^10 - 1, both beyond i64
+        let (a, b) = ("I~~~~~~~~~~~", "I~~~~~~~~~~");
+        let big = |s: &str| Expr::BigInteger(s.parse().unwrap());
+        let test = |program: String, expected: Expr| {
+            assert_eq!(eval_example(&program), expected, "{}", program);
+        };
+        test(a.to_string(), big("5062982072492057196543"));
+        test(format!("B/ {} {}", a, b), Expr::Integer(94));
+        test(format!("B% {} {}", a, b), Expr::Integer(93));
+        test(format!("B- {} {}", a, a), Expr::Integer(0));
+        test(format!("B< I! {}", b), Expr::Boolean(true));
+        test(format!("U- {}", a), big("-5062982072492057196543"));
+        test(format!("U# U$ {}", a), big("5062982072492057196543"));
+        let negative = serialize_expr(&big("-5062982072492057196543"));
+        assert_eq!(negative, format!("U- {}", a));
+    }
+
     #[test]
     fn test_if_operator() {
         assert_eq!(
@@ -613,6 +978,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_shadowed_apply() {
+        // Inner L# shadows the outer one, so the result is the inner argument
+        assert_eq!(
+            eval_example(r#"B$ L# B$ L# v# I$ I#"#),
+            Expr::Integer(3)
+        );
+        // Argument used twice, it is shared between both substitutions
+        assert_eq!(
+            eval_example(r#"B$ L# ? B$ L$ v# v# F T B= F T"#),
+            Expr::Boolean(true)
+        );
+    }
+
     #[test]
     fn test_eval() {
         let (res, steps) = eval_example_impl(
@@ -622,6 +1001,112 @@ mod tests {
         assert_eq!(steps, 21);
     }
 
+    #[test]
+    fn test_visitor_sees_every_variant() {
+        #[derive(Default)]
+        struct Count {
+            leaves: usize,
+            inner: usize,
+            vars: Vec<i64>,
+        }
+        impl Visitor for Count {
+            fn visit_expr(&mut self, expr_ptr: &ExprPtr) {
+                if children(&expr_ptr.borrow()).is_empty() {
+                    self.leaves += 1;
+                } else {
+                    self.inner += 1;
+                }
+                walk_expr(self, expr_ptr);
+            }
+            fn visit_var(&mut self, x: i64) {
+                self.vars.push(x);
+            }
+        }
+        let expr = parse_into_ast(r#"? T B$ L# U- v# I" B. S" F"#.to_string());
+        let mut count = Count::default();
+        count.visit_expr(&expr);
+        assert_eq!((count.leaves, count.inner, count.vars), (5, 5, vec![2]));
+    }
+
+    #[test]
+    fn test_fold_rebuilds() {
+        // Renames variable 2 to 5 everywhere and doubles integers
+        struct Rename;
+        impl Fold for Rename {
+            fn fold_var(&mut self, x: i64) -> Expr {
+                Expr::Var(if x == 2 { 5 } else { x })
+            }
+            fn fold_lambda(&mut self, x: i64, body: &ExprPtr) -> Expr {
+                Expr::Lambda(if x == 2 { 5 } else { x }, self.fold_expr(body))
+            }
+            fn fold_integer(&mut self, x: i64) -> Expr {
+                Expr::Integer(x * 2)
+            }
+        }
+        let text = r#"? T B$ L# U- v# I" B. S" F"#;
+        let expr = parse_into_ast(text.to_string());
+        let res = Rename.fold_expr(&expr);
+        assert_eq!(
+            serialize_expr(&res.borrow()),
+            r#"? T B$ L& U- v& I# B. S" F"#
+        );
+        assert_eq!(serialize_expr(&expr.borrow()), text);
+    }
+
+    #[test]
+    fn test_serialize_literals() {
+        let test = |e: Expr, s: &str| {
+            assert_eq!(serialize_expr(&e), s);
+            assert_eq!(*parse_into_ast(s.to_string()).borrow(), e);
+        };
+        test(Expr::Integer(0), "I!");
+        test(Expr::Integer(1337), "I/6");
+        test(Expr::Integer(94), "I\"!");
+        test(Expr::String("".to_string()), "S");
+        test(Expr::String("Hello World!".to_string()), "SB%,,/}Q/2,$_");
+        test(Expr::Lambda(0, as_ptr(Expr::Var(0))), "L! v!");
+        test(Expr::Lambda(94, as_ptr(Expr::Var(94))), "L\"! v\"!");
+
+        assert_eq!(serialize_expr(&Expr::Integer(-3)), "U- I$");
+        assert_eq!(eval_example("U- I$"), Expr::Integer(-3));
+        let min = serialize_expr(&Expr::Integer(i64::MIN));
+        assert_eq!(eval_example(&min), Expr::Integer(i64::MIN));
+
+        assert_eq!(
+            try_serialize_expr(&Expr::String("é".to_string())),
+            Err(codec::CodecError::Unencodable('é'))
+        );
+        let negative = Expr::Lambda(-1, as_ptr(Expr::Var(-1)));
+        assert!(matches!(
+            try_serialize_expr(&negative),
+            Err(codec::CodecError::Negative(_))
+        ));
+    }
+
+    #[test]
+    fn test_serialize_shared_subtree() {
+        let shared = as_ptr(Expr::Integer(2));
+        let e = Expr::Binary('+', shared.clone(), shared);
+        assert_eq!(serialize_expr(&e), "B+ I# I#");
+    }
+
+    #[test]
+    fn test_serialize_round_trip_problems() {
+        let mut paths: Vec<_> = fs::read_dir("problems")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let text = fs::read_to_string(&path).unwrap();
+            let expr = parse_into_ast(text.clone());
+            let serialized = serialize_expr(&expr.borrow());
+            assert_eq!(serialized, text.trim(), "{:?}", path);
+            assert_eq!(parse_into_ast(serialized), expr, "{:?}", path);
+        }
+    }
+
     #[test]
     fn test_language_test() {
         let example = fs::read_to_string("language_test.txt").unwrap();
@@ -631,11 +1116,32 @@ mod tests {
 }
 
 const OP_LIMIT: usize = 10_000_000;
-const DEBUG: bool = true;
+const STEP_LIMIT: usize = 550;
+
+thread_local! {
+    // Per thread, so tests running in parallel can't switch it for each other
+    static DEBUG: Cell<bool> = const { Cell::new(true) };
+}
+
+// Step-by-step tracing from `eval_expr` and `apply` on the current thread,
+// on by default
+pub fn set_debug(enabled: bool) {
+    DEBUG.with(|debug| debug.set(enabled));
+}
 
-pub fn eval_expr(mut expr_ptr: ExprPtr) -> (Expr, usize) {
-    for step in 0..550 {
-        if DEBUG {
+fn debug_enabled() -> bool {
+    DEBUG.with(|debug| debug.get())
+}
+
+pub fn eval_expr(expr_ptr: ExprPtr) -> (Expr, usize) {
+    eval_expr_limit(expr_ptr, STEP_LIMIT)
+}
+
+// Same as `eval_expr` with a custom number of steps, returns OP_LIMIT steps
+// if the expression is not a value by then
+pub fn eval_expr_limit(mut expr_ptr: ExprPtr, max_steps: usize) -> (Expr, usize) {
+    for step in 0..max_steps {
+        if debug_enabled() {
             println!("step = {}, AST:", step);
             if step >= 55 {
                 // print_ast_eval(expr_ptr.clone());
@@ -659,6 +1165,31 @@ pub fn eval_expr(mut expr_ptr: ExprPtr) -> (Expr, usize) {
     (borrowed.clone(), OP_LIMIT)
 }
 
+// Evaluates a closed expression quietly. `None` if it panics, runs out of
+// steps or reduces to a lambda, which `eval` cannot step any further.
+pub fn try_eval(expr_ptr: ExprPtr, max_steps: usize) -> Option<Expr> {
+    let was_debug = debug_enabled();
+    set_debug(false);
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut expr_ptr = expr_ptr;
+        for _ in 0..max_steps {
+            {
+                let e = expr_ptr.borrow();
+                if is_basic(&e) {
+                    return Some(e.clone());
+                }
+                if let Expr::Lambda(_, _) | Expr::Var(_) = *e {
+                    return None;
+                }
+            }
+            expr_ptr = eval(expr_ptr);
+        }
+        None
+    }));
+    set_debug(was_debug);
+    res.ok().flatten()
+}
+
 pub fn eval_example_impl(example: &str) -> (Expr, usize) {
     let expr_ptr = parse_into_ast(example.to_string());
     eval_expr(expr_ptr)
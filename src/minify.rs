@@ -0,0 +1,220 @@
+use crate::*;
+
+use std::collections::HashSet;
+
+// Renames lambda variables so programs get shorter. Ids are base-94, so
+// 0..=93 take one digit, the next 94^2 two and so on. Binders are renamed
+// in order of how often they are referenced, each taking the smallest id not
+// used by a binder it conflicts with.
+//
+// Inner binder B conflicts with outer binder A only when A is referenced
+// inside B's body: giving both the same id would make B capture those
+// references. Otherwise the two can share an id, as can binders in disjoint
+// scopes. Free variables keep their ids, and no binder takes one of them.
+//
+// Renaming keeps the binding structure, and the evaluator only substitutes
+// closed arguments, so the result evaluates the same way.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MinifyReport {
+    // Size of the serialised program, in bytes
+    pub before: usize,
+    pub after: usize,
+}
+
+impl MinifyReport {
+    pub fn saved(&self) -> usize {
+        self.before - self.after
+    }
+}
+
+#[derive(Default)]
+struct Binder {
+    refs: usize,
+    conflicts: HashSet<usize>,
+}
+
+// Binders are numbered in pre-order, `rename` walks the tree the same way
+struct Analysis {
+    binders: Vec<Binder>,
+    free: HashSet<i64>,
+}
+
+// Returns the binders outside `expr` that are referenced inside it
+fn analyze(expr: &Expr, scope: &mut Vec<(i64, usize)>, res: &mut Analysis) -> HashSet<usize> {
+    match expr {
+        Expr::Var(x) => match scope.iter().rev().find(|(id, _)| id == x) {
+            Some((_, b)) => {
+                res.binders[*b].refs += 1;
+                HashSet::from([*b])
+            }
+            None => {
+                res.free.insert(*x);
+                HashSet::new()
+            }
+        },
+        Expr::Lambda(x, body) => {
+            let b = res.binders.len();
+            res.binders.push(Binder::default());
+            scope.push((*x, b));
+            let mut used = analyze(&body.borrow(), scope, res);
+            scope.pop();
+            used.remove(&b);
+            for outer in used.iter() {
+                res.binders[b].conflicts.insert(*outer);
+                res.binders[*outer].conflicts.insert(b);
+            }
+            used
+        }
+        Expr::Unary(_, a) => analyze(&a.borrow(), scope, res),
+        Expr::Binary(_, a, b) => {
+            let mut used = analyze(&a.borrow(), scope, res);
+            used.extend(analyze(&b.borrow(), scope, res));
+            used
+        }
+        Expr::If(a, b, c) => {
+            let mut used = analyze(&a.borrow(), scope, res);
+            used.extend(analyze(&b.borrow(), scope, res));
+            used.extend(analyze(&c.borrow(), scope, res));
+            used
+        }
+        _ => HashSet::new(),
+    }
+}
+
+fn assign_ids(analysis: &Analysis) -> Vec<i64> {
+    let binders = &analysis.binders;
+    let mut order: Vec<usize> = (0..binders.len()).collect();
+    order.sort_by_key(|b| std::cmp::Reverse(binders[*b].refs));
+
+    let mut ids: Vec<Option<i64>> = vec![None; binders.len()];
+    for b in order {
+        let taken: HashSet<i64> = binders[b]
+            .conflicts
+            .iter()
+            .filter_map(|other| ids[*other])
+            .collect();
+        let id = (0..)
+            .find(|id| !taken.contains(id) && !analysis.free.contains(id))
+            .unwrap();
+        ids[b] = Some(id);
+    }
+    ids.into_iter().map(|id| id.unwrap()).collect()
+}
+
+// `scope` maps old ids to new ones, innermost last
+fn rename(expr: &Expr, ids: &[i64], next: &mut usize, scope: &mut Vec<(i64, i64)>) -> ExprPtr {
+    let res = match expr {
+        Expr::Var(x) => match scope.iter().rev().find(|(old, _)| old == x) {
+            Some((_, new)) => Expr::Var(*new),
+            None => Expr::Var(*x),
+        },
+        Expr::Lambda(x, body) => {
+            let id = ids[*next];
+            *next += 1;
+            scope.push((*x, id));
+            let body = rename(&body.borrow(), ids, next, scope);
+            scope.pop();
+            Expr::Lambda(id, body)
+        }
+        Expr::Unary(op, a) => Expr::Unary(*op, rename(&a.borrow(), ids, next, scope)),
+        Expr::Binary(op, a, b) => {
+            let a = rename(&a.borrow(), ids, next, scope);
+            Expr::Binary(*op, a, rename(&b.borrow(), ids, next, scope))
+        }
+        Expr::If(a, b, c) => {
+            let a = rename(&a.borrow(), ids, next, scope);
+            let b = rename(&b.borrow(), ids, next, scope);
+            Expr::If(a, b, rename(&c.borrow(), ids, next, scope))
+        }
+        _ => expr.clone(),
+    };
+    as_ptr(res)
+}
+
+pub fn minify(expr: &Expr) -> (ExprPtr, MinifyReport) {
+    let mut analysis = Analysis {
+        binders: Vec::new(),
+        free: HashSet::new(),
+    };
+    analyze(expr, &mut Vec::new(), &mut analysis);
+    let ids = assign_ids(&analysis);
+    let res = rename(expr, &ids, &mut 0, &mut Vec::new());
+
+    let before = serialize_expr(expr).len();
+    // The greedy assignment is not optimal, so in rare cases it needs
+    // longer ids than the input had; keep the input then
+    let after = serialize_expr(&res.borrow()).len();
+    if after > before {
+        return (
+            as_ptr(expr.clone()),
+            MinifyReport {
+                before,
+                after: before,
+            },
+        );
+    }
+    (res, MinifyReport { before, after })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minified(program: &str) -> String {
+        let expr = parse_into_ast(program.to_string());
+        let (res, report) = minify(&expr.borrow());
+        let text = serialize_expr(&res.borrow());
+        assert_eq!(report.after, text.len());
+        text
+    }
+
+    #[test]
+    fn test_renames_large_ids() {
+        // L~~ is var 94 * 93 + 93
+        assert_eq!(minified("B$ L~~ B+ v~~ v~~ I#"), "B$ L! B+ v! v! I#");
+        let expr = parse_into_ast("B$ L~~ B+ v~~ v~~ I#".to_string());
+        let (_, report) = minify(&expr.borrow());
+        assert_eq!(report.saved(), 3);
+    }
+
+    #[test]
+    fn test_most_referenced_first() {
+        assert_eq!(minified("L\" L# B+ v# B+ v# v\""), "L\" L! B+ v! B+ v! v\"");
+    }
+
+    #[test]
+    fn test_reuses_ids() {
+        // Disjoint scopes
+        assert_eq!(
+            minified("B. B$ L~~ v~~ S B$ L}} v}} S"),
+            "B. B$ L! v! S B$ L! v! S"
+        );
+        // Nested, but the outer variable is not used inside the inner lambda
+        assert_eq!(minified("L\" B+ v\" B$ L# v# I\""), "L! B+ v! B$ L! v! I\"");
+    }
+
+    #[test]
+    fn test_free_variables_are_kept() {
+        assert_eq!(minified("L# B+ v! v#"), "L\" B+ v! v\"");
+    }
+
+    #[test]
+    fn test_preserves_semantics() {
+        set_debug(false);
+        let text = fs::read_to_string("tests/conformance.txt").unwrap();
+        let cases = conformance::parse_cases(&text, ".").unwrap();
+        for case in cases.iter() {
+            let Ok(expr) = parser::parse_checked(&case.program) else {
+                continue;
+            };
+            let (res, report) = minify(&expr.borrow());
+            assert!(report.after <= report.before);
+            let case = conformance::Case {
+                program: serialize_expr(&res.borrow()),
+                ..case.clone()
+            };
+            assert_eq!(conformance::run_case(&case), Ok(()), "{}", case.name);
+        }
+    }
+}
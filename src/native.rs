@@ -0,0 +1,295 @@
+use crate::*;
+
+use num_bigint::BigInt;
+
+// Runtime for programs compiled to Rust by `transpile`. Values follow the
+// evaluator except that integers are `BigInt`, so nothing overflows.
+//
+// Arguments are passed as `Thunk`s, evaluated at most once when first
+// forced. Call-by-need gives the same results as the evaluator's
+// call-by-name for pure programs, an unused argument is never evaluated.
+// Operators evaluate both operands, `|` and `&` included, and panic on
+// operands of the wrong type like the evaluator does.
+
+#[derive(Clone)]
+pub enum Value {
+    Bool(bool),
+    Int(BigInt),
+    Str(String),
+    Fun(Rc<dyn Fn(Thunk) -> Value>),
+}
+
+impl Value {
+    pub fn fun(f: impl Fn(Thunk) -> Value + 'static) -> Value {
+        Value::Fun(Rc::new(f))
+    }
+
+    pub fn as_bool(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            _ => panic!("Expected a boolean, got {}", self),
+        }
+    }
+
+    pub fn as_int(&self) -> &BigInt {
+        match self {
+            Value::Int(x) => x,
+            _ => panic!("Expected an integer, got {}", self),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Value::Str(s) => s,
+            _ => panic!("Expected a string, got {}", self),
+        }
+    }
+
+    // The evaluator's result, `None` for functions
+    pub fn to_expr(&self) -> Option<Expr> {
+        match self {
+            Value::Bool(b) => Some(Expr::Boolean(*b)),
+            Value::Int(x) => Some(big_integer(x.clone())),
+            Value::Str(s) => Some(Expr::String(s.clone())),
+            Value::Fun(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(x) => write!(f, "{}", x),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Fun(_) => write!(f, "<function>"),
+        }
+    }
+}
+
+enum State {
+    Pending(Box<dyn FnOnce() -> Value>),
+    // Being evaluated, forcing it again would never finish
+    Running,
+    Done(Value),
+}
+
+#[derive(Clone)]
+pub struct Thunk(Rc<RefCell<State>>);
+
+impl Thunk {
+    pub fn new(f: impl FnOnce() -> Value + 'static) -> Thunk {
+        Thunk(Rc::new(RefCell::new(State::Pending(Box::new(f)))))
+    }
+
+    pub fn ready(value: Value) -> Thunk {
+        Thunk(Rc::new(RefCell::new(State::Done(value))))
+    }
+
+    // A thunk that `f` can refer to, for recursive definitions. The result
+    // usually holds on to the thunk, so the two are never freed.
+    pub fn fix(f: impl FnOnce(Thunk) -> Value + 'static) -> Thunk {
+        let res = Thunk(Rc::new(RefCell::new(State::Running)));
+        let this = res.clone();
+        *res.0.borrow_mut() = State::Pending(Box::new(move || f(this)));
+        res
+    }
+
+    pub fn force(&self) -> Value {
+        let state = std::mem::replace(&mut *self.0.borrow_mut(), State::Running);
+        let value = match state {
+            State::Pending(f) => f(),
+            State::Running => panic!("Thunk depends on its own value"),
+            State::Done(value) => value,
+        };
+        *self.0.borrow_mut() = State::Done(value.clone());
+        value
+    }
+}
+
+pub fn int(x: i64) -> Value {
+    Value::Int(BigInt::from(x))
+}
+
+// Literal beyond i64, in decimal
+pub fn big_int(digits: &str) -> Value {
+    Value::Int(digits.parse().unwrap())
+}
+
+pub fn string(s: &str) -> Value {
+    Value::Str(s.to_string())
+}
+
+pub fn unbound(x: i64) -> Value {
+    panic!("Unbound variable {}", x)
+}
+
+pub fn apply(f: Value, arg: Thunk) -> Value {
+    match f {
+        Value::Fun(f) => f(arg),
+        _ => panic!("Expected a function, got {}", f),
+    }
+}
+
+pub fn neg(a: Value) -> Value {
+    Value::Int(-a.as_int())
+}
+
+pub fn not(a: Value) -> Value {
+    Value::Bool(!a.as_bool())
+}
+
+pub fn str_to_int(a: Value) -> Value {
+    let digits = codec::encode_str(a.as_str()).unwrap_or_else(|e| panic!("[str_to_int] {}", e));
+    if digits.is_empty() {
+        return int(0);
+    }
+    Value::Int(codec::decode_bigint(&digits).unwrap())
+}
+
+pub fn int_to_str(a: Value) -> Value {
+    let x = a.as_int();
+    // No base-94 form, like the evaluator this comes out empty
+    let Ok(digits) = codec::encode_bigint(x) else {
+        return string("");
+    };
+    Value::Str(codec::decode_str(&digits).unwrap())
+}
+
+pub fn add(a: Value, b: Value) -> Value {
+    Value::Int(a.as_int() + b.as_int())
+}
+
+pub fn sub(a: Value, b: Value) -> Value {
+    Value::Int(a.as_int() - b.as_int())
+}
+
+pub fn mul(a: Value, b: Value) -> Value {
+    Value::Int(a.as_int() * b.as_int())
+}
+
+// `BigInt` division and remainder truncate towards zero, like i64 ones
+pub fn div(a: Value, b: Value) -> Value {
+    Value::Int(a.as_int() / b.as_int())
+}
+
+pub fn rem(a: Value, b: Value) -> Value {
+    Value::Int(a.as_int() % b.as_int())
+}
+
+pub fn lt(a: Value, b: Value) -> Value {
+    Value::Bool(a.as_int() < b.as_int())
+}
+
+pub fn gt(a: Value, b: Value) -> Value {
+    Value::Bool(a.as_int() > b.as_int())
+}
+
+pub fn eq(a: Value, b: Value) -> Value {
+    match (&a, &b) {
+        (Value::Bool(x), Value::Bool(y)) => Value::Bool(x == y),
+        (Value::Int(x), Value::Int(y)) => Value::Bool(x == y),
+        (Value::Str(x), Value::Str(y)) => Value::Bool(x == y),
+        _ => panic!("Unsupported comparison a={} vs b={}", a, b),
+    }
+}
+
+pub fn or(a: Value, b: Value) -> Value {
+    Value::Bool(a.as_bool() | b.as_bool())
+}
+
+pub fn and(a: Value, b: Value) -> Value {
+    Value::Bool(a.as_bool() & b.as_bool())
+}
+
+pub fn concat(a: Value, b: Value) -> Value {
+    Value::Str(a.as_str().to_string() + b.as_str())
+}
+
+fn char_count(n: &Value) -> usize {
+    usize::try_from(n.as_int()).unwrap_or_else(|_| panic!("Bad length {}", n))
+}
+
+pub fn take(n: Value, s: Value) -> Value {
+    let chars: Vec<char> = s.as_str().chars().collect();
+    Value::Str(chars[..char_count(&n)].iter().collect())
+}
+
+pub fn drop(n: Value, s: Value) -> Value {
+    let chars: Vec<char> = s.as_str().chars().collect();
+    Value::Str(chars[char_count(&n)..].iter().collect())
+}
+
+// Runs `program` on a thread with a large stack, deep recursion is common,
+// and prints the result
+pub fn run_main(program: fn() -> Value) {
+    let res = std::thread::Builder::new()
+        .stack_size(1 << 30)
+        .spawn(move || program().to_string())
+        .unwrap()
+        .join()
+        .unwrap();
+    println!("{}", res);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expr(value: Value) -> Expr {
+        value.to_expr().unwrap()
+    }
+
+    #[test]
+    fn test_operators_match_evaluator() {
+        set_debug(false);
+        let cases = [
+            ("U# S4%34", str_to_int(string("test"))),
+            ("U$ I4%34", int_to_str(int(15818151))),
+            ("U$ U- I$", int_to_str(int(-3))),
+            ("B/ U- I( I#", div(int(-7), int(2))),
+            ("B% U- I( I#", rem(int(-7), int(2))),
+            ("BT I$ S4%34", take(int(3), string("test"))),
+            ("BD I$ S4%34", drop(int(3), string("test"))),
+            ("B= S4 S4", eq(string("t"), string("t"))),
+        ];
+        for (program, value) in cases {
+            assert_eq!(eval_example(program), expr(value), "{}", program);
+        }
+    }
+
+    #[test]
+    fn test_big_integers() {
+        let x = mul(int(i64::MAX), int(i64::MAX));
+        assert_eq!(x.to_string(), "85070591730234615847396907784232501249");
+        let digits = "85070591730234615847396907784232501249";
+        assert_eq!(x.to_expr(), Some(Expr::BigInteger(digits.parse().unwrap())));
+        assert!(eq(str_to_int(int_to_str(x.clone())), x).as_bool());
+    }
+
+    #[test]
+    fn test_thunks() {
+        let count = Rc::new(RefCell::new(0));
+        let counter = count.clone();
+        let thunk = Thunk::new(move || {
+            *counter.borrow_mut() += 1;
+            int(1)
+        });
+        assert_eq!(expr(add(thunk.force(), thunk.force())), Expr::Integer(2));
+        assert_eq!(*count.borrow(), 1);
+
+        // Recursion through the thunk itself
+        let ones = Thunk::fix(|this| {
+            Value::fun(move |n| {
+                let n = n.force();
+                if n.as_int() == &BigInt::from(0) {
+                    string("")
+                } else {
+                    let rest = Thunk::ready(sub(n, int(1)));
+                    concat(string("1"), apply(this.force(), rest))
+                }
+            })
+        });
+        assert_eq!(apply(ones.force(), Thunk::ready(int(3))).to_string(), "111");
+    }
+}
@@ -0,0 +1,354 @@
+use crate::*;
+
+// Tokenizer and parser that keep track of where every token came from, so
+// malformed programs produce an error pointing into the source instead of a
+// bare panic. `parse_into_ast` stays the quick path for trusted input.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    // Byte offset and byte length of the token in the source
+    pub offset: usize,
+    pub len: usize,
+    // Position of the token in the token stream
+    pub index: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+// Spans of an AST, shaped like the expression it was parsed with: children
+// are in the same order as in the corresponding `Expr` variant
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpanTree {
+    // Span of the token that starts the node
+    pub span: Span,
+    // Byte offset right after the last token of the node
+    pub end: usize,
+    pub children: Vec<SpanTree>,
+}
+
+impl SpanTree {
+    // Follows child indices from the root, like `[1, 0]` for the first child
+    // of the second child
+    pub fn at(&self, path: &[usize]) -> Option<&SpanTree> {
+        let mut node = self;
+        for idx in path {
+            node = node.children.get(*idx)?;
+        }
+        Some(node)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    // Failing token, None when the input ended too early
+    pub span: Option<Span>,
+    // Source excerpt with a marker under the failing token
+    pub context: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span {
+            Some(span) => writeln!(
+                f,
+                "parse error at token {} (byte {}): {}",
+                span.index, span.offset, self.message
+            )?,
+            None => writeln!(f, "parse error at end of input: {}", self.message)?,
+        }
+        write!(f, "{}", self.context)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Bytes of context shown on each side of the failing token
+const CONTEXT_BYTES: usize = 30;
+
+fn floor_char_boundary(s: &str, mut idx: usize) -> usize {
+    while !s.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    idx
+}
+
+fn ceil_char_boundary(s: &str, mut idx: usize) -> usize {
+    while !s.is_char_boundary(idx) {
+        idx += 1;
+    }
+    idx
+}
+
+// Single-line excerpt around `offset..offset + len` with carets under it
+fn quote_source(source: &str, offset: usize, len: usize) -> String {
+    let start = floor_char_boundary(source, offset.saturating_sub(CONTEXT_BYTES));
+    let end = ceil_char_boundary(source, (offset + len + CONTEXT_BYTES).min(source.len()));
+    let prefix = if start > 0 { "..." } else { "" };
+    let suffix = if end < source.len() { "..." } else { "" };
+    // Newlines would break the marker alignment
+    let flat = |s: &str| s.replace(|c: char| c.is_whitespace(), " ");
+    let before = flat(&source[start..offset]);
+    let token = flat(&source[offset..offset + len]);
+    let after = flat(&source[offset + len..end]);
+    let pad = prefix.chars().count() + before.chars().count();
+    format!(
+        "  {}{}{}{}{}\n  {}{}",
+        prefix,
+        before,
+        token,
+        after,
+        suffix,
+        " ".repeat(pad),
+        "^".repeat(token.chars().count().max(1))
+    )
+}
+
+pub(crate) fn error_at(source: &str, span: Span, message: String) -> ParseError {
+    ParseError {
+        message,
+        span: Some(span),
+        context: quote_source(source, span.offset, span.len),
+    }
+}
+
+pub(crate) fn error_at_end(source: &str, message: String) -> ParseError {
+    ParseError {
+        message,
+        span: None,
+        context: quote_source(source, source.len(), 0),
+    }
+}
+
+// Like `parse_token`, but reports problems instead of panicking
+pub fn try_parse_token(s: &str) -> Result<Token, String> {
+    codec::decode_token(s).map_err(|e| e.to_string())
+}
+
+pub fn tokenize_with_spans(source: &str) -> Result<Vec<SpannedToken>, ParseError> {
+    let mut res = Vec::new();
+    let mut start = None;
+    // Trailing sentinel flushes the last token
+    let chars = source
+        .char_indices()
+        .chain(std::iter::once((source.len(), ' ')));
+    for (offset, c) in chars {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(offset),
+            (true, Some(token_start)) => {
+                let span = Span {
+                    offset: token_start,
+                    len: offset - token_start,
+                    index: res.len(),
+                };
+                let token = try_parse_token(&source[token_start..offset])
+                    .map_err(|message| error_at(source, span, message))?;
+                res.push(SpannedToken { token, span });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    Ok(res)
+}
+
+fn arity(token: &Token) -> usize {
+    match token {
+        Token::Unary(_) | Token::Lambda(_) => 1,
+        Token::Binary(_) => 2,
+        Token::If => 3,
+        _ => 0,
+    }
+}
+
+struct SpanParser<'a> {
+    source: &'a str,
+    tokens: &'a [SpannedToken],
+    idx: usize,
+}
+
+impl<'a> SpanParser<'a> {
+    fn parse(&mut self) -> Result<(Expr, SpanTree), ParseError> {
+        let Some(head) = self.tokens.get(self.idx) else {
+            return Err(error_at_end(
+                self.source,
+                "expected an expression".to_string(),
+            ));
+        };
+        self.idx += 1;
+
+        let mut exprs = Vec::new();
+        let mut children = Vec::new();
+        for operand in 0..arity(&head.token) {
+            if self.idx >= self.tokens.len() {
+                let message = format!(
+                    "'{}' needs {} operand(s), input ended after {}",
+                    &self.source[head.span.offset..head.span.offset + head.span.len],
+                    arity(&head.token),
+                    operand
+                );
+                return Err(error_at(self.source, head.span, message));
+            }
+            let (expr, tree) = self.parse()?;
+            exprs.push(as_ptr(expr));
+            children.push(tree);
+        }
+
+        let expr = match &head.token {
+            Token::Boolean(b) => Expr::Boolean(*b),
+            Token::Integer(x) => Expr::Integer(*x),
+            Token::BigInteger(x) => Expr::BigInteger(x.clone()),
+            Token::String(s) => Expr::String(s.clone()),
+            Token::Var(x) => Expr::Var(*x),
+            Token::Unary(op) => Expr::Unary(*op, exprs[0].clone()),
+            Token::Lambda(x) => Expr::Lambda(*x, exprs[0].clone()),
+            Token::Binary(op) => Expr::Binary(*op, exprs[0].clone(), exprs[1].clone()),
+            Token::If => Expr::If(exprs[0].clone(), exprs[1].clone(), exprs[2].clone()),
+        };
+        let end = match children.last() {
+            Some(last) => last.end,
+            None => head.span.offset + head.span.len,
+        };
+        let tree = SpanTree {
+            span: head.span,
+            end,
+            children,
+        };
+        Ok((expr, tree))
+    }
+}
+
+pub fn parse_tokens_with_spans(
+    source: &str,
+    tokens: &[SpannedToken],
+) -> Result<(ExprPtr, SpanTree), ParseError> {
+    let mut parser = SpanParser {
+        source,
+        tokens,
+        idx: 0,
+    };
+    let (expr, tree) = parser.parse()?;
+    if let Some(extra) = tokens.get(parser.idx) {
+        let message = format!(
+            "unexpected token after a complete program ({} more)",
+            tokens.len() - parser.idx
+        );
+        return Err(error_at(source, extra.span, message));
+    }
+    Ok((as_ptr(expr), tree))
+}
+
+pub fn parse_with_spans(source: &str) -> Result<(ExprPtr, SpanTree), ParseError> {
+    let tokens = tokenize_with_spans(source)?;
+    parse_tokens_with_spans(source, &tokens)
+}
+
+// `parse_into_ast` with diagnostics instead of panics
+pub fn parse_checked(source: &str) -> Result<ExprPtr, ParseError> {
+    parse_with_spans(source).map(|(expr, _)| expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_spans() {
+        let source = "B+  I#\n I$";
+        let tokens = tokenize_with_spans(source).unwrap();
+        let spans: Vec<(usize, usize, usize)> = tokens
+            .iter()
+            .map(|t| (t.span.offset, t.span.len, t.span.index))
+            .collect();
+        assert_eq!(spans, vec![(0, 2, 0), (4, 2, 1), (8, 2, 2)]);
+        assert_eq!(tokens[1].token, Token::Integer(2));
+    }
+
+    #[test]
+    fn test_parse_matches_parse_into_ast() {
+        let source = fs::read_to_string("language_test.txt").unwrap();
+        let (expr, _) = parse_with_spans(&source).unwrap();
+        assert_eq!(expr, parse_into_ast(source));
+    }
+
+    #[test]
+    fn test_span_tree() {
+        let source = "? B> I# I$ S9%3 S./";
+        let (_, tree) = parse_with_spans(source).unwrap();
+        assert_eq!(tree.children.len(), 3);
+        assert_eq!(tree.end, source.len());
+        let cond = tree.at(&[0]).unwrap();
+        assert_eq!((cond.span.offset, cond.end), (2, 10));
+        assert_eq!(tree.at(&[0, 1]).unwrap().span.index, 3);
+        assert_eq!(tree.at(&[2]).unwrap().span.offset, 16);
+        assert!(tree.at(&[3]).is_none());
+    }
+
+    #[test]
+    fn test_bad_token() {
+        let err = parse_with_spans("B+ I# X12 I$").unwrap_err();
+        let span = err.span.unwrap();
+        assert_eq!((span.offset, span.len, span.index), (6, 3, 2));
+        assert!(err.message.contains("unknown token indicator 'X'"));
+        let text = err.to_string();
+        assert!(text.contains("B+ I# X12 I$\n        ^^^"), "{}", text);
+    }
+
+    #[test]
+    fn test_token_errors() {
+        assert!(try_parse_token("U").is_err());
+        assert!(try_parse_token("U+").is_err());
+        assert!(try_parse_token("B?").is_err());
+        assert!(try_parse_token("B++").is_err());
+        assert!(try_parse_token("I").is_err());
+        assert!(try_parse_token("TT").is_err());
+        assert!(try_parse_token("L~~~~~~~~~~").is_err());
+        assert!(matches!(
+            try_parse_token("I~~~~~~~~~~"),
+            Ok(Token::BigInteger(_))
+        ));
+        assert_eq!(try_parse_token("S"), Ok(Token::String("".to_string())));
+        assert_eq!(try_parse_token("L!"), Ok(Token::Lambda(0)));
+    }
+
+    #[test]
+    fn test_truncated_program() {
+        let err = parse_with_spans("B$ L# B+ v#").unwrap_err();
+        // The innermost incomplete operator is reported
+        assert_eq!(err.span.unwrap().index, 2);
+        assert!(
+            err.message.contains("needs 2 operand(s)"),
+            "{}",
+            err.message
+        );
+
+        let err = parse_with_spans("   ").unwrap_err();
+        assert_eq!(err.span, None);
+    }
+
+    #[test]
+    fn test_trailing_tokens() {
+        let err = parse_with_spans("I# I$ I%").unwrap_err();
+        assert_eq!(err.span.unwrap().index, 1);
+    }
+
+    #[test]
+    fn test_context_is_windowed() {
+        let mut source = "B+ ".repeat(100);
+        source += "Xz";
+        source += &" I!".repeat(101);
+        let err = parse_with_spans(&source).unwrap_err();
+        assert_eq!(err.span.unwrap().index, 100);
+        let line = err.context.lines().next().unwrap();
+        assert!(
+            line.starts_with("  ...") && line.ends_with("..."),
+            "{}",
+            line
+        );
+        assert!(line.len() < 2 * CONTEXT_BYTES + 20);
+    }
+}
@@ -0,0 +1,517 @@
+use crate::*;
+
+// Partial evaluation: folds operators on literals, simplifies algebraic
+// identities, picks `if` branches on known conditions and applies lambdas to
+// known arguments. Optionally it also runs the evaluator on closed
+// applications and keeps the value if one comes out within a few steps.
+// That is off by default: a single `eval` step walks shared subtrees once
+// per reference, so on programs like `problems/1.txt` it runs out of memory
+// long before it runs out of steps.
+//
+// Every simplification keeps the program's meaning, including its errors:
+// `x * 1` only becomes `x` when `x` is known to be an integer or is used
+// where an integer is required anyway, since otherwise a string `x` would
+// stop failing. Rewrites that drop a subexpression, like `x * 0`, are only
+// correct when that subexpression terminates without an error, so they need
+// `Options::assume_total`.
+//
+// Beta reduction is always sound here because evaluation is call-by-name.
+// Only closed arguments are substituted, so nothing can be captured, and
+// only when that does not grow the program: the argument is a literal or the
+// variable is used at most once.
+//
+// `Options::use_ranges` first runs `absint` over the whole program, then
+// picks the branch of every `if` whose condition it decides and replaces
+// integer subexpressions with a single possible value by that value. Facts
+// only cover the evaluations that return, so this drops code like
+// `assume_total` does and needs it to be set as well.
+
+// The default keeps semantics and does not evaluate
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    // Assume every subexpression terminates without an error
+    pub assume_total: bool,
+    // Steps to spend evaluating each closed application, 0 disables it
+    pub eval_steps: usize,
+    // Use the facts of `absint::analyze`, only with `assume_total`
+    pub use_ranges: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Type {
+    Int,
+    Bool,
+    Str,
+}
+
+// The type an expression has if it evaluates at all
+fn type_of(expr: &Expr) -> Option<Type> {
+    match expr {
+        Expr::Integer(_) | Expr::Unary('-' | '#', _) => Some(Type::Int),
+        Expr::Boolean(_) | Expr::Unary('!', _) => Some(Type::Bool),
+        Expr::String(_) | Expr::Unary('$', _) => Some(Type::Str),
+        Expr::Binary(op, _, _) => match op {
+            '+' | '-' | '*' | '/' | '%' => Some(Type::Int),
+            '<' | '>' | '=' | '|' | '&' => Some(Type::Bool),
+            '.' | 'T' | 'D' => Some(Type::Str),
+            _ => None,
+        },
+        Expr::If(_, b, c) => {
+            let t = type_of(&b.borrow());
+            if t == type_of(&c.borrow()) {
+                t
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+// Type the operands of `op` must have, checked by the evaluator
+fn operand_type(op: char) -> Option<Type> {
+    match op {
+        '+' | '-' | '*' | '/' | '%' | '<' | '>' => Some(Type::Int),
+        '|' | '&' => Some(Type::Bool),
+        '.' => Some(Type::Str),
+        _ => None,
+    }
+}
+
+pub(crate) fn fold_unary(op: char, a: &Expr) -> Option<Expr> {
+    let res = match (op, a) {
+        ('-', Expr::Integer(x)) => Expr::Integer(x.checked_neg()?),
+        ('!', Expr::Boolean(x)) => Expr::Boolean(!x),
+        ('#', Expr::String(s)) if s.is_empty() => Expr::Integer(0),
+        ('#', Expr::String(s)) => {
+            let digits = codec::encode_str(s).ok()?;
+            Expr::Integer(codec::decode_int(&digits).ok()?)
+        }
+        // Same as the evaluator, negative numbers have no base-94 form
+        ('$', Expr::Integer(x)) if *x < 0 => Expr::String(String::new()),
+        ('$', Expr::Integer(x)) => {
+            let digits = codec::encode_int(*x).ok()?;
+            Expr::String(codec::decode_str(&digits).ok()?)
+        }
+        _ => return None,
+    };
+    Some(res)
+}
+
+// `None` whenever the evaluator would fail, so the error stays in place
+pub(crate) fn fold_binary(op: char, a: &Expr, b: &Expr) -> Option<Expr> {
+    let res = match (op, a, b) {
+        ('+', Expr::Integer(x), Expr::Integer(y)) => Expr::Integer(x.checked_add(*y)?),
+        ('-', Expr::Integer(x), Expr::Integer(y)) => Expr::Integer(x.checked_sub(*y)?),
+        ('*', Expr::Integer(x), Expr::Integer(y)) => Expr::Integer(x.checked_mul(*y)?),
+        ('/', Expr::Integer(x), Expr::Integer(y)) => Expr::Integer(x.checked_div(*y)?),
+        ('%', Expr::Integer(x), Expr::Integer(y)) => Expr::Integer(x.checked_rem(*y)?),
+        ('<', Expr::Integer(x), Expr::Integer(y)) => Expr::Boolean(x < y),
+        ('>', Expr::Integer(x), Expr::Integer(y)) => Expr::Boolean(x > y),
+        ('=', Expr::Integer(x), Expr::Integer(y)) => Expr::Boolean(x == y),
+        ('=', Expr::Boolean(x), Expr::Boolean(y)) => Expr::Boolean(x == y),
+        ('=', Expr::String(x), Expr::String(y)) => Expr::Boolean(x == y),
+        ('|', Expr::Boolean(x), Expr::Boolean(y)) => Expr::Boolean(*x || *y),
+        ('&', Expr::Boolean(x), Expr::Boolean(y)) => Expr::Boolean(*x && *y),
+        ('.', Expr::String(x), Expr::String(y)) => Expr::String(x.clone() + y),
+        ('T' | 'D', Expr::Integer(x), Expr::String(s)) => {
+            let chars: Vec<char> = s.chars().collect();
+            let n = usize::try_from(*x).ok().filter(|n| *n <= chars.len())?;
+            let part = if op == 'T' { &chars[..n] } else { &chars[n..] };
+            Expr::String(part.iter().collect())
+        }
+        _ => return None,
+    };
+    Some(res)
+}
+
+// Free occurrences of `x`
+pub(crate) fn count_uses(expr: &Expr, x: i64) -> usize {
+    match expr {
+        Expr::Var(y) => usize::from(*y == x),
+        Expr::Lambda(y, _) if *y == x => 0,
+        _ => children(expr)
+            .iter()
+            .map(|c| count_uses(&c.borrow(), x))
+            .sum(),
+    }
+}
+
+// Replaces free occurrences of `x`. Does not rename binders, callers make
+// sure `value` cannot be captured, for example because it is closed.
+pub(crate) fn substitute(expr_ptr: &ExprPtr, x: i64, value: &ExprPtr) -> ExprPtr {
+    let e = expr_ptr.borrow();
+    let res = match &*e {
+        Expr::Var(y) if *y == x => return value.clone(),
+        Expr::Lambda(y, _) if *y == x => return expr_ptr.clone(),
+        Expr::Lambda(y, body) => Expr::Lambda(*y, substitute(body, x, value)),
+        Expr::Unary(op, a) => Expr::Unary(*op, substitute(a, x, value)),
+        Expr::Binary(op, a, b) => {
+            Expr::Binary(*op, substitute(a, x, value), substitute(b, x, value))
+        }
+        Expr::If(a, b, c) => Expr::If(
+            substitute(a, x, value),
+            substitute(b, x, value),
+            substitute(c, x, value),
+        ),
+        _ => return expr_ptr.clone(),
+    };
+    as_ptr(res)
+}
+
+fn is_int(expr: &Expr, value: i64) -> bool {
+    matches!(expr, Expr::Integer(x) if *x == value)
+}
+
+fn is_bool(expr: &Expr, value: bool) -> bool {
+    matches!(expr, Expr::Boolean(x) if *x == value)
+}
+
+fn is_empty_str(expr: &Expr) -> bool {
+    matches!(expr, Expr::String(s) if s.is_empty())
+}
+
+struct Simplifier<'a> {
+    options: &'a Options,
+}
+
+impl Simplifier<'_> {
+    // `expected` is the type the surrounding code requires of the result,
+    // it fails on anything else just like the identity being removed would
+    fn simplify(&self, expr_ptr: &ExprPtr, expected: Option<Type>) -> ExprPtr {
+        let e = expr_ptr.borrow().clone();
+        match &e {
+            Expr::Unary(op, a) => {
+                let op = *op;
+                let expected_a = match op {
+                    '-' | '$' => Type::Int,
+                    '!' => Type::Bool,
+                    _ => Type::Str,
+                };
+                let a = self.simplify(a, Some(expected_a));
+                self.unary(op, a, expected)
+            }
+            Expr::Binary('$', f, arg) => {
+                let f = self.simplify(f, None);
+                let arg = self.simplify(arg, None);
+                self.apply(f, arg, expected)
+            }
+            Expr::Binary(op, a, b) => {
+                let op = *op;
+                let expected_a = match op {
+                    'T' | 'D' => Some(Type::Int),
+                    _ => operand_type(op),
+                };
+                let expected_b = match op {
+                    'T' | 'D' => Some(Type::Str),
+                    _ => operand_type(op),
+                };
+                let a = self.simplify(a, expected_a);
+                let b = self.simplify(b, expected_b);
+                self.binary(op, a, b, expected)
+            }
+            Expr::If(cond, then, otherwise) => {
+                let cond = self.simplify(cond, Some(Type::Bool));
+                let c = cond.borrow().clone();
+                match c {
+                    Expr::Boolean(true) => return self.simplify(then, expected),
+                    Expr::Boolean(false) => return self.simplify(otherwise, expected),
+                    _ => (),
+                }
+                let then = self.simplify(then, expected);
+                let otherwise = self.simplify(otherwise, expected);
+                // `!c` fails exactly when `c` does
+                if let Expr::Unary('!', inner) = &c {
+                    return as_ptr(Expr::If(inner.clone(), otherwise, then));
+                }
+                if self.options.assume_total && *then.borrow() == *otherwise.borrow() {
+                    return then;
+                }
+                as_ptr(Expr::If(cond, then, otherwise))
+            }
+            Expr::Lambda(x, body) => as_ptr(Expr::Lambda(*x, self.simplify(body, None))),
+            _ => expr_ptr.clone(),
+        }
+    }
+
+    // Whether `expr` can stand in for an operation that checks it has type `t`
+    fn has_type(&self, expr: &ExprPtr, t: Type, expected: Option<Type>) -> bool {
+        expected == Some(t) || type_of(&expr.borrow()) == Some(t)
+    }
+
+    fn unary(&self, op: char, a: ExprPtr, expected: Option<Type>) -> ExprPtr {
+        if let Some(res) = fold_unary(op, &a.borrow()) {
+            return as_ptr(res);
+        }
+        // `!!x` and `--x`
+        if let Expr::Unary(inner_op, x) = &*a.borrow() {
+            let t = if op == '!' { Type::Bool } else { Type::Int };
+            if *inner_op == op && (op == '!' || op == '-') && self.has_type(x, t, expected) {
+                return x.clone();
+            }
+        }
+        as_ptr(Expr::Unary(op, a))
+    }
+
+    fn binary(&self, op: char, a: ExprPtr, b: ExprPtr, expected: Option<Type>) -> ExprPtr {
+        if let Some(res) = fold_binary(op, &a.borrow(), &b.borrow()) {
+            return as_ptr(res);
+        }
+        let total = self.options.assume_total;
+        let (ea, eb) = (a.borrow().clone(), b.borrow().clone());
+        let int = |x: &ExprPtr| self.has_type(x, Type::Int, expected);
+        let boolean = |x: &ExprPtr| self.has_type(x, Type::Bool, expected);
+        let string = |x: &ExprPtr| self.has_type(x, Type::Str, expected);
+        match op {
+            '+' if is_int(&ea, 0) && int(&b) => b,
+            '+' | '-' if is_int(&eb, 0) && int(&a) => a,
+            '-' if is_int(&ea, 0) => self.unary('-', b, expected),
+            '*' | '/' if is_int(&eb, 1) && int(&a) => a,
+            '*' if is_int(&ea, 1) && int(&b) => b,
+            '*' if total && (is_int(&ea, 0) || is_int(&eb, 0)) => as_ptr(Expr::Integer(0)),
+            '&' if is_bool(&ea, true) && boolean(&b) => b,
+            '&' if is_bool(&eb, true) && boolean(&a) => a,
+            '&' if total && (is_bool(&ea, false) || is_bool(&eb, false)) => {
+                as_ptr(Expr::Boolean(false))
+            }
+            '|' if is_bool(&ea, false) && boolean(&b) => b,
+            '|' if is_bool(&eb, false) && boolean(&a) => a,
+            '|' if total && (is_bool(&ea, true) || is_bool(&eb, true)) => {
+                as_ptr(Expr::Boolean(true))
+            }
+            '.' if is_empty_str(&ea) && string(&b) => b,
+            '.' if is_empty_str(&eb) && string(&a) => a,
+            'D' if is_int(&ea, 0) && string(&b) => b,
+            _ => as_ptr(Expr::Binary(op, a, b)),
+        }
+    }
+
+    fn apply(&self, f: ExprPtr, arg: ExprPtr, expected: Option<Type>) -> ExprPtr {
+        let fe = f.borrow().clone();
+        if let Expr::Lambda(x, body) = &fe {
+            let literal = matches!(
+                *arg.borrow(),
+                Expr::Boolean(_) | Expr::Integer(_) | Expr::String(_)
+            );
+            let small = literal || count_uses(&body.borrow(), *x) <= 1;
+            if small && is_closed(&arg.borrow()) {
+                return self.simplify(&substitute(body, *x, &arg), expected);
+            }
+        }
+        let res = as_ptr(Expr::Binary('$', f, arg));
+        if self.options.eval_steps > 0 && is_closed(&res.borrow()) {
+            if let Some(value) = try_eval(res.clone(), self.options.eval_steps) {
+                return as_ptr(value);
+            }
+        }
+        res
+    }
+}
+
+// `expr` with the decided conditions and the known integers from `facts`
+// put in place
+fn apply_facts(expr_ptr: &ExprPtr, facts: &absint::Facts) -> ExprPtr {
+    let e = expr_ptr.borrow().clone();
+    if let Expr::If(cond, then, otherwise) = &e {
+        match facts.get(cond).and_then(|v| v.decided()) {
+            Some(true) => return apply_facts(then, facts),
+            Some(false) => return apply_facts(otherwise, facts),
+            None => {}
+        }
+    }
+    let known = facts
+        .get(expr_ptr)
+        .and_then(|v| v.interval())
+        .and_then(|i| i.singleton());
+    if let Some(x) = known {
+        return as_ptr(Expr::Integer(x));
+    }
+    let res = match &e {
+        Expr::Unary(op, a) => Expr::Unary(*op, apply_facts(a, facts)),
+        Expr::Binary(op, a, b) => Expr::Binary(*op, apply_facts(a, facts), apply_facts(b, facts)),
+        Expr::If(a, b, c) => Expr::If(
+            apply_facts(a, facts),
+            apply_facts(b, facts),
+            apply_facts(c, facts),
+        ),
+        Expr::Lambda(x, body) => Expr::Lambda(*x, apply_facts(body, facts)),
+        _ => return expr_ptr.clone(),
+    };
+    as_ptr(res)
+}
+
+pub fn partial_eval(expr: &Expr, options: &Options) -> ExprPtr {
+    let mut expr_ptr = as_ptr(expr.clone());
+    if options.use_ranges && options.assume_total {
+        let facts = absint::analyze(&expr_ptr);
+        expr_ptr = apply_facts(&expr_ptr, &facts);
+    }
+    Simplifier { options }.simplify(&expr_ptr, None)
+}
+
+// Partial evaluation with the default, semantics-preserving options
+pub fn simplify(expr: &Expr) -> ExprPtr {
+    partial_eval(expr, &Options::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simplified(program: &str) -> String {
+        let expr = parse_into_ast(program.to_string());
+        let res = simplify(&expr.borrow());
+        let text = serialize_expr(&res.borrow());
+        text
+    }
+
+    #[test]
+    fn test_folds_constants() {
+        assert_eq!(simplified("B+ I# B* I$ I%"), "I/");
+        assert_eq!(simplified("B. S4% S34"), "S4%34");
+        assert_eq!(simplified("U$ I4%34"), "S4%34");
+        assert_eq!(simplified("? B> I# I$ S! S\""), "S\"");
+        // Errors stay where they are
+        assert_eq!(simplified("B/ I# I!"), "B/ I# I!");
+        assert_eq!(simplified("BT I$ S!"), "BT I$ S!");
+        assert_eq!(simplified("B= I! S!"), "B= I! S!");
+    }
+
+    #[test]
+    fn test_identities_need_types() {
+        // `v!` might be a string
+        assert_eq!(simplified("L! B* v! I\""), "L! B* v! I\"");
+        // ... unless it is used as an integer anyway
+        assert_eq!(simplified("L! B+ I# B* v! I\""), "L! B+ I# v!");
+        assert_eq!(simplified("L! B+ B- v! I# I!"), "L! B- v! I#");
+        assert_eq!(simplified("L! U! U! B< v! I!"), "L! B< v! I!");
+        assert_eq!(simplified("L! ? U! v! I\" I#"), "L! ? v! I# I\"");
+        assert_eq!(simplified("L! B. S v!"), "L! B. S v!");
+    }
+
+    #[test]
+    fn test_absorbing_elements_need_totality() {
+        let program = "L! B* v! I!";
+        assert_eq!(simplified(program), program);
+        let expr = parse_into_ast(program.to_string());
+        let options = Options {
+            assume_total: true,
+            ..Options::default()
+        };
+        let res = partial_eval(&expr.borrow(), &options);
+        assert_eq!(serialize_expr(&res.borrow()), "L! I!");
+    }
+
+    #[test]
+    fn test_specialises_applications() {
+        // Literal arguments are substituted even when used twice
+        assert_eq!(
+            simplified("L! B$ L\" B+ v\" B* v\" v! I$"),
+            "L! B+ I$ B* I$ v!"
+        );
+        // Shadowed variables are left alone
+        assert_eq!(simplified("L! B$ L\" L\" v\" I$"), "L! L\" v\"");
+        // Not a literal and used twice: keep the application
+        let program = "L! B$ L\" B$ v\" v\" L# v#";
+        assert_eq!(simplified(program), program);
+        assert_eq!(simplified("B$ B$ L# L$ v# I( I)"), "I(");
+        // Closed applications are evaluated on request
+        let program = "B$ L# B$ v# B$ v# I! L\" B+ v\" v\"";
+        assert_eq!(simplified(program), program);
+        let expr = parse_into_ast(program.to_string());
+        let options = Options {
+            eval_steps: 100,
+            ..Options::default()
+        };
+        let res = partial_eval(&expr.borrow(), &options);
+        assert_eq!(*res.borrow(), Expr::Integer(0));
+    }
+
+    #[test]
+    fn test_uses_ranges() {
+        let options = Options {
+            assume_total: true,
+            use_ranges: true,
+            ..Options::default()
+        };
+        let ranged = |program: &str| {
+            let expr = parse_into_ast(program.to_string());
+            let res = partial_eval(&expr.borrow(), &options);
+            let text = serialize_expr(&res.borrow());
+            text
+        };
+        // `v!` is at least 1
+        assert_eq!(
+            ranged("L# B$ L! ? B> v! I! B* v! I# I! B+ U# v# I\""),
+            "L# B$ L! B* v! I# B+ U# v# I\""
+        );
+        // An even number modulo 2
+        assert_eq!(ranged("L# B% B* I# U# v# I#"), "L# I!");
+        // Both branches are possible
+        let program = "L# B$ L! ? B> v! I\" v! I! B+ U# v# I\"";
+        assert_eq!(ranged(program), simplified(program));
+    }
+
+    #[test]
+    fn test_deep_calls() {
+        // `f 2 . (x2 = 0 in ... x249 = 0 in f 1)` with
+        // `f x = if x = 1 then "a" else "b"`, the second call is nested too
+        // deep for `absint` to follow
+        let var = |x| as_ptr(Expr::Var(x));
+        let int = |x| as_ptr(Expr::Integer(x));
+        let apply = |f, arg| as_ptr(Expr::Binary('$', f, arg));
+        let f = Expr::If(
+            as_ptr(Expr::Binary('=', var(0), int(1))),
+            as_ptr(Expr::String("a".to_string())),
+            as_ptr(Expr::String("b".to_string())),
+        );
+        let mut deep = apply(var(1), int(1));
+        for x in 2..250 {
+            deep = apply(as_ptr(Expr::Lambda(x, deep)), int(0));
+        }
+        let body = as_ptr(Expr::Binary('.', apply(var(1), int(2)), deep));
+        let program = apply(
+            as_ptr(Expr::Lambda(1, body)),
+            as_ptr(Expr::Lambda(0, as_ptr(f))),
+        );
+        let options = Options {
+            assume_total: true,
+            use_ranges: true,
+            ..Options::default()
+        };
+        let res = partial_eval(&program.borrow(), &options);
+        let expected = Expr::String("ba".to_string());
+        assert_eq!(try_eval(res, 100_000), Some(expected));
+    }
+
+    #[test]
+    fn test_problem_2() {
+        // The multiplication by 0 hides a recursion that never finishes
+        let text = fs::read_to_string("problems/2.txt").unwrap();
+        let expr = parse_into_ast(text);
+        let options = Options {
+            assume_total: true,
+            ..Options::default()
+        };
+        let res = partial_eval(&expr.borrow(), &options);
+        assert_eq!(*res.borrow(), Expr::Integer(2134));
+    }
+
+    #[test]
+    fn test_preserves_semantics() {
+        set_debug(false);
+        let text = fs::read_to_string("tests/conformance.txt").unwrap();
+        let cases = conformance::parse_cases(&text, ".").unwrap();
+        for case in cases.iter() {
+            let Ok(expr) = parser::parse_checked(&case.program) else {
+                continue;
+            };
+            let res = simplify(&expr.borrow());
+            let case = conformance::Case {
+                program: serialize_expr(&res.borrow()),
+                ..case.clone()
+            };
+            assert_eq!(conformance::run_case(&case), Ok(()), "{}", case.name);
+        }
+    }
+}
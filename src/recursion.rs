@@ -0,0 +1,346 @@
+use crate::partial::{count_uses, substitute};
+use crate::scope::{bound_vars, free_vars, to_de_bruijn, DeBruijn};
+use crate::*;
+
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
+
+// Recognises recursion written with a fixed-point combinator,
+//
+//   B$ Y (L f. L x1. ... L xn. body)
+//
+// and pulls the function out as a definition: `f(x1, ..., xn) = body`.
+// The application is replaced by `v<name>` with a fresh id, and recursive
+// calls in `body` refer to the same id, so the definition reads like an
+// ordinary recursive function.
+//
+// Combinators are matched up to alpha-equivalence, either written out at the
+// application or bound once with `B$ (L y. ...) Y` and used through `y`.
+//
+// A definition's body may still use variables bound around the original
+// application, as the helpers in `problems/5.txt` do. Definitions are listed
+// inner first, so a definition only refers to names defined before it.
+
+#[derive(Clone, Debug)]
+pub struct Definition {
+    pub name: i64,
+    pub params: Vec<i64>,
+    pub body: ExprPtr,
+    pub combinator: &'static str,
+}
+
+#[derive(Clone, Debug)]
+pub struct Extracted {
+    pub main: ExprPtr,
+    pub definitions: Vec<Definition>,
+}
+
+static COMBINATORS: Lazy<Vec<(&'static str, DeBruijn)>> = Lazy::new(|| {
+    [
+        // L f. (L x. f (x x)) (L x. f (x x))
+        ("Y", "L! B$ L\" B$ v! B$ v\" v\" L\" B$ v! B$ v\" v\""),
+        // L f. (L x. x x) (L x. f (x x))
+        ("Y", "L! B$ L\" B$ v\" v\" L\" B$ v! B$ v\" v\""),
+        // L f. (L x. f (L v. x x v)) (L x. f (L v. x x v)), for strict evaluation
+        (
+            "Z",
+            "L! B$ L\" B$ v! L# B$ B$ v\" v\" v# L\" B$ v! L# B$ B$ v\" v\" v#",
+        ),
+        // (L x. L y. y (x x y)) (L x. L y. y (x x y))
+        (
+            "Theta",
+            "B$ L! L\" B$ v\" B$ B$ v! v! v\" L! L\" B$ v\" B$ B$ v! v! v\"",
+        ),
+    ]
+    .iter()
+    .map(|(name, program)| {
+        let expr = parse_into_ast(program.to_string());
+        let db = to_de_bruijn(&expr.borrow());
+        (*name, db)
+    })
+    .collect()
+});
+
+// Largest combinator, in nodes, anything bigger is not compared
+const MAX_COMBINATOR_NODES: usize = 24;
+
+fn fits(expr: &Expr, budget: &mut usize) -> bool {
+    if *budget == 0 {
+        return false;
+    }
+    *budget -= 1;
+    children(expr).iter().all(|c| fits(&c.borrow(), budget))
+}
+
+// Name of the fixed-point combinator `expr` is alpha-equivalent to
+pub fn fixpoint_combinator(expr: &Expr) -> Option<&'static str> {
+    let mut budget = MAX_COMBINATOR_NODES;
+    if !fits(expr, &mut budget) {
+        return None;
+    }
+    let db = to_de_bruijn(expr);
+    COMBINATORS
+        .iter()
+        .find(|(_, other)| *other == db)
+        .map(|(name, _)| *name)
+}
+
+struct Extractor {
+    // Enclosing binders, innermost last, with the combinator a let-bound
+    // variable stands for
+    scope: Vec<(i64, Option<&'static str>)>,
+    used: HashSet<i64>,
+    definitions: Vec<Definition>,
+    // Binders around the application of each definition, by index
+    enclosing: Vec<Vec<i64>>,
+}
+
+impl Extractor {
+    fn combinator(&self, f: &Expr) -> Option<&'static str> {
+        if let Expr::Var(x) = f {
+            return self
+                .scope
+                .iter()
+                .rev()
+                .find(|(y, _)| y == x)
+                .and_then(|(_, c)| *c);
+        }
+        fixpoint_combinator(f)
+    }
+
+    fn fresh(&mut self) -> i64 {
+        let id = (0..).find(|id| !self.used.contains(id)).unwrap();
+        self.used.insert(id);
+        id
+    }
+
+    // `nested` is the first definition extracted from inside `f`
+    fn define(&mut self, combinator: &'static str, f: &ExprPtr, nested: usize) -> Option<ExprPtr> {
+        let (self_var, body) = match &*f.borrow() {
+            Expr::Lambda(x, body) => (*x, body.clone()),
+            _ => return None,
+        };
+        let name = self.fresh();
+        let var = as_ptr(Expr::Var(name));
+        // Definitions from inside `f` can call it too, unless a binder
+        // between `f` (bound at `depth` while it was walked) and them takes
+        // the name
+        let depth = self.scope.len();
+        let inside = self.definitions[nested..]
+            .iter_mut()
+            .zip(&self.enclosing[nested..]);
+        for (def, enclosing) in inside {
+            let shadowed = enclosing[depth + 1..].contains(&self_var);
+            if !shadowed && !def.params.contains(&self_var) {
+                def.body = substitute(&def.body, self_var, &var);
+            }
+        }
+        let mut body = substitute(&body, self_var, &var);
+        let mut params = Vec::new();
+        loop {
+            let inner = match &*body.borrow() {
+                Expr::Lambda(x, inner) => {
+                    params.push(*x);
+                    inner.clone()
+                }
+                _ => break,
+            };
+            body = inner;
+        }
+        self.definitions.push(Definition {
+            name,
+            params,
+            body,
+            combinator,
+        });
+        self.enclosing
+            .push(self.scope.iter().map(|(x, _)| *x).collect());
+        Some(as_ptr(Expr::Var(name)))
+    }
+
+    fn walk(&mut self, expr_ptr: &ExprPtr) -> ExprPtr {
+        let e = expr_ptr.borrow().clone();
+        let res = match &e {
+            Expr::Binary('$', f, arg) => {
+                let combinator = self.combinator(&f.borrow());
+                if let Some(c) = combinator {
+                    let nested = self.definitions.len();
+                    let arg = self.walk(arg);
+                    if let Some(res) = self.define(c, &arg, nested) {
+                        return res;
+                    }
+                    return as_ptr(Expr::Binary('$', f.clone(), arg));
+                }
+                let let_bound = fixpoint_combinator(&arg.borrow());
+                if let (Expr::Lambda(y, body), Some(c)) = (&*f.borrow(), let_bound) {
+                    self.scope.push((*y, Some(c)));
+                    let body = self.walk(body);
+                    self.scope.pop();
+                    if count_uses(&body.borrow(), *y) == 0 {
+                        return body;
+                    }
+                    let f = as_ptr(Expr::Lambda(*y, body));
+                    return as_ptr(Expr::Binary('$', f, arg.clone()));
+                }
+                let f = self.walk(f);
+                Expr::Binary('$', f, self.walk(arg))
+            }
+            Expr::Lambda(x, body) => {
+                self.scope.push((*x, None));
+                let body = self.walk(body);
+                self.scope.pop();
+                Expr::Lambda(*x, body)
+            }
+            Expr::Unary(op, a) => Expr::Unary(*op, self.walk(a)),
+            Expr::Binary(op, a, b) => {
+                let a = self.walk(a);
+                Expr::Binary(*op, a, self.walk(b))
+            }
+            Expr::If(a, b, c) => {
+                let a = self.walk(a);
+                let b = self.walk(b);
+                Expr::If(a, b, self.walk(c))
+            }
+            _ => return expr_ptr.clone(),
+        };
+        as_ptr(res)
+    }
+}
+
+pub fn extract_recursion(expr: &Expr) -> Extracted {
+    let mut used = free_vars(expr);
+    used.extend(bound_vars(expr));
+    let mut extractor = Extractor {
+        scope: Vec::new(),
+        used,
+        definitions: Vec::new(),
+        enclosing: Vec::new(),
+    };
+    let main = extractor.walk(&as_ptr(expr.clone()));
+    Extracted {
+        main,
+        definitions: extractor.definitions,
+    }
+}
+
+// Names of the definitions that only use their parameters and earlier ones
+// of the same kind, so they can be written at the top level
+pub fn top_level(definitions: &[Definition]) -> HashSet<i64> {
+    let mut res = HashSet::new();
+    for def in definitions.iter() {
+        let closed = free_vars(&def.body.borrow())
+            .iter()
+            .all(|x| *x == def.name || def.params.contains(x) || res.contains(x));
+        if closed {
+            res.insert(def.name);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(program: &str) -> Extracted {
+        let expr = parse_into_ast(program.to_string());
+        let res = extract_recursion(&expr.borrow());
+        res
+    }
+
+    #[test]
+    fn test_recognises_combinators() {
+        let is_fix = |s: &str| fixpoint_combinator(&parse_into_ast(s.to_string()).borrow());
+        // The spelling used by the problems
+        assert_eq!(
+            is_fix("L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v#"),
+            Some("Y")
+        );
+        assert_eq!(
+            is_fix("L~ B$ L} B$ v~ B$ v} v} L| B$ v~ B$ v| v|"),
+            Some("Y")
+        );
+        assert_eq!(is_fix("L\" B$ L# B$ v\" B$ v# v# L# B$ v# B$ v# v#"), None);
+        assert_eq!(is_fix("L\" v\""), None);
+    }
+
+    #[test]
+    fn test_problem_4() {
+        let text = fs::read_to_string("problems/4.txt").unwrap();
+        let res = extract(text.trim());
+        assert_eq!(serialize_expr(&res.main.borrow()), "B$ v! II");
+        assert_eq!(res.definitions.len(), 1);
+        let def = &res.definitions[0];
+        assert_eq!(def.name, 0);
+        assert_eq!(def.params, vec![4]);
+        assert_eq!(def.combinator, "Y");
+        assert_eq!(
+            serialize_expr(&def.body.borrow()),
+            "? B< v% I# I\" B+ B$ v! B- v% I\" B$ v! B- v% I#"
+        );
+    }
+
+    #[test]
+    fn test_problem_5() {
+        let text = fs::read_to_string("problems/5.txt").unwrap();
+        let res = extract(text.trim());
+        let names: Vec<i64> = res.definitions.iter().map(|d| d.name).collect();
+        assert_eq!(names, vec![0, 8, 9]);
+        for def in res.definitions.iter() {
+            assert_eq!(def.params, vec![4]);
+            assert!(count_uses(&def.body.borrow(), def.name) > 0);
+        }
+        // The first helper calls the other two through `v'` and `v(`, the
+        // last one uses the `v&` of the lambda around it
+        let free = |def: &Definition| {
+            let mut res: Vec<i64> = free_vars(&def.body.borrow()).into_iter().collect();
+            res.sort();
+            res
+        };
+        assert_eq!(free(&res.definitions[0]), vec![0, 4, 6, 7]);
+        assert_eq!(free(&res.definitions[2]), vec![4, 5, 9]);
+    }
+
+    #[test]
+    fn test_nested_definitions() {
+        // The inner helper is defined inside the outer function, which it
+        // calls as `v$`
+        let text = fs::read_to_string("problems/12.txt").unwrap();
+        let res = extract(text.trim());
+        let names: Vec<i64> = res.definitions.iter().map(|d| d.name).collect();
+        assert_eq!(names, vec![0, 8]);
+        let inner = &res.definitions[0];
+        assert_eq!(inner.params, vec![6, 7]);
+        assert!(count_uses(&inner.body.borrow(), 3) == 0);
+        assert!(count_uses(&inner.body.borrow(), 8) > 0);
+    }
+
+    #[test]
+    fn test_shadowed_self() {
+        // Y (L s. L x. (L s. Y (L g. L y. s)) 0), the inner `s` is not the
+        // outer function
+        let y = "L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v#";
+        let program = format!("B$ {} L& L' B$ L& B$ {} L( L) v& I!", y, y);
+        let res = extract(&program);
+        assert_eq!(res.definitions.len(), 2);
+        let inner = &res.definitions[0];
+        assert_eq!(inner.params, vec![8]);
+        assert_eq!(serialize_expr(&inner.body.borrow()), "v&");
+    }
+
+    #[test]
+    fn test_let_bound_combinator() {
+        // B$ (L y. y g) Y
+        let res =
+            extract("B$ L) B$ v) L$ L% B$ v$ v% L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v#");
+        assert_eq!(serialize_expr(&res.main.borrow()), "v!");
+        assert_eq!(res.definitions[0].params, vec![4]);
+        assert_eq!(
+            serialize_expr(&res.definitions[0].body.borrow()),
+            "B$ v! v%"
+        );
+        // Shadowed, so not the combinator any more
+        let program = "B$ L) L) B$ v) L$ v$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v#";
+        assert!(extract(program).definitions.is_empty());
+    }
+}
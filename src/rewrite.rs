@@ -0,0 +1,729 @@
+use crate::parser::{error_at, ParseError};
+use crate::sexp::{check_arity, form, read_sexps, Sexp, BINARY_NAMES, UNARY_NAMES};
+use crate::*;
+
+use num_bigint::BigInt;
+
+use std::collections::HashSet;
+
+// Pattern-based term rewriting. Rules are written in the S-expression syntax
+// of `sexp`, with metavariables:
+//
+//   (rule double (+ ?x ?x) (* ?x 2))
+//   (rule drop-zero (drop 0 ?s:str) ?s)
+//   (rule small-mul (* ?a:int ?b:int) (* ?b ?a) (when (< ?b ?a)))
+//
+// `?x` matches any subexpression, `?x:<kind>` only a literal of that kind
+// (`int`, `bool`, `str`), a variable (`var`), a lambda (`lam`) or an
+// expression without free variables (`closed`). A metavariable used twice
+// must match equal subexpressions. `(lam ?v body)` and `(var ?v)` bind and
+// match variable ids. The optional `when` condition is instantiated like the
+// right-hand side and run with the evaluator; the rule fires only if it
+// evaluates to `true`. Conditions and rewrites that need more than that can
+// be written in Rust with `Rule::when` and `Rule::computed`.
+//
+// Right-hand sides are substituted literally, so a rule that introduces a
+// lambda can capture variables of the code it is moved into.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Any,
+    Int,
+    Bool,
+    Str,
+    Var,
+    Lambda,
+    Closed,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Id {
+    Fixed(i64),
+    Meta(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    Meta(String, Kind),
+    Boolean(bool),
+    Integer(i64),
+    String(String),
+    Var(Id),
+    Lambda(Id, Box<Pattern>),
+    Unary(char, Box<Pattern>),
+    Binary(char, Box<Pattern>, Box<Pattern>),
+    If(Box<Pattern>, Box<Pattern>, Box<Pattern>),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Bindings {
+    pub exprs: HashMap<String, ExprPtr>,
+    pub ids: HashMap<String, i64>,
+}
+
+type Condition = Box<dyn Fn(&Bindings) -> bool>;
+type Compute = Box<dyn Fn(&Bindings) -> Option<Expr>>;
+
+enum Rhs {
+    Template(Pattern),
+    Computed(Compute),
+}
+
+pub struct Rule {
+    pub name: String,
+    lhs: Pattern,
+    rhs: Rhs,
+    conditions: Vec<Condition>,
+}
+
+fn parse_kind(source: &str, sexp: &Sexp, kind: &str) -> Result<Kind, ParseError> {
+    let res = match kind {
+        "int" => Kind::Int,
+        "bool" => Kind::Bool,
+        "str" => Kind::Str,
+        "var" => Kind::Var,
+        "lam" => Kind::Lambda,
+        "closed" => Kind::Closed,
+        _ => {
+            let message = format!("unknown kind '{}'", kind);
+            return Err(error_at(source, sexp.span(), message));
+        }
+    };
+    Ok(res)
+}
+
+fn parse_id(source: &str, sexp: &Sexp) -> Result<Id, ParseError> {
+    if let Sexp::Atom(s, span) = sexp {
+        if let Some(name) = s.strip_prefix('?') {
+            return Ok(Id::Meta(name.to_string()));
+        }
+        if let Ok(x) = s.parse::<i64>() {
+            if x >= 0 {
+                return Ok(Id::Fixed(x));
+            }
+        }
+        return Err(error_at(source, *span, format!("bad variable '{}'", s)));
+    }
+    Err(error_at(
+        source,
+        sexp.span(),
+        "expected a variable".to_string(),
+    ))
+}
+
+pub fn parse_pattern(source: &str, sexp: &Sexp) -> Result<Pattern, ParseError> {
+    let sub = |s: &Sexp| parse_pattern(source, s).map(Box::new);
+    let res = match sexp {
+        Sexp::Str(s, _) => Pattern::String(s.clone()),
+        Sexp::Atom(s, span) => {
+            if let Some(meta) = s.strip_prefix('?') {
+                let (name, kind) = match meta.split_once(':') {
+                    Some((name, kind)) => (name, parse_kind(source, sexp, kind)?),
+                    None => (meta, Kind::Any),
+                };
+                return Ok(Pattern::Meta(name.to_string(), kind));
+            }
+            match s.as_str() {
+                "true" => Pattern::Boolean(true),
+                "false" => Pattern::Boolean(false),
+                _ => match s.parse::<i64>() {
+                    Ok(x) => Pattern::Integer(x),
+                    Err(_) => return Err(error_at(source, *span, format!("unexpected '{}'", s))),
+                },
+            }
+        }
+        Sexp::List(items, _) => {
+            let (name, args) = form(source, sexp, items)?;
+            let unary = UNARY_NAMES.iter().find(|(_, n)| *n == name);
+            let binary = BINARY_NAMES.iter().find(|(_, n)| *n == name);
+            match (name, unary, binary) {
+                ("var", _, _) => {
+                    check_arity(source, sexp, name, args, 1)?;
+                    Pattern::Var(parse_id(source, &args[0])?)
+                }
+                ("lam", _, _) => {
+                    check_arity(source, sexp, name, args, 2)?;
+                    Pattern::Lambda(parse_id(source, &args[0])?, sub(&args[1])?)
+                }
+                ("if", _, _) => {
+                    check_arity(source, sexp, name, args, 3)?;
+                    Pattern::If(sub(&args[0])?, sub(&args[1])?, sub(&args[2])?)
+                }
+                (_, Some((op, _)), _) => {
+                    check_arity(source, sexp, name, args, 1)?;
+                    Pattern::Unary(*op, sub(&args[0])?)
+                }
+                (_, _, Some((op, _))) => {
+                    check_arity(source, sexp, name, args, 2)?;
+                    Pattern::Binary(*op, sub(&args[0])?, sub(&args[1])?)
+                }
+                _ => {
+                    let message = format!("unknown form '{}'", name);
+                    return Err(error_at(source, items[0].span(), message));
+                }
+            }
+        }
+    };
+    Ok(res)
+}
+
+fn read_one_sexp(text: &str) -> Sexp {
+    let sexps = read_sexps(text).unwrap_or_else(|e| panic!("bad pattern: {}", e));
+    assert_eq!(sexps.len(), 1, "expected one pattern in '{}'", text);
+    sexps.into_iter().next().unwrap()
+}
+
+fn parse_one_pattern(text: &str) -> Pattern {
+    parse_pattern(text, &read_one_sexp(text)).unwrap_or_else(|e| panic!("bad pattern: {}", e))
+}
+
+fn kind_matches(kind: Kind, expr: &Expr) -> bool {
+    match kind {
+        Kind::Any => true,
+        Kind::Int => matches!(expr, Expr::Integer(_) | Expr::BigInteger(_)),
+        Kind::Bool => matches!(expr, Expr::Boolean(_)),
+        Kind::Str => matches!(expr, Expr::String(_)),
+        Kind::Var => matches!(expr, Expr::Var(_)),
+        Kind::Lambda => matches!(expr, Expr::Lambda(_, _)),
+        Kind::Closed => is_closed(expr),
+    }
+}
+
+fn match_id(id: &Id, x: i64, b: &mut Bindings) -> bool {
+    match id {
+        Id::Fixed(y) => *y == x,
+        Id::Meta(name) => match b.ids.get(name) {
+            Some(y) => *y == x,
+            None => {
+                b.ids.insert(name.clone(), x);
+                true
+            }
+        },
+    }
+}
+
+pub fn match_pattern(pattern: &Pattern, expr_ptr: &ExprPtr, b: &mut Bindings) -> bool {
+    let e = expr_ptr.borrow();
+    match (pattern, &*e) {
+        (Pattern::Meta(name, kind), _) => {
+            if !kind_matches(*kind, &e) {
+                return false;
+            }
+            match b.exprs.get(name) {
+                Some(bound) => *bound.borrow() == *e,
+                None => {
+                    b.exprs.insert(name.clone(), expr_ptr.clone());
+                    true
+                }
+            }
+        }
+        (Pattern::Boolean(x), Expr::Boolean(y)) => x == y,
+        (Pattern::Integer(x), Expr::Integer(y)) => x == y,
+        (Pattern::Integer(x), Expr::BigInteger(y)) => BigInt::from(*x) == *y,
+        (Pattern::String(x), Expr::String(y)) => x == y,
+        (Pattern::Var(id), Expr::Var(x)) => match_id(id, *x, b),
+        (Pattern::Lambda(id, p), Expr::Lambda(x, body)) => {
+            match_id(id, *x, b) && match_pattern(p, body, b)
+        }
+        (Pattern::Unary(op, p), Expr::Unary(op2, a)) => op == op2 && match_pattern(p, a, b),
+        (Pattern::Binary(op, p, q), Expr::Binary(op2, x, y)) => {
+            op == op2 && match_pattern(p, x, b) && match_pattern(q, y, b)
+        }
+        (Pattern::If(p, q, r), Expr::If(x, y, z)) => {
+            match_pattern(p, x, b) && match_pattern(q, y, b) && match_pattern(r, z, b)
+        }
+        _ => false,
+    }
+}
+
+fn instantiate_id(id: &Id, b: &Bindings) -> Option<i64> {
+    match id {
+        Id::Fixed(x) => Some(*x),
+        Id::Meta(name) => b.ids.get(name).copied(),
+    }
+}
+
+// None if the template uses a metavariable the pattern did not bind
+pub fn instantiate(template: &Pattern, b: &Bindings) -> Option<ExprPtr> {
+    let sub = |p: &Pattern| instantiate(p, b);
+    let res = match template {
+        Pattern::Meta(name, _) => return b.exprs.get(name).cloned(),
+        Pattern::Boolean(x) => Expr::Boolean(*x),
+        Pattern::Integer(x) => Expr::Integer(*x),
+        Pattern::String(x) => Expr::String(x.clone()),
+        Pattern::Var(id) => Expr::Var(instantiate_id(id, b)?),
+        Pattern::Lambda(id, p) => Expr::Lambda(instantiate_id(id, b)?, sub(p)?),
+        Pattern::Unary(op, p) => Expr::Unary(*op, sub(p)?),
+        Pattern::Binary(op, p, q) => Expr::Binary(*op, sub(p)?, sub(q)?),
+        Pattern::If(p, q, r) => Expr::If(sub(p)?, sub(q)?, sub(r)?),
+    };
+    Some(as_ptr(res))
+}
+
+// Steps a `when` condition may take
+const CONDITION_STEPS: usize = 1000;
+
+fn eval_condition(cond: &Pattern, b: &Bindings) -> bool {
+    let Some(expr) = instantiate(cond, b) else {
+        return false;
+    };
+    matches!(try_eval(expr, CONDITION_STEPS), Some(Expr::Boolean(true)))
+}
+
+impl Rule {
+    // Panics on malformed patterns, for rules written in code
+    pub fn new(name: &str, lhs: &str, rhs: &str) -> Rule {
+        let lhs = parse_one_pattern(lhs);
+        let template = parse_one_pattern(rhs);
+        check_bound(rhs, &read_one_sexp(rhs), &template, &lhs)
+            .unwrap_or_else(|e| panic!("bad rule {}: {}", name, e));
+        Rule {
+            name: name.to_string(),
+            lhs,
+            rhs: Rhs::Template(template),
+            conditions: Vec::new(),
+        }
+    }
+
+    // Rule whose replacement is built in Rust, `None` means no rewrite
+    pub fn computed(
+        name: &str,
+        lhs: &str,
+        rhs: impl Fn(&Bindings) -> Option<Expr> + 'static,
+    ) -> Rule {
+        Rule {
+            name: name.to_string(),
+            lhs: parse_one_pattern(lhs),
+            rhs: Rhs::Computed(Box::new(rhs)),
+            conditions: Vec::new(),
+        }
+    }
+
+    // Adds a side condition, all of them must hold
+    pub fn when(mut self, cond: impl Fn(&Bindings) -> bool + 'static) -> Rule {
+        self.conditions.push(Box::new(cond));
+        self
+    }
+
+    pub fn apply(&self, expr_ptr: &ExprPtr) -> Option<ExprPtr> {
+        let mut b = Bindings::default();
+        if !match_pattern(&self.lhs, expr_ptr, &mut b) {
+            return None;
+        }
+        if !self.conditions.iter().all(|cond| cond(&b)) {
+            return None;
+        }
+        match &self.rhs {
+            Rhs::Template(template) => instantiate(template, &b),
+            Rhs::Computed(f) => f(&b).map(as_ptr),
+        }
+    }
+}
+
+// Collects the metavariables in `pattern`, expressions and variable ids apart
+fn metas(pattern: &Pattern, exprs: &mut HashSet<String>, ids: &mut HashSet<String>) {
+    let mut id = |id: &Id| {
+        if let Id::Meta(name) = id {
+            ids.insert(name.clone());
+        }
+    };
+    match pattern {
+        Pattern::Meta(name, _) => {
+            exprs.insert(name.clone());
+        }
+        Pattern::Boolean(_) | Pattern::Integer(_) | Pattern::String(_) => {}
+        Pattern::Var(x) => id(x),
+        Pattern::Lambda(x, p) => {
+            id(x);
+            metas(p, exprs, ids);
+        }
+        Pattern::Unary(_, p) => metas(p, exprs, ids),
+        Pattern::Binary(_, p, q) => {
+            metas(p, exprs, ids);
+            metas(q, exprs, ids);
+        }
+        Pattern::If(p, q, r) => {
+            metas(p, exprs, ids);
+            metas(q, exprs, ids);
+            metas(r, exprs, ids);
+        }
+    }
+}
+
+// Fails on the first metavariable of `template` that `lhs` does not bind,
+// `instantiate` could never build anything for it
+fn check_bound(
+    source: &str,
+    sexp: &Sexp,
+    template: &Pattern,
+    lhs: &Pattern,
+) -> Result<(), ParseError> {
+    let (mut exprs, mut ids) = (HashSet::new(), HashSet::new());
+    metas(lhs, &mut exprs, &mut ids);
+    let (mut used_exprs, mut used_ids) = (HashSet::new(), HashSet::new());
+    metas(template, &mut used_exprs, &mut used_ids);
+    let unbound = used_exprs
+        .difference(&exprs)
+        .chain(used_ids.difference(&ids))
+        .min();
+    match unbound {
+        Some(name) => {
+            let message = format!("'?{}' is not bound by the pattern", name);
+            Err(error_at(source, sexp.span(), message))
+        }
+        None => Ok(()),
+    }
+}
+
+// `(rule <name> <lhs> <rhs> [(when <condition>)])`, one per form
+pub fn parse_rules(source: &str) -> Result<Vec<Rule>, ParseError> {
+    let mut res = Vec::new();
+    for sexp in read_sexps(source)? {
+        let Sexp::List(items, _) = &sexp else {
+            return Err(error_at(source, sexp.span(), "expected a rule".to_string()));
+        };
+        let (head, args) = form(source, &sexp, items)?;
+        if head != "rule" {
+            let message = format!("expected 'rule', got '{}'", head);
+            return Err(error_at(source, items[0].span(), message));
+        }
+        if args.len() != 3 && args.len() != 4 {
+            let message = "a rule has a name, a pattern, a replacement and maybe a condition";
+            return Err(error_at(source, sexp.span(), message.to_string()));
+        }
+        let Sexp::Atom(name, _) = &args[0] else {
+            return Err(error_at(
+                source,
+                args[0].span(),
+                "expected a rule name".to_string(),
+            ));
+        };
+        let lhs = parse_pattern(source, &args[1])?;
+        let rhs = parse_pattern(source, &args[2])?;
+        check_bound(source, &args[2], &rhs, &lhs)?;
+        let mut rule = Rule {
+            name: name.clone(),
+            lhs,
+            rhs: Rhs::Template(rhs),
+            conditions: Vec::new(),
+        };
+        if let Some(when) = args.get(3) {
+            let Sexp::List(items, _) = when else {
+                return Err(error_at(
+                    source,
+                    when.span(),
+                    "expected (when ...)".to_string(),
+                ));
+            };
+            let (head, cond) = form(source, when, items)?;
+            if head != "when" {
+                return Err(error_at(
+                    source,
+                    when.span(),
+                    "expected (when ...)".to_string(),
+                ));
+            }
+            check_arity(source, when, head, cond, 1)?;
+            let cond_sexp = &cond[0];
+            let cond = parse_pattern(source, cond_sexp)?;
+            check_bound(source, cond_sexp, &cond, &rule.lhs)?;
+            rule = rule.when(move |b| eval_condition(&cond, b));
+        }
+        res.push(rule);
+    }
+    Ok(res)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    // Outermost redexes first
+    TopDown,
+    // Innermost redexes first
+    BottomUp,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    pub order: Order,
+    // Stops rules that never converge, like `(+ ?a ?b) => (+ ?b ?a)`
+    pub max_rewrites: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            order: Order::TopDown,
+            max_rewrites: 10_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fired {
+    pub rule: String,
+    // Child indices from the root to the rewritten node, at the time it fired
+    pub path: Vec<usize>,
+    pub before: String,
+    pub after: String,
+}
+
+pub struct RewriteResult {
+    pub expr: ExprPtr,
+    pub trace: Vec<Fired>,
+    // False if `max_rewrites` ran out first
+    pub fixpoint: bool,
+}
+
+struct Rewriter<'a> {
+    rules: &'a [Rule],
+    options: Options,
+    trace: Vec<Fired>,
+}
+
+impl Rewriter<'_> {
+    fn out_of_budget(&self) -> bool {
+        self.trace.len() >= self.options.max_rewrites
+    }
+
+    // Applies rules at the node itself until none matches
+    fn at_node(&mut self, mut expr_ptr: ExprPtr, path: &[usize]) -> ExprPtr {
+        'outer: while !self.out_of_budget() {
+            for rule in self.rules {
+                if let Some(res) = rule.apply(&expr_ptr) {
+                    self.trace.push(Fired {
+                        rule: rule.name.clone(),
+                        path: path.to_vec(),
+                        before: sexp::to_sexp(&expr_ptr.borrow()),
+                        after: sexp::to_sexp(&res.borrow()),
+                    });
+                    expr_ptr = res;
+                    continue 'outer;
+                }
+            }
+            break;
+        }
+        expr_ptr
+    }
+
+    fn in_children(&mut self, expr_ptr: ExprPtr, path: &mut Vec<usize>) -> ExprPtr {
+        let e = expr_ptr.borrow().clone();
+        let mut visit = |idx: usize, child: &ExprPtr| {
+            path.push(idx);
+            let res = self.pass(child.clone(), path);
+            path.pop();
+            res
+        };
+        let res = match &e {
+            Expr::Unary(op, a) => Expr::Unary(*op, visit(0, a)),
+            Expr::Lambda(x, a) => Expr::Lambda(*x, visit(0, a)),
+            Expr::Binary(op, a, b) => {
+                let a = visit(0, a);
+                Expr::Binary(*op, a, visit(1, b))
+            }
+            Expr::If(a, b, c) => {
+                let a = visit(0, a);
+                let b = visit(1, b);
+                Expr::If(a, b, visit(2, c))
+            }
+            _ => return expr_ptr,
+        };
+        as_ptr(res)
+    }
+
+    fn pass(&mut self, expr_ptr: ExprPtr, path: &mut Vec<usize>) -> ExprPtr {
+        match self.options.order {
+            Order::TopDown => {
+                let res = self.at_node(expr_ptr, path);
+                self.in_children(res, path)
+            }
+            Order::BottomUp => {
+                let res = self.in_children(expr_ptr, path);
+                self.at_node(res, path)
+            }
+        }
+    }
+}
+
+// Applies `rules` until none matches anywhere, or `max_rewrites` is reached.
+// Rules are tried in order, the first match wins.
+pub fn rewrite(expr_ptr: &ExprPtr, rules: &[Rule], options: &Options) -> RewriteResult {
+    let mut rewriter = Rewriter {
+        rules,
+        options: *options,
+        trace: Vec::new(),
+    };
+    let mut res = expr_ptr.clone();
+    loop {
+        let fired = rewriter.trace.len();
+        res = rewriter.pass(res, &mut Vec::new());
+        if rewriter.trace.len() == fired {
+            return RewriteResult {
+                expr: res,
+                trace: rewriter.trace,
+                fixpoint: true,
+            };
+        }
+        if rewriter.out_of_budget() {
+            return RewriteResult {
+                expr: res,
+                trace: rewriter.trace,
+                fixpoint: false,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(rules: &[Rule], program: &str, order: Order) -> (String, Vec<String>) {
+        let expr = sexp::parse_sexp(program).unwrap();
+        let options = Options {
+            order,
+            ..Options::default()
+        };
+        let res = rewrite(&expr, rules, &options);
+        assert!(res.fixpoint);
+        let fired = res.trace.iter().map(|f| f.rule.clone()).collect();
+        let text = sexp::to_sexp(&res.expr.borrow());
+        (text, fired)
+    }
+
+    #[test]
+    fn test_metavariables() {
+        let rules = parse_rules("(rule double (+ ?x ?x) (* ?x 2))").unwrap();
+        // Structurally equal operands, not only the same pointer
+        let (res, fired) = run(&rules, "(+ (var 1) (var 1))", Order::TopDown);
+        assert_eq!(res, "(* (var 1) 2)");
+        assert_eq!(fired, vec!["double"]);
+        let (res, fired) = run(&rules, "(+ (var 1) (var 2))", Order::TopDown);
+        assert_eq!(res, "(+ (var 1) (var 2))");
+        assert!(fired.is_empty());
+    }
+
+    #[test]
+    fn test_kinds_and_ids() {
+        let rules = parse_rules(
+            "; string literals only
+             (rule drop-zero (drop 0 ?s:str) ?s)
+             (rule eta (lam ?v (app ?f:var (var ?v))) ?f)",
+        )
+        .unwrap();
+        let (res, _) = run(&rules, "(drop 0 \"ab\")", Order::TopDown);
+        assert_eq!(res, "\"ab\"");
+        let (res, _) = run(&rules, "(drop 0 (var 1))", Order::TopDown);
+        assert_eq!(res, "(drop 0 (var 1))");
+        let (res, _) = run(&rules, "(lam 2 (app (var 1) (var 2)))", Order::TopDown);
+        assert_eq!(res, "(var 1)");
+        let (res, _) = run(&rules, "(lam 2 (app (var 1) (var 3)))", Order::TopDown);
+        assert_eq!(res, "(lam 2 (app (var 1) (var 3)))");
+
+        let closed = [Rule::new("closed", "(app ?f:closed ?x)", "?x")];
+        let (res, _) = run(&closed, "(app (lam 1 (var 1)) 5)", Order::TopDown);
+        assert_eq!(res, "5");
+        let (res, _) = run(&closed, "(app (lam 1 (var 2)) 5)", Order::TopDown);
+        assert_eq!(res, "(app (lam 1 (var 2)) 5)");
+
+        // Literals beyond i64 are ints too
+        let double = [Rule::new("double", "(+ ?x:int ?x)", "(* ?x 2)")];
+        let big = "100000000000000000000";
+        let (res, _) = run(&double, &format!("(+ {} {})", big, big), Order::TopDown);
+        assert_eq!(res, format!("(* {} 2)", big));
+        let zero = Pattern::Integer(0);
+        let mut b = Bindings::default();
+        assert!(match_pattern(
+            &zero,
+            &as_ptr(Expr::BigInteger(BigInt::from(0))),
+            &mut b
+        ));
+        assert!(!match_pattern(
+            &zero,
+            &as_ptr(Expr::BigInteger(BigInt::from(1))),
+            &mut b
+        ));
+    }
+
+    #[test]
+    fn test_conditions() {
+        // Sorts the operands of a product of literals
+        let rules =
+            parse_rules("(rule sort (* ?a:int ?b:int) (* ?b ?a) (when (< ?b ?a)))").unwrap();
+        let (res, fired) = run(&rules, "(* 5 3)", Order::TopDown);
+        assert_eq!((res.as_str(), fired.len()), ("(* 3 5)", 1));
+
+        let rules = [Rule::new("pos", "(neg ?n:int)", "0")
+            .when(|b| matches!(*b.exprs["n"].borrow(), Expr::Integer(n) if n > 10))];
+        let (res, _) = run(&rules, "(+ (neg 5) (neg 50))", Order::TopDown);
+        assert_eq!(res, "(+ (neg 5) 0)");
+    }
+
+    #[test]
+    fn test_order_and_trace() {
+        let rules = [
+            Rule::new("inner", "(+ ?a:int ?b:int)", "0"),
+            Rule::new("outer", "(+ ?a ?b:int)", "1"),
+        ];
+        let (res, fired) = run(&rules, "(+ (+ 2 3) 4)", Order::TopDown);
+        assert_eq!((res.as_str(), fired), ("1", vec!["outer".to_string()]));
+        let (res, fired) = run(&rules, "(+ (+ 2 3) 4)", Order::BottomUp);
+        assert_eq!(res, "0");
+        assert_eq!(fired, vec!["inner", "inner"]);
+
+        let expr = sexp::parse_sexp("(not (+ (+ 2 3) 4))").unwrap();
+        let res = rewrite(&expr, &rules[..1], &Options::default());
+        let first = &res.trace[0];
+        assert_eq!(first.path, vec![0, 0]);
+        assert_eq!(
+            (first.before.as_str(), first.after.as_str()),
+            ("(+ 2 3)", "0")
+        );
+    }
+
+    #[test]
+    fn test_computed_and_budget() {
+        let strlen = [Rule::computed("strlen", "?s:str", |b| {
+            let Expr::String(s) = &*b.exprs["s"].borrow() else {
+                return None;
+            };
+            Some(Expr::Integer(s.len() as i64))
+        })];
+        let (res, _) = run(&strlen, "(concat \"abc\" \"de\")", Order::TopDown);
+        assert_eq!(res, "(concat 3 2)");
+
+        let swap = [Rule::new("swap", "(+ ?a ?b)", "(+ ?b ?a)")];
+        let expr = sexp::parse_sexp("(+ 1 2)").unwrap();
+        let options = Options {
+            max_rewrites: 7,
+            ..Options::default()
+        };
+        let res = rewrite(&expr, &swap, &options);
+        assert!(!res.fixpoint);
+        assert_eq!(res.trace.len(), 7);
+    }
+
+    #[test]
+    fn test_rule_errors() {
+        assert!(parse_rules("(rule a (+ ?x) ?x)").is_err());
+        assert!(parse_rules("(rule a ?x:float ?x)").is_err());
+        assert!(parse_rules("(rule a ?x)").is_err());
+        assert!(parse_rules("(rewrite a ?x ?x)").is_err());
+        let Err(err) = parse_rules("(rule a ?x ?x (if true))") else {
+            panic!("expected an error");
+        };
+        assert!(err.message.contains("when"), "{}", err);
+        let Err(err) = parse_rules("(rule a (+ ?x 0) (* ?y 1))") else {
+            panic!("expected an error");
+        };
+        assert!(err.message.contains("'?y' is not bound"), "{}", err);
+        assert!(parse_rules("(rule a (lam ?v ?b) (lam ?w ?b))").is_err());
+        assert!(parse_rules("(rule a ?x:int ?x (when (< ?y 0)))").is_err());
+        assert!(parse_rules("(rule a (lam ?v (var ?v)) (lam ?v (var ?v)))").is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "'?c' is not bound by the pattern")]
+    fn test_new_unbound() {
+        Rule::new("swap", "(+ ?a ?b)", "(+ ?b ?c)");
+    }
+}
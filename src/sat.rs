@@ -0,0 +1,386 @@
+use std::collections::BinaryHeap;
+
+// A small CDCL SAT solver, so the CNF from `bitblast` can be solved without
+// writing it out for minisat. Clauses are DIMACS literals, `v` or `-v` for
+// variables 1..=n. It has the usual parts: two watched literals per clause,
+// first-UIP learning, activity-based decisions with saved phases, and
+// restarts, but it never forgets a learnt clause, so `max_conflicts` is
+// also what bounds its memory.
+
+const DECAY: f64 = 0.95;
+const RESTART_FIRST: usize = 100;
+const RESTART_GROWTH: f64 = 1.5;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Solution {
+    // A literal per variable, positive for the true ones
+    Sat(Vec<i64>),
+    Unsat,
+    // Ran out of conflicts
+    Unknown,
+}
+
+// Literal `v` is `2 * (v - 1)`, `-v` is `2 * (v - 1) + 1`
+fn lit(l: i64) -> usize {
+    2 * (l.unsigned_abs() as usize - 1) + usize::from(l < 0)
+}
+
+fn var(l: usize) -> usize {
+    l / 2
+}
+
+struct Solver {
+    clauses: Vec<Vec<usize>>,
+    // Clauses by one of their first two literals
+    watches: Vec<Vec<usize>>,
+    // Per variable: 1 true, -1 false, 0 unassigned
+    values: Vec<i8>,
+    levels: Vec<usize>,
+    reasons: Vec<Option<usize>>,
+    phases: Vec<bool>,
+    activity: Vec<f64>,
+    increment: f64,
+    // Decision candidates, stale entries are skipped
+    heap: BinaryHeap<(u64, usize)>,
+    trail: Vec<usize>,
+    // Start of each decision level in `trail`
+    limits: Vec<usize>,
+    propagated: usize,
+    seen: Vec<bool>,
+}
+
+impl Solver {
+    fn value(&self, l: usize) -> i8 {
+        let v = self.values[var(l)];
+        if l % 2 == 1 {
+            -v
+        } else {
+            v
+        }
+    }
+
+    fn level(&self) -> usize {
+        self.limits.len()
+    }
+
+    fn assign(&mut self, l: usize, reason: Option<usize>) {
+        let v = var(l);
+        self.values[v] = if l % 2 == 1 { -1 } else { 1 };
+        self.levels[v] = self.level();
+        self.reasons[v] = reason;
+        self.trail.push(l);
+    }
+
+    fn add_clause(&mut self, literals: Vec<usize>) -> usize {
+        let index = self.clauses.len();
+        self.watches[literals[0]].push(index);
+        self.watches[literals[1]].push(index);
+        self.clauses.push(literals);
+        index
+    }
+
+    // The conflicting clause, if any
+    fn propagate(&mut self) -> Option<usize> {
+        while self.propagated < self.trail.len() {
+            let falsified = self.trail[self.propagated] ^ 1;
+            self.propagated += 1;
+            let watching = std::mem::take(&mut self.watches[falsified]);
+            let mut kept = Vec::with_capacity(watching.len());
+            let mut conflict = None;
+            for (i, c) in watching.iter().enumerate() {
+                if conflict.is_some() {
+                    kept.extend_from_slice(&watching[i..]);
+                    break;
+                }
+                let clause = &mut self.clauses[*c];
+                if clause[0] == falsified {
+                    clause.swap(0, 1);
+                }
+                let first = clause[0];
+                if self.value(first) == 1 {
+                    kept.push(*c);
+                    continue;
+                }
+                let clause = &self.clauses[*c];
+                let replacement = (2..clause.len()).find(|k| self.value(clause[*k]) != -1);
+                if let Some(k) = replacement {
+                    let clause = &mut self.clauses[*c];
+                    clause.swap(1, k);
+                    let watch = clause[1];
+                    self.watches[watch].push(*c);
+                    continue;
+                }
+                kept.push(*c);
+                if self.value(first) == -1 {
+                    conflict = Some(*c);
+                } else {
+                    self.assign(first, Some(*c));
+                }
+            }
+            self.watches[falsified] = kept;
+            if conflict.is_some() {
+                return conflict;
+            }
+        }
+        None
+    }
+
+    fn bump(&mut self, v: usize) {
+        self.activity[v] += self.increment;
+        if self.activity[v] > 1e100 {
+            for a in self.activity.iter_mut() {
+                *a *= 1e-100;
+            }
+            self.increment *= 1e-100;
+            let entries: Vec<(u64, usize)> = (0..self.values.len())
+                .filter(|v| self.values[*v] == 0)
+                .map(|v| (self.activity[v].to_bits(), v))
+                .collect();
+            self.heap = entries.into_iter().collect();
+        }
+        self.heap.push((self.activity[v].to_bits(), v));
+    }
+
+    // First-UIP clause, its asserting literal first, and the level to go
+    // back to
+    fn analyze(&mut self, conflict: usize) -> (Vec<usize>, usize) {
+        let mut learnt = vec![0];
+        let mut pending = 0;
+        let mut clause = conflict;
+        let mut implied: Option<usize> = None;
+        let mut index = self.trail.len();
+        loop {
+            let literals = self.clauses[clause].clone();
+            // The implied literal of a reason clause is its first
+            let start = usize::from(implied.is_some());
+            for l in literals[start..].iter() {
+                let v = var(*l);
+                if self.seen[v] || self.levels[v] == 0 {
+                    continue;
+                }
+                self.seen[v] = true;
+                self.bump(v);
+                if self.levels[v] == self.level() {
+                    pending += 1;
+                } else {
+                    learnt.push(*l);
+                }
+            }
+            loop {
+                index -= 1;
+                if self.seen[var(self.trail[index])] {
+                    break;
+                }
+            }
+            let l = self.trail[index];
+            self.seen[var(l)] = false;
+            pending -= 1;
+            if pending == 0 {
+                learnt[0] = l ^ 1;
+                break;
+            }
+            implied = Some(l);
+            clause = self.reasons[var(l)].unwrap();
+        }
+        for l in learnt[1..].iter() {
+            self.seen[var(*l)] = false;
+        }
+        let mut back = 0;
+        if learnt.len() > 1 {
+            let deepest = (1..learnt.len())
+                .max_by_key(|i| self.levels[var(learnt[*i])])
+                .unwrap();
+            learnt.swap(1, deepest);
+            back = self.levels[var(learnt[1])];
+        }
+        self.increment /= DECAY;
+        (learnt, back)
+    }
+
+    fn backtrack(&mut self, level: usize) {
+        if self.level() <= level {
+            return;
+        }
+        let start = self.limits[level];
+        for l in self.trail.drain(start..) {
+            let v = var(l);
+            self.values[v] = 0;
+            self.reasons[v] = None;
+            self.phases[v] = l % 2 == 0;
+            self.heap.push((self.activity[v].to_bits(), v));
+        }
+        self.limits.truncate(level);
+        self.propagated = self.trail.len();
+    }
+
+    fn decide(&mut self) -> Option<usize> {
+        while let Some((activity, v)) = self.heap.pop() {
+            if self.values[v] == 0 && activity == self.activity[v].to_bits() {
+                return Some(2 * v + usize::from(!self.phases[v]));
+            }
+        }
+        None
+    }
+
+    fn model(&self) -> Vec<i64> {
+        (0..self.values.len())
+            .map(|v| {
+                let x = v as i64 + 1;
+                if self.values[v] == 1 {
+                    x
+                } else {
+                    -x
+                }
+            })
+            .collect()
+    }
+}
+
+// A model of `clauses` over variables 1..=`variables`
+pub fn solve(clauses: &[Vec<i64>], variables: i64, max_conflicts: usize) -> Solution {
+    let n = variables as usize;
+    let mut solver = Solver {
+        clauses: Vec::new(),
+        watches: vec![Vec::new(); 2 * n],
+        values: vec![0; n],
+        levels: vec![0; n],
+        reasons: vec![None; n],
+        phases: vec![false; n],
+        activity: vec![0.0; n],
+        increment: 1.0,
+        heap: (0..n).map(|v| (0.0f64.to_bits(), v)).collect(),
+        trail: Vec::new(),
+        limits: Vec::new(),
+        propagated: 0,
+        seen: vec![false; n],
+    };
+    for clause in clauses.iter() {
+        let mut literals: Vec<usize> = clause.iter().map(|l| lit(*l)).collect();
+        literals.sort();
+        literals.dedup();
+        if literals.windows(2).any(|w| w[0] ^ 1 == w[1]) {
+            continue;
+        }
+        match literals.len() {
+            0 => return Solution::Unsat,
+            1 => match solver.value(literals[0]) {
+                -1 => return Solution::Unsat,
+                0 => solver.assign(literals[0], None),
+                _ => {}
+            },
+            _ => {
+                solver.add_clause(literals);
+            }
+        }
+    }
+    let mut conflicts = 0;
+    let mut restart = RESTART_FIRST;
+    let mut since_restart = 0;
+    loop {
+        if let Some(conflict) = solver.propagate() {
+            if solver.level() == 0 {
+                return Solution::Unsat;
+            }
+            conflicts += 1;
+            since_restart += 1;
+            if conflicts > max_conflicts {
+                return Solution::Unknown;
+            }
+            let (learnt, back) = solver.analyze(conflict);
+            solver.backtrack(back);
+            let asserting = learnt[0];
+            if learnt.len() == 1 {
+                solver.assign(asserting, None);
+            } else {
+                let index = solver.add_clause(learnt);
+                solver.assign(asserting, Some(index));
+            }
+            continue;
+        }
+        if since_restart >= restart {
+            since_restart = 0;
+            restart = (restart as f64 * RESTART_GROWTH) as usize;
+            solver.backtrack(0);
+        }
+        match solver.decide() {
+            Some(l) => {
+                solver.limits.push(solver.trail.len());
+                solver.assign(l, None);
+            }
+            None => return Solution::Sat(solver.model()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn satisfies(clauses: &[Vec<i64>], model: &[i64]) -> bool {
+        clauses.iter().all(|c| c.iter().any(|l| model.contains(l)))
+    }
+
+    #[test]
+    fn test_small() {
+        let clauses = vec![vec![1, 2], vec![-1, 3], vec![-3, -2], vec![-2, 1]];
+        let Solution::Sat(model) = solve(&clauses, 3, 100) else {
+            panic!("unsat");
+        };
+        assert!(satisfies(&clauses, &model));
+        assert_eq!(model, vec![1, -2, 3]);
+        let clauses = vec![vec![1, 2], vec![-1, 2], vec![1, -2], vec![-1, -2]];
+        assert_eq!(solve(&clauses, 2, 100), Solution::Unsat);
+        assert_eq!(solve(&[vec![]], 1, 100), Solution::Unsat);
+        assert_eq!(solve(&[vec![1], vec![-1]], 1, 100), Solution::Unsat);
+    }
+
+    #[test]
+    fn test_pigeons() {
+        // n + 1 pigeons do not fit in n holes, `p * n + h + 1` puts pigeon
+        // `p` in hole `h`
+        let n = 6;
+        let var = |p: i64, h: i64| p * n + h + 1;
+        let mut clauses: Vec<Vec<i64>> = (0..=n)
+            .map(|p| (0..n).map(|h| var(p, h)).collect())
+            .collect();
+        for h in 0..n {
+            for p in 0..=n {
+                for q in p + 1..=n {
+                    clauses.push(vec![-var(p, h), -var(q, h)]);
+                }
+            }
+        }
+        let variables = (n + 1) * n;
+        assert_eq!(solve(&clauses, variables, 100_000), Solution::Unsat);
+        assert_eq!(solve(&clauses, variables, 10), Solution::Unknown);
+        // ... but n do
+        clauses.retain(|c| c.iter().all(|l| (l.abs() - 1) / n != n));
+        let Solution::Sat(model) = solve(&clauses, variables, 100_000) else {
+            panic!("unsat");
+        };
+        assert!(satisfies(&clauses, &model));
+    }
+
+    #[test]
+    fn test_problem_7() {
+        // The clauses `convert_to_sat3` takes from the program
+        let text = std::fs::read_to_string("solutions/7-minisat.txt").unwrap();
+        let mut lines = text.lines();
+        let header: Vec<i64> = lines.next().unwrap()[6..]
+            .split(' ')
+            .map(|x| x.parse().unwrap())
+            .collect();
+        let clauses: Vec<Vec<i64>> = lines
+            .map(|line| {
+                let mut c: Vec<i64> = line.split(' ').map(|x| x.parse().unwrap()).collect();
+                c.pop();
+                c
+            })
+            .collect();
+        assert_eq!(clauses.len() as i64, header[1]);
+        let Solution::Sat(model) = solve(&clauses, header[0], 100_000) else {
+            panic!("unsat");
+        };
+        assert!(satisfies(&clauses, &model));
+    }
+}
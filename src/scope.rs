@@ -0,0 +1,235 @@
+use crate::*;
+
+use num_bigint::BigInt;
+
+use std::collections::HashSet;
+
+// Variable scoping: free and bound variables, alpha-equivalence and a
+// De Bruijn form.
+//
+// In the De Bruijn form a bound variable is the number of lambdas between it
+// and its binder, 0 being the nearest one, and lambdas carry no name. Terms
+// that only differ in the names of bound variables have the same form, so it
+// derives `Hash` and works as a key. `canonical` turns it back into an
+// `Expr` with binders named by depth, the alpha-normal form.
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DeBruijn {
+    Boolean(bool),
+    Integer(i64),
+    BigInteger(BigInt),
+    String(String),
+    Bound(usize),
+    Free(i64),
+    Unary(char, Box<DeBruijn>),
+    Binary(char, Box<DeBruijn>, Box<DeBruijn>),
+    If(Box<DeBruijn>, Box<DeBruijn>, Box<DeBruijn>),
+    Lambda(Box<DeBruijn>),
+}
+
+fn free_vars_impl(expr: &Expr, bound: &mut Vec<i64>, res: &mut HashSet<i64>) {
+    match expr {
+        Expr::Var(x) => {
+            if !bound.contains(x) {
+                res.insert(*x);
+            }
+        }
+        Expr::Lambda(x, body) => {
+            bound.push(*x);
+            free_vars_impl(&body.borrow(), bound, res);
+            bound.pop();
+        }
+        _ => {
+            for c in children(expr).iter() {
+                free_vars_impl(&c.borrow(), bound, res);
+            }
+        }
+    }
+}
+
+pub fn free_vars(expr: &Expr) -> HashSet<i64> {
+    let mut res = HashSet::new();
+    free_vars_impl(expr, &mut Vec::new(), &mut res);
+    res
+}
+
+// Ids of all lambdas in `expr`, used or not
+pub fn bound_vars(expr: &Expr) -> HashSet<i64> {
+    let mut res = HashSet::new();
+    let mut stack = vec![as_ptr(expr.clone())];
+    while let Some(e) = stack.pop() {
+        let e = e.borrow();
+        if let Expr::Lambda(x, _) = *e {
+            res.insert(x);
+        }
+        stack.extend(children(&e));
+    }
+    res
+}
+
+// `scope` holds the enclosing binders, innermost last
+fn to_de_bruijn_impl(expr: &Expr, scope: &mut Vec<i64>) -> DeBruijn {
+    let mut sub = |e: &ExprPtr| Box::new(to_de_bruijn_impl(&e.borrow(), scope));
+    match expr {
+        Expr::Boolean(b) => DeBruijn::Boolean(*b),
+        Expr::Integer(x) => DeBruijn::Integer(*x),
+        Expr::BigInteger(x) => DeBruijn::BigInteger(x.clone()),
+        Expr::String(s) => DeBruijn::String(s.clone()),
+        Expr::Var(x) => match scope.iter().rev().position(|y| y == x) {
+            Some(index) => DeBruijn::Bound(index),
+            None => DeBruijn::Free(*x),
+        },
+        Expr::Unary(op, a) => DeBruijn::Unary(*op, sub(a)),
+        Expr::Binary(op, a, b) => {
+            let a = sub(a);
+            DeBruijn::Binary(*op, a, sub(b))
+        }
+        Expr::If(a, b, c) => {
+            let a = sub(a);
+            let b = sub(b);
+            DeBruijn::If(a, b, sub(c))
+        }
+        Expr::Lambda(x, body) => {
+            scope.push(*x);
+            let body = to_de_bruijn_impl(&body.borrow(), scope);
+            scope.pop();
+            DeBruijn::Lambda(Box::new(body))
+        }
+    }
+}
+
+pub fn to_de_bruijn(expr: &Expr) -> DeBruijn {
+    to_de_bruijn_impl(expr, &mut Vec::new())
+}
+
+fn free_in_de_bruijn(db: &DeBruijn, res: &mut HashSet<i64>) {
+    match db {
+        DeBruijn::Free(x) => {
+            res.insert(*x);
+        }
+        DeBruijn::Unary(_, a) | DeBruijn::Lambda(a) => free_in_de_bruijn(a, res),
+        DeBruijn::Binary(_, a, b) => {
+            free_in_de_bruijn(a, res);
+            free_in_de_bruijn(b, res);
+        }
+        DeBruijn::If(a, b, c) => {
+            free_in_de_bruijn(a, res);
+            free_in_de_bruijn(b, res);
+            free_in_de_bruijn(c, res);
+        }
+        _ => (),
+    }
+}
+
+// `names[d]` is the id of the binder at lambda depth `d`
+fn from_de_bruijn_impl(db: &DeBruijn, depth: usize, names: &[i64]) -> Option<ExprPtr> {
+    let sub = |e: &DeBruijn| from_de_bruijn_impl(e, depth, names);
+    let res = match db {
+        DeBruijn::Boolean(b) => Expr::Boolean(*b),
+        DeBruijn::Integer(x) => Expr::Integer(*x),
+        DeBruijn::BigInteger(x) => Expr::BigInteger(x.clone()),
+        DeBruijn::String(s) => Expr::String(s.clone()),
+        DeBruijn::Bound(index) => Expr::Var(names[depth.checked_sub(index + 1)?]),
+        DeBruijn::Free(x) => Expr::Var(*x),
+        DeBruijn::Unary(op, a) => Expr::Unary(*op, sub(a)?),
+        DeBruijn::Binary(op, a, b) => Expr::Binary(*op, sub(a)?, sub(b)?),
+        DeBruijn::If(a, b, c) => Expr::If(sub(a)?, sub(b)?, sub(c)?),
+        DeBruijn::Lambda(body) => {
+            Expr::Lambda(names[depth], from_de_bruijn_impl(body, depth + 1, names)?)
+        }
+    };
+    Some(as_ptr(res))
+}
+
+fn lambda_depth(db: &DeBruijn) -> usize {
+    match db {
+        DeBruijn::Lambda(a) => 1 + lambda_depth(a),
+        DeBruijn::Unary(_, a) => lambda_depth(a),
+        DeBruijn::Binary(_, a, b) => lambda_depth(a).max(lambda_depth(b)),
+        DeBruijn::If(a, b, c) => lambda_depth(a).max(lambda_depth(b)).max(lambda_depth(c)),
+        _ => 0,
+    }
+}
+
+// Names the binder at depth `d` with the `d`-th smallest id that is not a
+// free variable, so nothing gets captured. `None` if an index points past
+// the outermost lambda.
+pub fn from_de_bruijn(db: &DeBruijn) -> Option<ExprPtr> {
+    let mut free = HashSet::new();
+    free_in_de_bruijn(db, &mut free);
+    let names: Vec<i64> = (0..)
+        .filter(|id| !free.contains(id))
+        .take(lambda_depth(db))
+        .collect();
+    from_de_bruijn_impl(db, 0, &names)
+}
+
+pub fn alpha_eq(a: &Expr, b: &Expr) -> bool {
+    to_de_bruijn(a) == to_de_bruijn(b)
+}
+
+// Alpha-normal form: alpha-equivalent expressions give equal results, and
+// ids are as small as they can be at each depth
+pub fn canonical(expr: &Expr) -> ExprPtr {
+    from_de_bruijn(&to_de_bruijn(expr)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(program: &str) -> ExprPtr {
+        parse_into_ast(program.to_string())
+    }
+
+    #[test]
+    fn test_free_and_bound() {
+        let expr = parse("B+ v# L# B$ L$ v# v%");
+        assert_eq!(free_vars(&expr.borrow()), HashSet::from([2, 4]));
+        assert_eq!(bound_vars(&expr.borrow()), HashSet::from([2, 3]));
+        assert!(free_vars(&parse("L# L$ v#").borrow()).is_empty());
+    }
+
+    #[test]
+    fn test_de_bruijn() {
+        let expr = parse("L# L$ B+ v# B$ v$ v%");
+        let db = to_de_bruijn(&expr.borrow());
+        let expected = DeBruijn::Lambda(Box::new(DeBruijn::Lambda(Box::new(DeBruijn::Binary(
+            '+',
+            Box::new(DeBruijn::Bound(1)),
+            Box::new(DeBruijn::Binary(
+                '$',
+                Box::new(DeBruijn::Bound(0)),
+                Box::new(DeBruijn::Free(4)),
+            )),
+        )))));
+        assert_eq!(db, expected);
+        // Binders skip the free `v%`
+        let back = from_de_bruijn(&db).unwrap();
+        assert_eq!(serialize_expr(&back.borrow()), "L! L\" B+ v! B$ v\" v%");
+        assert!(alpha_eq(&back.borrow(), &expr.borrow()));
+
+        assert!(from_de_bruijn(&DeBruijn::Lambda(Box::new(DeBruijn::Bound(1)))).is_none());
+    }
+
+    #[test]
+    fn test_alpha_eq() {
+        let eq = |a: &str, b: &str| alpha_eq(&parse(a).borrow(), &parse(b).borrow());
+        assert!(eq("L# v#", "L$ v$"));
+        assert!(eq("L# L$ v#", "L$ L# v$"));
+        assert!(!eq("L# L$ v#", "L# L$ v$"));
+        // Free variables must match by name
+        assert!(!eq("L# v$", "L# v%"));
+        assert!(!eq("L# v$", "L$ v$"));
+    }
+
+    #[test]
+    fn test_canonical() {
+        let canon = |s: &str| serialize_expr(&canonical(&parse(s).borrow()).borrow());
+        assert_eq!(canon("L~ L} v~"), "L! L\" v!");
+        assert_eq!(canon("B. L$ v$ L% v%"), "B. L! v! L! v!");
+        assert_eq!(canon("L# B+ v! v#"), "L\" B+ v! v\"");
+        // Shadowing resolves to the inner binder
+        assert_eq!(canon("L# L# v#"), "L! L\" v\"");
+    }
+}
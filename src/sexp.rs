@@ -0,0 +1,451 @@
+use crate::parser::{error_at, error_at_end, ParseError, Span};
+use crate::*;
+
+use num_bigint::BigInt;
+
+// S-expression syntax for programs, meant for editing by hand:
+//
+//   (app (lam 2 (+ (var 2) 1)) 3)    is    B$ L# B+ v# I" I$
+//
+// Literals are integers (`-5` is allowed), `true`, `false` and JSON-style
+// quoted strings. Forms are `(var N)`, `(lam N body)`, `(app f x)`,
+// `(if cond then else)`, the unary operators in `UNARY_NAMES` and the binary
+// ones in `BINARY_NAMES`. `;` starts a comment that runs to the end of the
+// line.
+
+pub const UNARY_NAMES: [(char, &str); 4] = [
+    ('-', "neg"),
+    ('!', "not"),
+    ('#', "str->int"),
+    ('$', "int->str"),
+];
+
+pub const BINARY_NAMES: [(char, &str); 14] = [
+    ('+', "+"),
+    ('-', "-"),
+    ('*', "*"),
+    ('/', "/"),
+    ('%', "%"),
+    ('<', "<"),
+    ('>', ">"),
+    ('=', "="),
+    ('|', "or"),
+    ('&', "and"),
+    ('.', "concat"),
+    ('T', "take"),
+    ('D', "drop"),
+    ('$', "app"),
+];
+
+fn unary_name(op: char) -> &'static str {
+    UNARY_NAMES.iter().find(|(c, _)| *c == op).unwrap().1
+}
+
+fn binary_name(op: char) -> &'static str {
+    BINARY_NAMES.iter().find(|(c, _)| *c == op).unwrap().1
+}
+
+// Everything but the operands, like `(lam 2` for a lambda
+fn head(expr: &Expr) -> String {
+    match expr {
+        Expr::Unary(op, _) => format!("({}", unary_name(*op)),
+        Expr::Binary(op, _, _) => format!("({}", binary_name(*op)),
+        Expr::If(_, _, _) => "(if".to_string(),
+        Expr::Lambda(x, _) => format!("(lam {}", x),
+        _ => atom(expr),
+    }
+}
+
+fn atom(expr: &Expr) -> String {
+    match expr {
+        Expr::Boolean(b) => b.to_string(),
+        Expr::Integer(x) => x.to_string(),
+        Expr::BigInteger(x) => x.to_string(),
+        Expr::String(s) => serde_json::to_string(s).unwrap(),
+        Expr::Var(x) => format!("(var {})", x),
+        _ => unreachable!("not an atom"),
+    }
+}
+
+fn write_flat(expr: &Expr, res: &mut String) {
+    let args = children(expr);
+    res.push_str(&head(expr));
+    if args.is_empty() {
+        return;
+    }
+    for arg in args {
+        res.push(' ');
+        write_flat(&arg.borrow(), res);
+    }
+    res.push(')');
+}
+
+// Length of the one-line form, or None once it goes over `limit`
+fn flat_len(expr: &Expr, limit: usize) -> Option<usize> {
+    let args = children(expr);
+    let mut res = head(expr).len() + if args.is_empty() { 0 } else { 1 };
+    for arg in args {
+        if res > limit {
+            return None;
+        }
+        res += 1 + flat_len(&arg.borrow(), limit - res)?;
+    }
+    (res <= limit).then_some(res)
+}
+
+fn write_pretty(expr: &Expr, indent: usize, width: usize, res: &mut String) {
+    let room = width.saturating_sub(indent);
+    let args = children(expr);
+    if args.is_empty() || flat_len(expr, room).is_some() {
+        write_flat(expr, res);
+        return;
+    }
+    res.push_str(&head(expr));
+    for arg in args {
+        res.push('\n');
+        res.push_str(&" ".repeat(indent + 2));
+        write_pretty(&arg.borrow(), indent + 2, width, res);
+    }
+    res.push(')');
+}
+
+pub fn to_sexp(expr: &Expr) -> String {
+    let mut res = String::new();
+    write_flat(expr, &mut res);
+    res
+}
+
+// Breaks forms that do not fit in `width` columns, one operand per line
+pub fn to_sexp_pretty(expr: &Expr, width: usize) -> String {
+    let mut res = String::new();
+    write_pretty(expr, 0, width, &mut res);
+    res
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Lexeme {
+    Open,
+    Close,
+    Atom(String),
+    Str(String),
+}
+
+fn lex(source: &str) -> Result<Vec<(Lexeme, Span)>, ParseError> {
+    let bytes = source.as_bytes();
+    let mut res = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let start = pos;
+        let lexeme = match bytes[pos] {
+            b if b.is_ascii_whitespace() => {
+                pos += 1;
+                continue;
+            }
+            b';' => {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+            b'(' => {
+                pos += 1;
+                Lexeme::Open
+            }
+            b')' => {
+                pos += 1;
+                Lexeme::Close
+            }
+            b'"' => {
+                pos += 1;
+                while pos < bytes.len() && bytes[pos] != b'"' {
+                    pos += if bytes[pos] == b'\\' { 2 } else { 1 };
+                }
+                let span = Span {
+                    offset: start,
+                    len: pos.min(bytes.len()) - start,
+                    index: res.len(),
+                };
+                if pos >= bytes.len() {
+                    return Err(error_at(source, span, "unterminated string".to_string()));
+                }
+                pos += 1;
+                let literal = &source[start..pos];
+                let value = serde_json::from_str::<String>(literal)
+                    .map_err(|e| error_at(source, span, format!("bad string literal: {}", e)))?;
+                Lexeme::Str(value)
+            }
+            _ => {
+                while pos < bytes.len()
+                    && !bytes[pos].is_ascii_whitespace()
+                    && !b"();\"".contains(&bytes[pos])
+                {
+                    pos += 1;
+                }
+                Lexeme::Atom(source[start..pos].to_string())
+            }
+        };
+        let span = Span {
+            offset: start,
+            len: pos - start,
+            index: res.len(),
+        };
+        res.push((lexeme, span));
+    }
+    Ok(res)
+}
+
+// Syntax tree of S-expressions before they are given a meaning, shared with
+// the rule syntax in `rewrite`. The span of a list covers its opening
+// parenthesis.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Sexp {
+    Atom(String, Span),
+    Str(String, Span),
+    List(Vec<Sexp>, Span),
+}
+
+impl Sexp {
+    pub fn span(&self) -> Span {
+        match self {
+            Sexp::Atom(_, span) | Sexp::Str(_, span) | Sexp::List(_, span) => *span,
+        }
+    }
+}
+
+// Reads every top-level S-expression of `source`
+pub fn read_sexps(source: &str) -> Result<Vec<Sexp>, ParseError> {
+    let lexemes = lex(source)?;
+    // Lists being read, with the span of their opening parenthesis
+    let mut stack: Vec<(Vec<Sexp>, Span)> = Vec::new();
+    let mut res = Vec::new();
+    for (lexeme, span) in lexemes {
+        let item = match lexeme {
+            Lexeme::Open => {
+                stack.push((Vec::new(), span));
+                continue;
+            }
+            Lexeme::Close => match stack.pop() {
+                Some((items, open)) => Sexp::List(items, open),
+                None => return Err(error_at(source, span, "unexpected ')'".to_string())),
+            },
+            Lexeme::Atom(s) => Sexp::Atom(s, span),
+            Lexeme::Str(s) => Sexp::Str(s, span),
+        };
+        match stack.last_mut() {
+            Some((items, _)) => items.push(item),
+            None => res.push(item),
+        }
+    }
+    if !stack.is_empty() {
+        return Err(error_at_end(
+            source,
+            "expected ')', input ended".to_string(),
+        ));
+    }
+    Ok(res)
+}
+
+// Reads exactly one S-expression
+pub fn read_sexp(source: &str) -> Result<Sexp, ParseError> {
+    let mut items = read_sexps(source)?.into_iter();
+    let Some(res) = items.next() else {
+        return Err(error_at_end(
+            source,
+            "expected an expression, input ended".to_string(),
+        ));
+    };
+    if let Some(extra) = items.next() {
+        let message = "unexpected text after the expression".to_string();
+        return Err(error_at(source, extra.span(), message));
+    }
+    Ok(res)
+}
+
+// Splits `(name operands...)` and checks the operand count
+pub fn form<'a>(
+    source: &str,
+    sexp: &'a Sexp,
+    items: &'a [Sexp],
+) -> Result<(&'a str, &'a [Sexp]), ParseError> {
+    match items.first() {
+        Some(Sexp::Atom(name, _)) => Ok((name, &items[1..])),
+        Some(other) => Err(error_at(
+            source,
+            other.span(),
+            "expected a form name".to_string(),
+        )),
+        None => Err(error_at(source, sexp.span(), "empty form".to_string())),
+    }
+}
+
+pub fn check_arity(
+    source: &str,
+    sexp: &Sexp,
+    name: &str,
+    args: &[Sexp],
+    n: usize,
+) -> Result<(), ParseError> {
+    if args.len() > n {
+        let message = format!("too many operands for '{}'", name);
+        return Err(error_at(source, args[n].span(), message));
+    }
+    if args.len() < n {
+        let message = format!("'{}' needs {} operand(s), got {}", name, n, args.len());
+        return Err(error_at(source, sexp.span(), message));
+    }
+    Ok(())
+}
+
+// Non-negative variable number of `var` and `lam`
+fn number(source: &str, sexp: &Sexp) -> Result<i64, ParseError> {
+    match sexp {
+        Sexp::Atom(s, span) => match s.parse::<i64>() {
+            Ok(x) if x >= 0 => Ok(x),
+            _ => Err(error_at(
+                source,
+                *span,
+                format!("bad variable number '{}'", s),
+            )),
+        },
+        _ => Err(error_at(
+            source,
+            sexp.span(),
+            "expected a variable number".to_string(),
+        )),
+    }
+}
+
+pub fn sexp_to_expr(source: &str, sexp: &Sexp) -> Result<ExprPtr, ParseError> {
+    let expr = match sexp {
+        Sexp::Str(s, span) if !codec::is_encodable(s) => {
+            let message = "string cannot be encoded in the ICFP text".to_string();
+            return Err(error_at(source, *span, message));
+        }
+        Sexp::Str(s, _) => Expr::String(s.clone()),
+        Sexp::Atom(s, span) => match s.as_str() {
+            "true" => Expr::Boolean(true),
+            "false" => Expr::Boolean(false),
+            _ => match (s.parse::<i64>(), s.parse::<BigInt>()) {
+                (Ok(x), _) => Expr::Integer(x),
+                (_, Ok(x)) => Expr::BigInteger(x),
+                _ => return Err(error_at(source, *span, format!("unexpected '{}'", s))),
+            },
+        },
+        Sexp::List(items, _) => {
+            let (name, args) = form(source, sexp, items)?;
+            let arg = |idx: usize| sexp_to_expr(source, &args[idx]);
+            let unary = UNARY_NAMES.iter().find(|(_, n)| *n == name);
+            let binary = BINARY_NAMES.iter().find(|(_, n)| *n == name);
+            match (name, unary, binary) {
+                ("var", _, _) => {
+                    check_arity(source, sexp, name, args, 1)?;
+                    Expr::Var(number(source, &args[0])?)
+                }
+                ("lam", _, _) => {
+                    check_arity(source, sexp, name, args, 2)?;
+                    Expr::Lambda(number(source, &args[0])?, arg(1)?)
+                }
+                ("if", _, _) => {
+                    check_arity(source, sexp, name, args, 3)?;
+                    Expr::If(arg(0)?, arg(1)?, arg(2)?)
+                }
+                (_, Some((op, _)), _) => {
+                    check_arity(source, sexp, name, args, 1)?;
+                    Expr::Unary(*op, arg(0)?)
+                }
+                (_, _, Some((op, _))) => {
+                    check_arity(source, sexp, name, args, 2)?;
+                    Expr::Binary(*op, arg(0)?, arg(1)?)
+                }
+                _ => {
+                    let message = format!("unknown form '{}'", name);
+                    return Err(error_at(source, items[0].span(), message));
+                }
+            }
+        }
+    };
+    Ok(as_ptr(expr))
+}
+
+pub fn parse_sexp(source: &str) -> Result<ExprPtr, ParseError> {
+    sexp_to_expr(source, &read_sexp(source)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example() {
+        let expr = parse_sexp("(app (lam 2 (+ (var 2) 1)) 3)").unwrap();
+        assert_eq!(serialize_expr(&expr.borrow()), "B$ L# B+ v# I\" I$");
+        let icfp = parse_into_ast("B$ L# B+ v# I\" I$".to_string());
+        assert_eq!(to_sexp(&icfp.borrow()), "(app (lam 2 (+ (var 2) 1)) 3)");
+    }
+
+    #[test]
+    fn test_literals_and_comments() {
+        let source = "; header\n(if (not true) \"a \\\"b\\\"\\n\" ; trailing\n  (concat -5 false))";
+        let expr = parse_sexp(source).unwrap();
+        let expected = Expr::If(
+            as_ptr(Expr::Unary('!', as_ptr(Expr::Boolean(true)))),
+            as_ptr(Expr::String("a \"b\"\n".to_string())),
+            as_ptr(Expr::Binary(
+                '.',
+                as_ptr(Expr::Integer(-5)),
+                as_ptr(Expr::Boolean(false)),
+            )),
+        );
+        assert_eq!(*expr.borrow(), expected);
+        assert_eq!(
+            to_sexp(&expected),
+            "(if (not true) \"a \\\"b\\\"\\n\" (concat -5 false))"
+        );
+    }
+
+    #[test]
+    fn test_pretty() {
+        let expr = parse_sexp("(app (lam 2 (+ (var 2) 1)) 3)").unwrap();
+        let pretty = to_sexp_pretty(&expr.borrow(), 20);
+        assert_eq!(pretty, "(app\n  (lam 2\n    (+ (var 2) 1))\n  3)");
+        assert_eq!(*parse_sexp(&pretty).unwrap().borrow(), *expr.borrow());
+    }
+
+    #[test]
+    fn test_round_trip_programs() {
+        // The sudokus nest a thousand levels deep, too many for the default
+        // test stack in a debug build
+        let run = || {
+            for entry in fs::read_dir("problems").unwrap() {
+                let path = entry.unwrap().path();
+                let text = fs::read_to_string(&path).unwrap();
+                let expr = parser::parse_checked(text.trim()).unwrap();
+                let pretty = to_sexp_pretty(&expr.borrow(), 80);
+                let back = parse_sexp(&pretty).unwrap();
+                assert_eq!(*back.borrow(), *expr.borrow(), "{:?}", path);
+            }
+        };
+        let thread = std::thread::Builder::new().stack_size(1 << 26);
+        thread.spawn(run).unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = parse_sexp("(lam 2 (var 2)").unwrap_err();
+        assert!(err.message.contains("expected ')'"), "{}", err);
+        let err = parse_sexp("(+ 1 2 3)").unwrap_err();
+        assert!(err.message.contains("too many operands"), "{}", err);
+        assert_eq!(err.span.unwrap().offset, 7);
+        let err = parse_sexp("(frob 1)").unwrap_err();
+        assert!(err.message.contains("unknown form 'frob'"), "{}", err);
+        assert!(parse_sexp("(var -1)").is_err());
+        let err = parse_sexp("(concat \"a\" \"é\")").unwrap_err();
+        assert!(err.message.contains("cannot be encoded"), "{}", err);
+        assert_eq!(err.span.unwrap().offset, 12);
+        assert!(parse_sexp("(lam x (var x))").is_err());
+        assert!(parse_sexp("\"open").is_err());
+        assert!(parse_sexp("1 2").is_err());
+        assert!(parse_sexp(")").is_err());
+        assert!(parse_sexp("").is_err());
+    }
+}
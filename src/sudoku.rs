@@ -64,48 +64,33 @@ pub fn solve_from_state(state: &mut Grid) -> bool {
     true
 }
 
-fn visit_sudoku(expr_ptr: ExprPtr, res: &mut HashMap<usize, u8>) {
-    let e = &*expr_ptr.borrow();
-    match e {
-        Expr::Unary(_, a) => {
-            visit_sudoku(a.clone(), res);
-        }
-        Expr::Binary(op, a, b) => {
-            if *op == '=' {
-                println!("At Op(=) a={:?}, b={:?}", short_str(&a.borrow()), short_str(&b.borrow()));
-                if let Expr::Var(x) = &*a.borrow() {
-                    if let Expr::Integer(y) = &*b.borrow() {
-                        res.insert(*x as usize, *y as u8);
-                    }
+// Collects `v<cell> = <digit>` comparisons, the given digits of the puzzle
+struct SudokuVisitor {
+    res: HashMap<usize, u8>,
+}
+
+impl Visitor for SudokuVisitor {
+    fn visit_binary(&mut self, op: char, a: &ExprPtr, b: &ExprPtr) {
+        if op == '=' {
+            println!("At Op(=) a={:?}, b={:?}", short_str(&a.borrow()), short_str(&b.borrow()));
+            if let Expr::Var(x) = &*a.borrow() {
+                if let Expr::Integer(y) = &*b.borrow() {
+                    self.res.insert(*x as usize, *y as u8);
                 }
-            } else {
-                visit_sudoku(a.clone(), res);
-                visit_sudoku(b.clone(), res);    
             }
-        }
-        Expr::Lambda(_, a) => {
-            visit_sudoku(a.clone(), res);
-        }
-        Expr::If(a, b, c) => {
-            visit_sudoku(a.clone(), res);
-            visit_sudoku(b.clone(), res);
-            visit_sudoku(c.clone(), res);
-        }
-        Expr::Var(x) => {
-            // Ignore variables
-        }
-        Expr::String(_) | Expr::Integer(_)=> {
-            // ignore temporary strings
-        }
-        _ => {
-            panic!("Unexpected expression: {:?}", e);
+        } else {
+            self.visit_expr(a);
+            self.visit_expr(b);
         }
     }
 }
 
 pub fn extract_initial_state(expr_ptr: ExprPtr) -> Grid {
-    let mut res = HashMap::new();
-    visit_sudoku(expr_ptr, &mut res);
+    let mut visitor = SudokuVisitor {
+        res: HashMap::new(),
+    };
+    visitor.visit_expr(&expr_ptr);
+    let res = visitor.res;
     let mut state = vec![vec![0; 9]; 9];
     println!("{:?}", res);
     for (k, v) in res.iter() {
@@ -117,3 +102,25 @@ pub fn extract_initial_state(expr_ptr: ExprPtr) -> Grid {
     state
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_extract_initial_state() {
+        // Their powers of 9 are literals beyond i64
+        for (problem, clues) in [(9, 0), (10, 27), (11, 17)] {
+            let text = fs::read_to_string(format!("problems/{}.txt", problem)).unwrap();
+            let mut state = extract_initial_state(parse_into_ast(text));
+            let given = state.iter().flatten().filter(|d| **d != 0).count();
+            assert_eq!(given, clues, "problem {}", problem);
+            let before = state.clone();
+            assert!(solve_from_state(&mut state), "problem {}", problem);
+            for (old, new) in before.iter().flatten().zip(state.iter().flatten()) {
+                assert!(*old == 0 || old == new, "problem {}", problem);
+            }
+        }
+    }
+}
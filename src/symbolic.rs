@@ -0,0 +1,610 @@
+use crate::partial::{fold_binary, fold_unary};
+use crate::*;
+
+use std::collections::HashMap;
+
+// Symbolic execution of validators. One parameter of the program, a
+// top-level lambda `L x0. ... L xn. body`, becomes an unknown integer and
+// `body` is evaluated with it: arithmetic and comparisons on the unknown
+// build a `Term` instead of a number, and an `if` on such a term runs both
+// branches. The program's paths come out with the conditions that select
+// each one and the term it returns, so "find `x` with `program(x)`" becomes
+// a constraint over `Term::Input`.
+//
+// Paths are explored one at a time by running the program again with a
+// different choice at the last symbolic `if`. Evaluation is call-by-name,
+// like `eval`, with each argument evaluated at most once per path. `/` and
+// `%` by a symbolic divisor add the condition that it is not 0, since the
+// evaluator fails otherwise. Terms are unbounded integers: the evaluator's
+// overflows are not modelled. Paths are not checked for feasibility, a
+// condition may contradict an earlier one.
+//
+// Strings only work while they do not depend on the input, `$` of a
+// symbolic integer or `T` by a symbolic count gives `Error::Unsupported`.
+
+pub type TermPtr = Rc<Term>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Term {
+    // The symbolic parameter
+    Input,
+    Integer(i64),
+    Boolean(bool),
+    // `-` and `!`
+    Unary(char, TermPtr),
+    // Arithmetic, comparisons, `=`, `|` and `&`
+    Binary(char, TermPtr, TermPtr),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sort {
+    Int,
+    Bool,
+}
+
+impl Term {
+    pub fn sort(&self) -> Sort {
+        match self {
+            Term::Input | Term::Integer(_) | Term::Unary('-', _) => Sort::Int,
+            Term::Binary('+' | '-' | '*' | '/' | '%', _, _) => Sort::Int,
+            _ => Sort::Bool,
+        }
+    }
+
+    fn literal(&self) -> Option<Expr> {
+        match self {
+            Term::Integer(x) => Some(Expr::Integer(*x)),
+            Term::Boolean(b) => Some(Expr::Boolean(*b)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Term::Input => write!(f, "x"),
+            Term::Integer(x) => write!(f, "{}", x),
+            Term::Boolean(b) => write!(f, "{}", b),
+            Term::Unary(op, a) => write!(f, "{}{}", op, a),
+            Term::Binary(op, a, b) => write!(f, "({} {} {})", a, op, b),
+        }
+    }
+}
+
+fn constant(e: Expr) -> Option<TermPtr> {
+    match e {
+        Expr::Integer(x) => Some(Rc::new(Term::Integer(x))),
+        Expr::Boolean(b) => Some(Rc::new(Term::Boolean(b))),
+        _ => None,
+    }
+}
+
+// Folded when the operands are constants, checked by the caller otherwise
+fn unary(op: char, a: &TermPtr) -> Option<TermPtr> {
+    match a.literal() {
+        Some(x) => constant(fold_unary(op, &x)?),
+        None => Some(Rc::new(Term::Unary(op, a.clone()))),
+    }
+}
+
+fn binary(op: char, a: &TermPtr, b: &TermPtr) -> Option<TermPtr> {
+    match (a.literal(), b.literal()) {
+        (Some(x), Some(y)) => constant(fold_binary(op, &x, &y)?),
+        _ => Some(Rc::new(Term::Binary(op, a.clone(), b.clone()))),
+    }
+}
+
+// `a & b`, without the `true` operands
+pub fn conjunction(terms: &[TermPtr]) -> TermPtr {
+    let mut res: Option<TermPtr> = None;
+    for t in terms.iter() {
+        match **t {
+            Term::Boolean(true) => continue,
+            Term::Boolean(false) => return t.clone(),
+            _ => {}
+        }
+        res = Some(match res {
+            Some(r) => Rc::new(Term::Binary('&', r, t.clone())),
+            None => t.clone(),
+        });
+    }
+    res.unwrap_or_else(|| Rc::new(Term::Boolean(true)))
+}
+
+fn evaluate_impl(
+    term: &TermPtr,
+    input: i64,
+    memo: &mut HashMap<*const Term, Option<Expr>>,
+) -> Option<Expr> {
+    let key = Rc::as_ptr(term);
+    if let Some(res) = memo.get(&key) {
+        return res.clone();
+    }
+    let res = match &**term {
+        Term::Input => Some(Expr::Integer(input)),
+        Term::Integer(x) => Some(Expr::Integer(*x)),
+        Term::Boolean(b) => Some(Expr::Boolean(*b)),
+        Term::Unary(op, a) => evaluate_impl(a, input, memo).and_then(|a| fold_unary(*op, &a)),
+        Term::Binary(op, a, b) => {
+            let a = evaluate_impl(a, input, memo);
+            let b = evaluate_impl(b, input, memo);
+            fold_binary(*op, &a?, &b?)
+        }
+    };
+    memo.insert(key, res.clone());
+    res
+}
+
+// Value of `term` for an input, `None` where the evaluator would fail
+pub fn evaluate(term: &TermPtr, input: i64) -> Option<Expr> {
+    evaluate_impl(term, input, &mut HashMap::new())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Output {
+    Term(TermPtr),
+    Str(String),
+    Function,
+    // The path fails, like `B+ I! S!`
+    Error(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Path {
+    // All of them hold on this path
+    pub conditions: Vec<TermPtr>,
+    pub output: Output,
+}
+
+impl Path {
+    // Condition for this path to return `target`, `None` if it cannot
+    pub fn constraint(&self, target: &Expr) -> Option<TermPtr> {
+        let goal = match (&self.output, target) {
+            (Output::Term(t), Expr::Integer(_)) if t.sort() == Sort::Int => {
+                binary('=', t, &constant(target.clone())?)?
+            }
+            (Output::Term(t), Expr::Boolean(_)) if t.sort() == Sort::Bool => {
+                binary('=', t, &constant(target.clone())?)?
+            }
+            (Output::Str(s), Expr::String(expected)) if s == expected => {
+                Rc::new(Term::Boolean(true))
+            }
+            _ => return None,
+        };
+        let mut terms = self.conditions.clone();
+        terms.push(goal);
+        let res = conjunction(&terms);
+        if *res == Term::Boolean(false) {
+            return None;
+        }
+        Some(res)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    // The program has fewer top-level lambdas
+    MissingParameter(usize),
+    Unsupported(String),
+    StepLimit,
+    PathLimit,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::MissingParameter(n) => write!(f, "program has no parameter {}", n),
+            Error::Unsupported(what) => write!(f, "unsupported: {}", what),
+            Error::StepLimit => write!(f, "a path ran out of steps"),
+            Error::PathLimit => write!(f, "too many paths"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    pub max_paths: usize,
+    // Per path
+    pub max_steps: usize,
+    // Nested evaluations, deeper recursion counts as running out of steps.
+    // The default fits a 2 MiB thread stack in a debug build.
+    pub max_depth: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            max_paths: 1000,
+            max_steps: 1_000_000,
+            max_depth: 500,
+        }
+    }
+}
+
+type Env = Option<Rc<Frame>>;
+
+struct Frame {
+    var: i64,
+    thunk: Thunk,
+    next: Env,
+}
+
+type Thunk = Rc<RefCell<Delayed>>;
+
+enum Delayed {
+    Pending(ExprPtr, Env),
+    Running,
+    Done(Result<Value, Stop>),
+}
+
+#[derive(Clone)]
+enum Value {
+    Term(TermPtr),
+    Str(String),
+    Closure(i64, ExprPtr, Env),
+    // A parameter before the symbolic one
+    Opaque(usize),
+}
+
+#[derive(Clone)]
+enum Stop {
+    // This path fails
+    Fails(String),
+    Abort(Error),
+}
+
+fn fails<T>(what: &str) -> Result<T, Stop> {
+    Err(Stop::Fails(what.to_string()))
+}
+
+fn unsupported<T>(what: &str) -> Result<T, Stop> {
+    Err(Stop::Abort(Error::Unsupported(what.to_string())))
+}
+
+fn opaque<T>(param: usize) -> Result<T, Stop> {
+    let what = format!("use of parameter {}, which is not symbolic", param);
+    Err(Stop::Abort(Error::Unsupported(what)))
+}
+
+fn bind(var: i64, thunk: Thunk, next: &Env) -> Env {
+    Some(Rc::new(Frame {
+        var,
+        thunk,
+        next: next.clone(),
+    }))
+}
+
+fn lookup(env: &Env, x: i64) -> Option<Thunk> {
+    let mut frame = env.as_ref();
+    while let Some(f) = frame {
+        if f.var == x {
+            return Some(f.thunk.clone());
+        }
+        frame = f.next.as_ref();
+    }
+    None
+}
+
+fn as_literal(v: &Value) -> Option<Expr> {
+    match v {
+        Value::Term(t) => t.literal(),
+        Value::Str(s) => Some(Expr::String(s.clone())),
+        _ => None,
+    }
+}
+
+fn from_literal(e: Expr) -> Value {
+    match &e {
+        Expr::String(s) => Value::Str(s.clone()),
+        _ => Value::Term(constant(e).unwrap()),
+    }
+}
+
+struct Executor<'a> {
+    options: &'a Options,
+    // Branch taken at each symbolic `if` so far, replayed from earlier runs
+    decisions: Vec<bool>,
+    next: usize,
+    conditions: Vec<TermPtr>,
+    steps: usize,
+    depth: usize,
+}
+
+impl Executor<'_> {
+    fn branch(&mut self, cond: &TermPtr) -> bool {
+        if self.next == self.decisions.len() {
+            self.decisions.push(true);
+        }
+        let taken = self.decisions[self.next];
+        self.next += 1;
+        let holds = if taken {
+            cond.clone()
+        } else {
+            Rc::new(Term::Unary('!', cond.clone()))
+        };
+        self.conditions.push(holds);
+        taken
+    }
+
+    fn force(&mut self, thunk: &Thunk) -> Result<Value, Stop> {
+        let state = std::mem::replace(&mut *thunk.borrow_mut(), Delayed::Running);
+        let res = match state {
+            Delayed::Pending(expr_ptr, env) => self.eval(&expr_ptr, &env),
+            // Needs its own value, never finishes
+            Delayed::Running => Err(Stop::Abort(Error::StepLimit)),
+            Delayed::Done(res) => res,
+        };
+        *thunk.borrow_mut() = Delayed::Done(res.clone());
+        res
+    }
+
+    fn unary(&mut self, op: char, a: Value) -> Result<Value, Stop> {
+        if let Some(x) = as_literal(&a) {
+            return match fold_unary(op, &x) {
+                Some(e) => Ok(from_literal(e)),
+                None => fails("unary operator on a wrong operand"),
+            };
+        }
+        let Value::Term(t) = a else {
+            return match a {
+                Value::Opaque(i) => opaque(i),
+                _ => fails("unary operator on a function"),
+            };
+        };
+        match (op, t.sort()) {
+            ('-', Sort::Int) | ('!', Sort::Bool) => Ok(Value::Term(unary(op, &t).unwrap())),
+            ('$', Sort::Int) => unsupported("`$` of a symbolic integer"),
+            _ => fails("unary operator on a wrong operand"),
+        }
+    }
+
+    fn binary(&mut self, op: char, a: Value, b: Value) -> Result<Value, Stop> {
+        if let (Some(x), Some(y)) = (as_literal(&a), as_literal(&b)) {
+            return match fold_binary(op, &x, &y) {
+                Some(e) => Ok(from_literal(e)),
+                None => fails("binary operator fails"),
+            };
+        }
+        if let (Value::Opaque(i), _) | (_, Value::Opaque(i)) = (&a, &b) {
+            return opaque(*i);
+        }
+        let (Value::Term(x), Value::Term(y)) = (&a, &b) else {
+            return match (op, &a) {
+                ('T' | 'D', Value::Term(_)) => unsupported("a symbolic count"),
+                _ => fails("binary operator on a wrong operand"),
+            };
+        };
+        let sorts = match op {
+            '+' | '-' | '*' | '/' | '%' | '<' | '>' => {
+                x.sort() == Sort::Int && y.sort() == Sort::Int
+            }
+            '|' | '&' => x.sort() == Sort::Bool && y.sort() == Sort::Bool,
+            '=' => x.sort() == y.sort(),
+            _ => false,
+        };
+        if !sorts {
+            return fails("binary operator on a wrong operand");
+        }
+        if matches!(op, '/' | '%') {
+            match y.literal() {
+                Some(Expr::Integer(0)) => return fails("division by zero"),
+                Some(_) => {}
+                None => {
+                    let zero = Rc::new(Term::Integer(0));
+                    let is_zero = Rc::new(Term::Binary('=', y.clone(), zero));
+                    self.conditions.push(Rc::new(Term::Unary('!', is_zero)));
+                }
+            }
+        }
+        Ok(Value::Term(binary(op, x, y).unwrap()))
+    }
+
+    fn eval(&mut self, expr_ptr: &ExprPtr, env: &Env) -> Result<Value, Stop> {
+        self.steps += 1;
+        if self.steps > self.options.max_steps || self.depth >= self.options.max_depth {
+            return Err(Stop::Abort(Error::StepLimit));
+        }
+        self.depth += 1;
+        let res = self.eval_impl(expr_ptr, env);
+        self.depth -= 1;
+        res
+    }
+
+    fn eval_impl(&mut self, expr_ptr: &ExprPtr, env: &Env) -> Result<Value, Stop> {
+        let e = expr_ptr.borrow().clone();
+        match &e {
+            Expr::Boolean(b) => Ok(Value::Term(Rc::new(Term::Boolean(*b)))),
+            Expr::Integer(x) => Ok(Value::Term(Rc::new(Term::Integer(*x)))),
+            Expr::BigInteger(_) => unsupported("integer beyond i64"),
+            Expr::String(s) => Ok(Value::Str(s.clone())),
+            Expr::Var(x) => match lookup(env, *x) {
+                Some(thunk) => self.force(&thunk),
+                None => fails("unbound variable"),
+            },
+            Expr::Lambda(x, body) => Ok(Value::Closure(*x, body.clone(), env.clone())),
+            Expr::Unary(op, a) => {
+                let a = self.eval(a, env)?;
+                self.unary(*op, a)
+            }
+            Expr::Binary('$', f, arg) => match self.eval(f, env)? {
+                Value::Closure(x, body, closure_env) => {
+                    let thunk = Rc::new(RefCell::new(Delayed::Pending(arg.clone(), env.clone())));
+                    self.eval(&body, &bind(x, thunk, &closure_env))
+                }
+                Value::Opaque(i) => opaque(i),
+                _ => fails("application of a non-function"),
+            },
+            Expr::Binary(op, a, b) => {
+                let a = self.eval(a, env)?;
+                let b = self.eval(b, env)?;
+                self.binary(*op, a, b)
+            }
+            Expr::If(cond, then, otherwise) => {
+                let taken = match self.eval(cond, env)? {
+                    Value::Term(t) => match *t {
+                        Term::Boolean(b) => b,
+                        _ if t.sort() == Sort::Bool => self.branch(&t),
+                        _ => return fails("condition is not a boolean"),
+                    },
+                    Value::Opaque(i) => return opaque(i),
+                    _ => return fails("condition is not a boolean"),
+                };
+                let branch = if taken { then } else { otherwise };
+                self.eval(branch, env)
+            }
+        }
+    }
+}
+
+// Every path of `expr` with its parameter `param` symbolic, in the order
+// that takes the `then` branch first
+pub fn execute(expr: &Expr, param: usize, options: &Options) -> Result<Vec<Path>, Error> {
+    let mut body = as_ptr(expr.clone());
+    let mut env: Env = None;
+    for i in 0..=param {
+        let (x, inner) = match &*body.borrow() {
+            Expr::Lambda(x, inner) => (*x, inner.clone()),
+            _ => return Err(Error::MissingParameter(param)),
+        };
+        let value = if i == param {
+            Value::Term(Rc::new(Term::Input))
+        } else {
+            Value::Opaque(i)
+        };
+        env = bind(x, Rc::new(RefCell::new(Delayed::Done(Ok(value)))), &env);
+        body = inner;
+    }
+    let mut paths = Vec::new();
+    let mut decisions = Vec::new();
+    loop {
+        if paths.len() == options.max_paths {
+            return Err(Error::PathLimit);
+        }
+        let mut executor = Executor {
+            options,
+            decisions,
+            next: 0,
+            conditions: Vec::new(),
+            steps: 0,
+            depth: 0,
+        };
+        let output = match executor.eval(&body, &env) {
+            Ok(Value::Term(t)) => Output::Term(t),
+            Ok(Value::Str(s)) => Output::Str(s),
+            Ok(Value::Closure(_, _, _)) => Output::Function,
+            Ok(Value::Opaque(i)) => {
+                let what = format!("returns parameter {}", i);
+                return Err(Error::Unsupported(what));
+            }
+            Err(Stop::Fails(what)) => Output::Error(what),
+            Err(Stop::Abort(e)) => return Err(e),
+        };
+        paths.push(Path {
+            conditions: executor.conditions,
+            output,
+        });
+        // The next path flips the last `then` choice
+        decisions = executor.decisions;
+        while decisions.last() == Some(&false) {
+            decisions.pop();
+        }
+        match decisions.last_mut() {
+            Some(last) => *last = false,
+            None => break,
+        }
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recursion::extract_recursion;
+
+    fn paths(program: &str, param: usize) -> Result<Vec<(Vec<String>, String)>, Error> {
+        let expr = parse_into_ast(program.to_string());
+        let paths = execute(&expr.borrow(), param, &Options::default())?;
+        let res = paths
+            .iter()
+            .map(|path| {
+                let conditions = path.conditions.iter().map(|c| c.to_string()).collect();
+                let output = match &path.output {
+                    Output::Term(t) => t.to_string(),
+                    output => format!("{:?}", output),
+                };
+                (conditions, output)
+            })
+            .collect();
+        Ok(res)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        // Bit 1 of the input
+        let res = paths("L! B= B% B/ v! I# I# I\"", 0).unwrap();
+        assert_eq!(res, vec![(vec![], "(((x / 2) % 2) = 1)".to_string())]);
+        // Constants are folded, a symbolic divisor must not be 0
+        let res = paths("L! B/ B* I# I$ B- v! I\"", 0).unwrap();
+        assert_eq!(res[0].0, vec!["!((x - 1) = 0)"]);
+        assert_eq!(res[0].1, "(6 / (x - 1))");
+    }
+
+    #[test]
+    fn test_branches() {
+        let res = paths("L! ? B< v! I+ B* v! I# ? B= v! I, S U- v!", 0).unwrap();
+        let expected = [
+            (vec!["(x < 10)"], "(x * 2)"),
+            (vec!["!(x < 10)", "(x = 11)"], "Str(\"\")"),
+            (vec!["!(x < 10)", "!(x = 11)"], "-x"),
+        ];
+        let expected: Vec<(Vec<String>, String)> = expected
+            .iter()
+            .map(|(c, o)| (c.iter().map(|c| c.to_string()).collect(), o.to_string()))
+            .collect();
+        assert_eq!(res, expected);
+        // The argument is only evaluated when it is used, and once
+        let res = paths("L! B$ L\" ? v\" I! I\" B< v! I!", 0).unwrap();
+        assert_eq!(res.len(), 2);
+        let res = paths("L! B$ L\" I! ? v! I! I\"", 0).unwrap();
+        assert_eq!(res, vec![(vec![], "0".to_string())]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let res = paths("L! B+ v! S!", 0).unwrap();
+        assert!(res[0].1.starts_with("Error("));
+        assert_eq!(paths("L! L\" B+ v! I!", 0).unwrap()[0].1, "Function");
+        let res = paths("L! L\" B+ v! v\"", 1);
+        assert!(matches!(res, Err(Error::Unsupported(_))));
+        assert_eq!(paths("L! v!", 1), Err(Error::MissingParameter(1)));
+        assert!(matches!(paths("L! U$ v!", 0), Err(Error::Unsupported(_))));
+        // Recursion on the input never finishes
+        let program = "L! B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L$ L% ? B= v% I! I! B$ v$ B- v% I\" v!";
+        assert_eq!(paths(program, 0), Err(Error::StepLimit));
+    }
+
+    #[test]
+    fn test_problem_7() {
+        // The condition of the search loop, with bits of `x` as variables
+        let text = fs::read_to_string("problems/7.txt").unwrap();
+        let extracted = extract_recursion(&parse_into_ast(text.trim().to_string()).borrow());
+        let def = &extracted.definitions[0];
+        let validator = Expr::Lambda(def.params[0], def.body.clone());
+        let paths = execute(&validator, 0, &Options::default()).unwrap();
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0].output, Output::Term(Rc::new(Term::Input)));
+        // The other path calls the function, which is not bound here
+        assert!(matches!(paths[1].output, Output::Error(_)));
+        let answer = 584302217761;
+        let constraint = paths[0].constraint(&Expr::Integer(answer)).unwrap();
+        assert_eq!(evaluate(&constraint, answer), Some(Expr::Boolean(true)));
+        assert_eq!(
+            evaluate(&constraint, answer - 1),
+            Some(Expr::Boolean(false))
+        );
+        assert_eq!(paths[1].constraint(&Expr::Integer(answer)), None);
+    }
+}
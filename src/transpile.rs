@@ -0,0 +1,724 @@
+use crate::recursion::{extract_recursion, top_level, Definition, Extracted};
+use crate::scope::free_vars;
+use crate::types::{infer_extracted, Type};
+use crate::*;
+
+use std::collections::BTreeSet;
+
+// Compiles a program to a standalone Rust module on top of `native`:
+//
+//   use icfpc_2024::native::*;
+//
+//   fn f0(x4: Thunk) -> Value {
+//       if lt(x4.force(), int(2)).as_bool() {
+//           int(1)
+//       } else {
+//           add(f0(Thunk::new(...)), f0(Thunk::new(...)))
+//       }
+//   }
+//
+//   pub fn run() -> Value {
+//       f0(Thunk::ready(int(40)))
+//   }
+//
+//   fn main() {
+//       run_main(run);
+//   }
+//
+// Put into `src/bin/`, it runs the program natively and prints the result.
+//
+// Variables hold `Thunk`s and lambdas become `Value::fun` closures. Closures
+// own their variables, so the ones they use are cloned first, which only
+// copies an `Rc`. Recursion is extracted as in `recursion`: definitions that
+// only use their parameters and each other become `fn` items called with all
+// their arguments at once, the others a `Thunk::fix` where they were defined.
+//
+// An argument is evaluated before the call when the parameter is an integer
+// the definition forces on every path, see `strict_params`. Otherwise an
+// accumulator like `f (n + 1) (acc * n)` builds a chain of thunks as long as
+// the loop, and forcing it at the end overflows the stack. Deep recursion
+// itself still needs the large stack of `run_main`.
+//
+// The output only depends on the program, so it can be checked in, see
+// tests/transpiled/cases.rs.
+
+const INDENT: &str = "    ";
+
+// Longest `if` written on one line
+const MAX_INLINE_IF: usize = 60;
+
+enum Binding {
+    Local(String),
+    // Name and parameters of a top-level `fn`
+    Function(String, Vec<i64>),
+}
+
+struct Transpiler {
+    // Definitions not written yet, by variable
+    pending: HashMap<i64, Definition>,
+    // Innermost last
+    scope: Vec<(i64, Binding)>,
+    // By definition, see `strict_params`
+    strict: HashMap<i64, Vec<bool>>,
+    functions: usize,
+}
+
+fn var_name(x: i64) -> String {
+    format!("x{}", x)
+}
+
+fn indented(indent: usize, text: &str) -> String {
+    format!("{}{}", INDENT.repeat(indent), text)
+}
+
+// Moves the lines after the first one a level left
+fn dedent(text: &str) -> String {
+    text.replace(&format!("\n{}", INDENT), "\n")
+}
+
+fn literal(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Boolean(b) => Some(format!("Value::Bool({})", b)),
+        Expr::Integer(x) => Some(format!("int({})", x)),
+        Expr::BigInteger(x) => Some(format!("big_int(\"{}\")", x)),
+        // Debug output is a valid Rust literal
+        Expr::String(s) => Some(format!("string({:?})", s)),
+        _ => None,
+    }
+}
+
+fn operator(op: char) -> &'static str {
+    match op {
+        '-' => "neg",
+        '!' => "not",
+        '#' => "str_to_int",
+        '$' => "int_to_str",
+        _ => panic!("Unexpected op: {}", op),
+    }
+}
+
+fn binary_operator(op: char) -> &'static str {
+    match op {
+        '+' => "add",
+        '-' => "sub",
+        '*' => "mul",
+        '/' => "div",
+        '%' => "rem",
+        '<' => "lt",
+        '>' => "gt",
+        '=' => "eq",
+        '|' => "or",
+        '&' => "and",
+        '.' => "concat",
+        'T' => "take",
+        'D' => "drop",
+        _ => panic!("Unexpected op: {}", op),
+    }
+}
+
+// `B$ (L x body) arg`
+fn as_let(expr: &Expr) -> Option<(i64, ExprPtr, ExprPtr)> {
+    let Expr::Binary('$', f, arg) = expr else {
+        return None;
+    };
+    let Expr::Lambda(x, body) = &*f.borrow() else {
+        return None;
+    };
+    Some((*x, body.clone(), arg.clone()))
+}
+
+fn lambda(params: &[i64], body: &ExprPtr) -> ExprPtr {
+    params
+        .iter()
+        .rev()
+        .fold(body.clone(), |e, x| as_ptr(Expr::Lambda(*x, e)))
+}
+
+// Whether evaluating `expr` always forces `x`, `strict` being the
+// parameters each definition forces
+fn forces(expr_ptr: &ExprPtr, x: i64, strict: &HashMap<i64, Vec<bool>>) -> bool {
+    let e = expr_ptr.borrow();
+    if let Some((y, body, arg)) = as_let(&e) {
+        return (y != x && forces(&body, x, strict))
+            || (forces(&body, y, strict) && forces(&arg, x, strict));
+    }
+    match &*e {
+        Expr::Var(y) => *y == x,
+        Expr::Unary(_, a) => forces(a, x, strict),
+        Expr::Binary('$', f, arg) => {
+            let mut args = vec![arg.clone()];
+            let mut head = f.clone();
+            loop {
+                let f = match &*head.borrow() {
+                    Expr::Binary('$', f, arg) if as_let(&head.borrow()).is_none() => {
+                        args.push(arg.clone());
+                        f.clone()
+                    }
+                    _ => break,
+                };
+                head = f;
+            }
+            args.reverse();
+            let called = match &*head.borrow() {
+                // Fewer arguments only make a closure
+                Expr::Var(f) => strict.get(f).filter(|s| s.len() <= args.len()),
+                _ => None,
+            };
+            let forced_arg = called.is_some_and(|s| {
+                s.iter()
+                    .zip(args.iter())
+                    .any(|(strict_param, arg)| *strict_param && forces(arg, x, strict))
+            });
+            forced_arg || forces(&head, x, strict)
+        }
+        Expr::Binary(_, a, b) => forces(a, x, strict) || forces(b, x, strict),
+        Expr::If(cond, then, otherwise) => {
+            forces(cond, x, strict) || (forces(then, x, strict) && forces(otherwise, x, strict))
+        }
+        _ => false,
+    }
+}
+
+// For each definition, which parameters are integers it forces whenever it
+// returns. Evaluating their arguments first gives the same result.
+fn strict_params(extracted: &Extracted) -> HashMap<i64, Vec<bool>> {
+    let typing = Some(infer_extracted(extracted)).filter(|t| t.errors.is_empty());
+    let mut strict: HashMap<i64, Vec<bool>> = HashMap::new();
+    for def in extracted.definitions.iter() {
+        let n = def.params.len();
+        let ints = match typing
+            .as_ref()
+            .and_then(|t| t.definition(def.name)?.split(n))
+        {
+            Some((args, _)) => args.iter().map(|t| *t == Type::Int).collect(),
+            None => vec![false; n],
+        };
+        strict.insert(def.name, ints);
+    }
+    // Recursive calls are assumed to force what the definition does, until
+    // a path shows otherwise
+    loop {
+        let mut changed = false;
+        for def in extracted.definitions.iter() {
+            for (i, x) in def.params.iter().enumerate() {
+                let shadowed = def.params[i + 1..].contains(x);
+                if strict[&def.name][i] && (shadowed || !forces(&def.body, *x, &strict)) {
+                    strict.get_mut(&def.name).unwrap()[i] = false;
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            return strict;
+        }
+    }
+}
+
+impl Transpiler {
+    fn lookup(&self, x: i64) -> Option<&Binding> {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(y, _)| *y == x)
+            .map(|(_, b)| b)
+    }
+
+    fn fresh_function(&mut self) -> String {
+        let name = format!("f{}", self.functions);
+        self.functions += 1;
+        name
+    }
+
+    // `expr` with the pending definitions put back where they are used, so
+    // it has their free variables. `inside` are the definitions being put
+    // back, which refer to themselves.
+    fn expanded_impl(&self, expr_ptr: &ExprPtr, inside: &mut Vec<i64>) -> ExprPtr {
+        let e = expr_ptr.borrow().clone();
+        let mut sub = |a: &ExprPtr| self.expanded_impl(a, inside);
+        let res = match &e {
+            Expr::Var(x) => match self.pending.get(x) {
+                Some(def) if !inside.contains(x) => {
+                    inside.push(*x);
+                    let f = self.expanded_impl(&lambda(&def.params, &def.body), inside);
+                    inside.pop();
+                    Expr::Lambda(*x, f)
+                }
+                _ => return expr_ptr.clone(),
+            },
+            Expr::Unary(op, a) => Expr::Unary(*op, sub(a)),
+            Expr::Binary(op, a, b) => {
+                let a = sub(a);
+                Expr::Binary(*op, a, sub(b))
+            }
+            Expr::If(a, b, c) => {
+                let a = sub(a);
+                let b = sub(b);
+                Expr::If(a, b, sub(c))
+            }
+            Expr::Lambda(x, a) => Expr::Lambda(*x, sub(a)),
+            _ => return expr_ptr.clone(),
+        };
+        as_ptr(res)
+    }
+
+    fn expanded(&self, expr_ptr: &ExprPtr) -> ExprPtr {
+        self.expanded_impl(expr_ptr, &mut Vec::new())
+    }
+
+    // Local variables a closure computing `expr` has to own
+    fn captures(&self, expr_ptr: &ExprPtr) -> BTreeSet<String> {
+        free_vars(&self.expanded(expr_ptr).borrow())
+            .into_iter()
+            .filter_map(|x| match self.lookup(x) {
+                Some(Binding::Local(name)) => Some(name.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Wraps the closure `write` returns in a block that clones what it
+    // captures, `expr` being what the closure computes
+    fn closure<F>(&mut self, expr_ptr: &ExprPtr, indent: usize, write: F) -> String
+    where
+        F: FnOnce(&mut Self) -> String,
+    {
+        // Before writing, which takes the pending definitions
+        let captures = self.captures(expr_ptr);
+        let text = write(self);
+        if captures.is_empty() {
+            return text;
+        }
+        let mut lines = vec!["{".to_string()];
+        for name in captures.iter() {
+            lines.push(indented(
+                indent + 1,
+                &format!("let {} = {}.clone();", name, name),
+            ));
+        }
+        // `text` was written for `indent`
+        let text = text.replace('\n', &format!("\n{}", INDENT));
+        lines.push(indented(indent + 1, &text));
+        lines.push(indented(indent, "}"));
+        lines.join("\n")
+    }
+
+    fn param_name(x: i64, body: &Expr) -> String {
+        if free_vars(body).contains(&x) {
+            var_name(x)
+        } else {
+            "_".to_string()
+        }
+    }
+
+    // `move |x1| ... move |xn| body` as nested `Value::fun` closures
+    fn function_value(&mut self, params: &[i64], body: &ExprPtr, indent: usize) -> String {
+        let Some((x, rest)) = params.split_first() else {
+            return self.value(body, indent);
+        };
+        let inner = lambda(rest, body);
+        let name = Self::param_name(*x, &self.expanded(&inner).borrow());
+        self.scope.push((*x, Binding::Local(var_name(*x))));
+        let text = if rest.is_empty() {
+            self.body(body, indent)
+        } else {
+            self.closure(&inner, indent, |t| t.function_value(rest, body, indent))
+        };
+        self.scope.pop();
+        format!("Value::fun(move |{}| {})", name, text)
+    }
+
+    // Closure body: an expression, or a block when it needs several lines
+    fn body(&mut self, expr_ptr: &ExprPtr, indent: usize) -> String {
+        let lines = self.block(expr_ptr, indent + 1);
+        if let [line] = &lines[..] {
+            return dedent(line.trim_start());
+        }
+        format!("{{\n{}\n{}}}", lines.join("\n"), INDENT.repeat(indent))
+    }
+
+    fn define(&mut self, x: i64, indent: usize) -> String {
+        let def = self.pending.remove(&x).unwrap();
+        let name = self.fresh_function();
+        let f = as_ptr(Expr::Lambda(x, lambda(&def.params, &def.body)));
+        self.closure(&f, indent, |t| {
+            t.scope.push((x, Binding::Local(name.clone())));
+            let text = t.function_value(&def.params, &def.body, indent);
+            t.scope.pop();
+            format!("Thunk::fix(move |{}| {})", name, text)
+        })
+    }
+
+    // The `fn` for `x` as a value, a curried closure once it has parameters
+    fn function_item(&mut self, x: i64, name: &str, params: &[i64], indent: usize) -> String {
+        match params.len() {
+            0 => format!("{}()", name),
+            1 => format!("Value::fun({})", name),
+            _ => {
+                let call = params.iter().fold(as_ptr(Expr::Var(x)), |f, p| {
+                    as_ptr(Expr::Binary('$', f, as_ptr(Expr::Var(*p))))
+                });
+                self.value(&lambda(params, &call), indent)
+            }
+        }
+    }
+
+    fn thunk(&mut self, expr_ptr: &ExprPtr, indent: usize) -> String {
+        let e = expr_ptr.borrow().clone();
+        if let Some(text) = literal(&e) {
+            return format!("Thunk::ready({})", text);
+        }
+        // Nothing to evaluate
+        if let Expr::Lambda(_, _) = e {
+            return format!("Thunk::ready({})", self.value(expr_ptr, indent));
+        }
+        if let Expr::Var(x) = e {
+            if self.pending.contains_key(&x) {
+                return self.define(x, indent);
+            }
+            match self.lookup(x) {
+                Some(Binding::Local(name)) => return format!("{}.clone()", name),
+                Some(Binding::Function(name, params)) if params.is_empty() => {
+                    return format!("Thunk::new({})", name);
+                }
+                Some(Binding::Function(name, params)) => {
+                    let (name, params) = (name.clone(), params.clone());
+                    let value = self.function_item(x, &name, &params, indent);
+                    return format!("Thunk::ready({})", value);
+                }
+                None => {}
+            }
+        }
+        self.closure(expr_ptr, indent, |t| {
+            format!("Thunk::new(move || {})", t.value(expr_ptr, indent))
+        })
+    }
+
+    // Evaluated already when the parameter is strict
+    fn argument(&mut self, expr_ptr: &ExprPtr, strict: bool, indent: usize) -> String {
+        if strict && literal(&expr_ptr.borrow()).is_none() {
+            return format!("Thunk::ready({})", self.value(expr_ptr, indent));
+        }
+        self.thunk(expr_ptr, indent)
+    }
+
+    fn value(&mut self, expr_ptr: &ExprPtr, indent: usize) -> String {
+        let e = expr_ptr.borrow().clone();
+        if let Some(text) = literal(&e) {
+            return text;
+        }
+        if let Expr::If(_, _, _) = e {
+            let lines = self.block(expr_ptr, indent);
+            return lines.join("\n").trim_start().to_string();
+        }
+        if as_let(&e).is_some() {
+            return self.body(expr_ptr, indent);
+        }
+        match &e {
+            &Expr::Var(x) => {
+                if self.pending.contains_key(&x) {
+                    return format!("{}.force()", self.define(x, indent));
+                }
+                match self.lookup(x) {
+                    Some(Binding::Local(name)) => format!("{}.force()", name),
+                    Some(Binding::Function(name, params)) => {
+                        let (name, params) = (name.clone(), params.clone());
+                        self.function_item(x, &name, &params, indent)
+                    }
+                    None => format!("unbound({})", x),
+                }
+            }
+            Expr::Lambda(x, body) => {
+                self.closure(expr_ptr, indent, |t| t.function_value(&[*x], body, indent))
+            }
+            Expr::Unary(op, a) => format!("{}({})", operator(*op), self.value(a, indent)),
+            Expr::Binary('$', _, _) => {
+                let mut args = Vec::new();
+                let mut head = expr_ptr.clone();
+                while as_let(&head.borrow()).is_none() {
+                    let f = match &*head.borrow() {
+                        Expr::Binary('$', f, arg) => {
+                            args.push(arg.clone());
+                            f.clone()
+                        }
+                        _ => break,
+                    };
+                    head = f;
+                }
+                args.reverse();
+                let function = match &*head.borrow() {
+                    Expr::Var(x) => match self.lookup(*x) {
+                        Some(Binding::Function(name, params)) if args.len() >= params.len() => {
+                            Some((name.clone(), params.len()))
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let strict = match &*head.borrow() {
+                    Expr::Var(x) => self.strict.get(x).filter(|s| s.len() <= args.len()),
+                    _ => None,
+                };
+                let strict = strict.cloned().unwrap_or_default();
+                let (mut res, arity) = match function {
+                    Some((name, arity)) => {
+                        let call_args: Vec<String> = args[..arity]
+                            .iter()
+                            .zip(strict.iter())
+                            .map(|(arg, strict)| self.argument(arg, *strict, indent))
+                            .collect();
+                        (format!("{}({})", name, call_args.join(", ")), arity)
+                    }
+                    None => (self.value(&head, indent), 0),
+                };
+                for (i, arg) in args.iter().enumerate().skip(arity) {
+                    let strict = strict.get(i) == Some(&true);
+                    res = format!("apply({}, {})", res, self.argument(arg, strict, indent));
+                }
+                res
+            }
+            Expr::Binary(op, a, b) => {
+                let a = self.value(a, indent);
+                let b = self.value(b, indent);
+                format!("{}({}, {})", binary_operator(*op), a, b)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Statements ending in the value of `expr`, each line indented
+    fn block(&mut self, expr_ptr: &ExprPtr, indent: usize) -> Vec<String> {
+        let e = expr_ptr.borrow().clone();
+        if let Some((x, body, arg)) = as_let(&e) {
+            let mut lines = Vec::new();
+            // Unused, so never evaluated
+            if free_vars(&self.expanded(&body).borrow()).contains(&x) {
+                let value = self.thunk(&arg, indent);
+                lines.push(indented(
+                    indent,
+                    &format!("let {} = {};", var_name(x), value),
+                ));
+            }
+            self.scope.push((x, Binding::Local(var_name(x))));
+            lines.extend(self.block(&body, indent));
+            self.scope.pop();
+            return lines;
+        }
+        let Expr::If(cond, then, otherwise) = &e else {
+            return vec![indented(indent, &self.value(expr_ptr, indent))];
+        };
+        let cond = self.value(cond, indent);
+        let then = self.block(then, indent + 1);
+        let otherwise_lines = self.block(otherwise, indent + 1);
+        if let ([a], [b]) = (&then[..], &otherwise_lines[..]) {
+            let text = format!(
+                "if {}.as_bool() {{ {} }} else {{ {} }}",
+                cond,
+                a.trim_start(),
+                b.trim_start()
+            );
+            if !text.contains('\n') && text.len() <= MAX_INLINE_IF {
+                return vec![indented(indent, &text)];
+            }
+        }
+        let mut lines = vec![indented(indent, &format!("if {}.as_bool() {{", cond))];
+        lines.extend(then);
+        if let [first, rest @ ..] = &otherwise_lines[..] {
+            // Chains of `if`s read as `else if`
+            let is_chain = matches!(*otherwise.borrow(), Expr::If(_, _, _))
+                && first.trim_start().starts_with("if ")
+                && !rest.is_empty();
+            if is_chain {
+                lines.push(indented(indent, &format!("}} else {}", first.trim_start())));
+                for line in rest.iter() {
+                    lines.push(dedent(&line[INDENT.len()..]));
+                }
+                return lines;
+            }
+        }
+        lines.push(indented(indent, "} else {"));
+        lines.extend(otherwise_lines);
+        lines.push(indented(indent, "}"));
+        lines
+    }
+}
+
+pub fn transpile(expr: &Expr) -> String {
+    let extracted = extract_recursion(expr);
+    let top = top_level(&extracted.definitions);
+    let mut transpiler = Transpiler {
+        pending: HashMap::new(),
+        scope: Vec::new(),
+        strict: strict_params(&extracted),
+        functions: 0,
+    };
+    let mut items = Vec::new();
+    for def in extracted.definitions.iter() {
+        if !top.contains(&def.name) {
+            transpiler.pending.insert(def.name, def.clone());
+            continue;
+        }
+        let name = transpiler.fresh_function();
+        transpiler.scope.push((
+            def.name,
+            Binding::Function(name.clone(), def.params.clone()),
+        ));
+        let params: Vec<String> = def
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let rest = lambda(&def.params[i + 1..], &def.body);
+                let used = free_vars(&transpiler.expanded(&rest).borrow()).contains(x);
+                format!("{}{}: Thunk", if used { "" } else { "_" }, var_name(*x))
+            })
+            .collect();
+        // Parameters go below the functions so they shadow nothing
+        let depth = transpiler.scope.len();
+        for x in def.params.iter() {
+            transpiler.scope.push((*x, Binding::Local(var_name(*x))));
+        }
+        let mut lines = vec![format!("fn {}({}) -> Value {{", name, params.join(", "))];
+        lines.extend(transpiler.block(&def.body, 1));
+        lines.push("}".to_string());
+        transpiler.scope.truncate(depth);
+        items.push(lines.join("\n"));
+    }
+    let mut lines = vec!["pub fn run() -> Value {".to_string()];
+    lines.extend(transpiler.block(&extracted.main, 1));
+    lines.push("}".to_string());
+    items.push(lines.join("\n"));
+    items.push("fn main() {\n    run_main(run);\n}".to_string());
+    format!("use icfpc_2024::native::*;\n\n{}\n", items.join("\n\n"))
+}
+
+// The checked-in tests/transpiled/cases.rs: a module per case, and a list
+// of them to run
+pub fn transpile_cases(cases: &[conformance::Case]) -> String {
+    let mut res = vec![
+        "// Generated by `transpile --cases tests/conformance.txt`, do not edit.".to_string(),
+        String::new(),
+        "use icfpc_2024::native::Value;".to_string(),
+        String::new(),
+        "pub type Case = (&'static str, fn() -> Value);".to_string(),
+        String::new(),
+    ];
+    let mut names = Vec::new();
+    for case in cases.iter() {
+        let Ok(expr) = parser::parse_checked(&case.program) else {
+            continue;
+        };
+        let name = case.name.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+        res.push("#[allow(dead_code)]".to_string());
+        res.push(format!("pub mod {} {{", name));
+        for line in transpile(&expr.borrow()).lines() {
+            res.push(if line.is_empty() {
+                String::new()
+            } else {
+                indented(1, line)
+            });
+        }
+        res.push("}".to_string());
+        res.push(String::new());
+        names.push((case.name.clone(), name));
+    }
+    res.push("pub fn cases() -> Vec<Case> {".to_string());
+    res.push(indented(1, "vec!["));
+    for (case, name) in names.iter() {
+        res.push(indented(2, &format!("({:?}, {}::run),", case, name)));
+    }
+    res.push(indented(1, "]"));
+    res.push("}".to_string());
+    res.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transpiled(program: &str) -> String {
+        transpile(&parse_into_ast(program.to_string()).borrow())
+    }
+
+    #[test]
+    fn test_problem_4() {
+        let text = fs::read_to_string("problems/4.txt").unwrap();
+        let expected = [
+            "use icfpc_2024::native::*;",
+            "",
+            "fn f0(x4: Thunk) -> Value {",
+            "    if lt(x4.force(), int(2)).as_bool() {",
+            "        int(1)",
+            "    } else {",
+            "        add(f0(Thunk::ready(sub(x4.force(), int(1)))), f0(Thunk::ready(sub(x4.force(), int(2)))))",
+            "    }",
+            "}",
+            "",
+            "pub fn run() -> Value {",
+            "    f0(Thunk::ready(int(40)))",
+            "}",
+            "",
+            "fn main() {",
+            "    run_main(run);",
+            "}",
+            "",
+        ];
+        assert_eq!(transpiled(text.trim()), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_lets() {
+        let text = transpiled("B$ L# B$ L$ B+ v# v# I\" B$ L% v% I#");
+        let expected = [
+            "pub fn run() -> Value {",
+            "    let x2 = Thunk::new(move || {",
+            "        let x4 = Thunk::ready(int(2));",
+            "        x4.force()",
+            "    });",
+            "    add(x2.force(), x2.force())",
+            "}",
+        ];
+        assert!(text.contains(&expected.join("\n")), "{}", text);
+        // `v$` is unused, so its value is never needed
+        let text = transpiled("B$ L$ I\" B/ I! I!");
+        assert!(
+            text.contains("pub fn run() -> Value {\n    int(1)\n}"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let text = fs::read_to_string("problems/5.txt").unwrap();
+        assert_eq!(transpiled(text.trim()), transpiled(text.trim()));
+    }
+
+    #[test]
+    fn test_checked_in_cases() {
+        // Regenerate with `cargo run --bin transpile -- --cases
+        // tests/conformance.txt > tests/transpiled/cases.rs`
+        let text = fs::read_to_string("tests/conformance.txt").unwrap();
+        let cases = conformance::parse_cases(&text, ".").unwrap();
+        let expected = fs::read_to_string("tests/transpiled/cases.rs").unwrap();
+        assert!(
+            transpile_cases(&cases) == expected,
+            "cases.rs is out of date"
+        );
+    }
+
+    #[test]
+    fn test_checked_in_problems() {
+        // Regenerate with `cargo run --bin transpile -- problems/<n>.txt >
+        // tests/transpiled/problem_<n>.rs`
+        for n in [1, 12] {
+            let text = fs::read_to_string(format!("problems/{}.txt", n)).unwrap();
+            let path = format!("tests/transpiled/problem_{}.rs", n);
+            let expected = fs::read_to_string(&path).unwrap();
+            assert!(
+                transpiled(text.trim()) == expected,
+                "{} is out of date",
+                path
+            );
+        }
+    }
+}
@@ -0,0 +1,593 @@
+use crate::recursion::{fixpoint_combinator, Definition, Extracted};
+use crate::*;
+
+use std::collections::HashSet;
+
+// Hindley-Milner type inference. Types are `int`, `bool`, `str`, functions
+// and type variables, `B$ (L x body) arg` is a `let`, so `x` can be used at
+// several types. `=` compares any two values of the same type.
+//
+// The fixed-point combinators apply a term to itself and have no type. In
+// permissive mode the ones `recursion` recognises are given the type they
+// would have, `('a -> 'a) -> 'a`, instead of being checked.
+//
+// Inference goes on after an error with the types it has, so one pass finds
+// every ill-typed subterm. Errors carry the path to the subterm, child
+// indices as in `children`, so they can be reported before evaluating.
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Type {
+    Int,
+    Bool,
+    Str,
+    Var(usize),
+    Fun(Box<Type>, Box<Type>),
+}
+
+fn fun(a: Type, b: Type) -> Type {
+    Type::Fun(Box::new(a), Box::new(b))
+}
+
+fn var_name(n: usize) -> String {
+    let letter = (b'a' + (n % 26) as u8) as char;
+    match n / 26 {
+        0 => format!("'{}", letter),
+        k => format!("'{}{}", letter, k),
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Bool => write!(f, "bool"),
+            Type::Str => write!(f, "str"),
+            Type::Var(n) => write!(f, "{}", var_name(*n)),
+            Type::Fun(a, b) => match **a {
+                Type::Fun(_, _) => write!(f, "({}) -> {}", a, b),
+                _ => write!(f, "{} -> {}", a, b),
+            },
+        }
+    }
+}
+
+impl Type {
+    fn vars(&self, res: &mut Vec<usize>) {
+        match self {
+            Type::Var(n) if !res.contains(n) => res.push(*n),
+            Type::Fun(a, b) => {
+                a.vars(res);
+                b.vars(res);
+            }
+            _ => (),
+        }
+    }
+
+    fn rename(&self, names: &HashMap<usize, Type>) -> Type {
+        match self {
+            Type::Var(n) => names.get(n).cloned().unwrap_or(Type::Var(*n)),
+            Type::Fun(a, b) => fun(a.rename(names), b.rename(names)),
+            _ => self.clone(),
+        }
+    }
+
+    // Variables renamed to 'a, 'b, ... in order of appearance
+    pub fn normalized(&self) -> Type {
+        let mut vars = Vec::new();
+        self.vars(&mut vars);
+        let names = vars
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (*n, Type::Var(i)))
+            .collect();
+        self.rename(&names)
+    }
+
+    // Argument types and result of a function taking `n` arguments
+    pub fn split(&self, n: usize) -> Option<(Vec<Type>, Type)> {
+        let mut args = Vec::new();
+        let mut res = self;
+        for _ in 0..n {
+            let Type::Fun(a, b) = res else {
+                return None;
+            };
+            args.push((**a).clone());
+            res = b;
+        }
+        Some((args, res.clone()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeError {
+    pub path: Vec<usize>,
+    pub expected: Type,
+    pub found: Type,
+}
+
+impl TypeError {
+    // Whether the two types only unify as an infinite type, as in `x x`
+    pub fn is_infinite(&self) -> bool {
+        let occurs = |a: &Type, b: &Type| {
+            let mut vars = Vec::new();
+            b.vars(&mut vars);
+            matches!(a, Type::Var(n) if vars.contains(n)) && a != b
+        };
+        occurs(&self.expected, &self.found) || occurs(&self.found, &self.expected)
+    }
+}
+
+pub fn path_string(path: &[usize]) -> String {
+    if path.is_empty() {
+        return "root".to_string();
+    }
+    let parts: Vec<String> = path.iter().map(|i| i.to_string()).collect();
+    parts.join(".")
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Together, so shared variables keep the same name
+        let Type::Fun(expected, found) =
+            fun(self.expected.clone(), self.found.clone()).normalized()
+        else {
+            unreachable!();
+        };
+        if self.is_infinite() {
+            // The variable first
+            let (a, b) = match *found {
+                Type::Var(_) => (found, expected),
+                _ => (expected, found),
+            };
+            write!(
+                f,
+                "at {}: infinite type {} = {}",
+                path_string(&self.path),
+                a,
+                b
+            )
+        } else {
+            write!(
+                f,
+                "at {}: expected {}, found {}",
+                path_string(&self.path),
+                expected,
+                found
+            )
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    // Type the fixed-point combinators instead of checking them
+    pub permissive: bool,
+}
+
+#[derive(Clone, Debug)]
+struct Scheme {
+    vars: Vec<usize>,
+    ty: Type,
+}
+
+impl Scheme {
+    fn mono(ty: Type) -> Scheme {
+        Scheme {
+            vars: Vec::new(),
+            ty,
+        }
+    }
+}
+
+fn unary_type(op: char) -> (Type, Type) {
+    match op {
+        '-' => (Type::Int, Type::Int),
+        '!' => (Type::Bool, Type::Bool),
+        '#' => (Type::Str, Type::Int),
+        '$' => (Type::Int, Type::Str),
+        _ => panic!("Unexpected op: {}", op),
+    }
+}
+
+// Operand and result types, except for `=` and `$`
+fn binary_type(op: char) -> (Type, Type, Type) {
+    match op {
+        '+' | '-' | '*' | '/' | '%' => (Type::Int, Type::Int, Type::Int),
+        '<' | '>' => (Type::Int, Type::Int, Type::Bool),
+        '|' | '&' => (Type::Bool, Type::Bool, Type::Bool),
+        '.' => (Type::Str, Type::Str, Type::Str),
+        'T' | 'D' => (Type::Int, Type::Str, Type::Str),
+        _ => panic!("Unexpected op: {}", op),
+    }
+}
+
+struct Inferer {
+    permissive: bool,
+    // Bound type variables
+    vars: Vec<Option<Type>>,
+    // Innermost last
+    env: Vec<(i64, Scheme)>,
+    // Extracted definitions not typed yet, and the types of the others
+    pending: HashMap<i64, Definition>,
+    definitions: HashMap<i64, Scheme>,
+    path: Vec<usize>,
+    errors: Vec<TypeError>,
+    // Type of each lambda, by node
+    lambdas: HashMap<*const RefCell<Expr>, (ExprPtr, Type)>,
+}
+
+impl Inferer {
+    fn new(permissive: bool) -> Inferer {
+        Inferer {
+            permissive,
+            vars: Vec::new(),
+            env: Vec::new(),
+            pending: HashMap::new(),
+            definitions: HashMap::new(),
+            path: Vec::new(),
+            errors: Vec::new(),
+            lambdas: HashMap::new(),
+        }
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.vars.push(None);
+        Type::Var(self.vars.len() - 1)
+    }
+
+    fn resolve(&self, t: &Type) -> Type {
+        match t {
+            Type::Var(n) => match &self.vars[*n] {
+                Some(bound) => self.resolve(bound),
+                None => t.clone(),
+            },
+            Type::Fun(a, b) => fun(self.resolve(a), self.resolve(b)),
+            _ => t.clone(),
+        }
+    }
+
+    fn unify(&mut self, a: &Type, b: &Type) -> bool {
+        let (a, b) = (self.resolve(a), self.resolve(b));
+        match (&a, &b) {
+            (Type::Var(n), Type::Var(m)) if n == m => true,
+            (Type::Var(n), t) | (t, Type::Var(n)) => {
+                let mut vars = Vec::new();
+                t.vars(&mut vars);
+                if vars.contains(n) {
+                    return false;
+                }
+                self.vars[*n] = Some(t.clone());
+                true
+            }
+            (Type::Fun(a1, b1), Type::Fun(a2, b2)) => {
+                // Both, so an error in one still tells about the other
+                let args = self.unify(a1, a2);
+                self.unify(b1, b2) && args
+            }
+            _ => a == b,
+        }
+    }
+
+    // `child` is the subterm that has type `found`
+    fn expect(&mut self, found: &Type, expected: &Type, child: &[usize]) {
+        if !self.unify(found, expected) {
+            let mut path = self.path.clone();
+            path.extend_from_slice(child);
+            self.errors.push(TypeError {
+                path,
+                expected: self.resolve(expected),
+                found: self.resolve(found),
+            });
+        }
+    }
+
+    fn free_in_env(&self) -> HashSet<usize> {
+        let mut vars = Vec::new();
+        let schemes = self.env.iter().map(|(_, s)| s);
+        for scheme in schemes.chain(self.definitions.values()) {
+            let mut scheme_vars = Vec::new();
+            self.resolve(&scheme.ty).vars(&mut scheme_vars);
+            vars.extend(scheme_vars.into_iter().filter(|n| !scheme.vars.contains(n)));
+        }
+        vars.into_iter().collect()
+    }
+
+    fn generalize(&self, t: &Type) -> Scheme {
+        let ty = self.resolve(t);
+        let env = self.free_in_env();
+        let mut vars = Vec::new();
+        ty.vars(&mut vars);
+        vars.retain(|n| !env.contains(n));
+        Scheme { vars, ty }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let names = scheme.vars.iter().map(|n| (*n, self.fresh())).collect();
+        scheme.ty.rename(&names)
+    }
+
+    // `f` as `fix (L name. L params. body)`
+    fn define(&mut self, def: Definition) -> Scheme {
+        let t = self.fresh();
+        self.definitions.insert(def.name, Scheme::mono(t.clone()));
+        let f = def
+            .params
+            .iter()
+            .rev()
+            .fold(def.body.clone(), |e, x| as_ptr(Expr::Lambda(*x, e)));
+        let found = self.infer(&f);
+        self.expect(&found, &t, &[]);
+        self.definitions.remove(&def.name);
+        let scheme = self.generalize(&t);
+        self.definitions.insert(def.name, scheme.clone());
+        scheme
+    }
+
+    fn var(&mut self, x: i64) -> Type {
+        let scheme = match self.env.iter().rev().find(|(y, _)| *y == x) {
+            Some((_, scheme)) => scheme.clone(),
+            None => match self.pending.remove(&x) {
+                Some(def) => self.define(def),
+                // Free, so anything goes
+                None => match self.definitions.get(&x) {
+                    Some(scheme) => scheme.clone(),
+                    None => return self.fresh(),
+                },
+            },
+        };
+        self.instantiate(&scheme)
+    }
+
+    fn sub(&mut self, index: usize, expr_ptr: &ExprPtr) -> Type {
+        self.path.push(index);
+        let res = self.infer(expr_ptr);
+        self.path.pop();
+        res
+    }
+
+    fn infer(&mut self, expr_ptr: &ExprPtr) -> Type {
+        let e = expr_ptr.borrow().clone();
+        if self.permissive && fixpoint_combinator(&e).is_some() {
+            let a = self.fresh();
+            return fun(fun(a.clone(), a.clone()), a);
+        }
+        match &e {
+            Expr::Boolean(_) => Type::Bool,
+            Expr::Integer(_) | Expr::BigInteger(_) => Type::Int,
+            Expr::String(_) => Type::Str,
+            Expr::Var(x) => self.var(*x),
+            Expr::Lambda(x, body) => {
+                let a = self.fresh();
+                self.env.push((*x, Scheme::mono(a.clone())));
+                let b = self.sub(0, body);
+                self.env.pop();
+                let t = fun(a, b);
+                self.lambdas
+                    .insert(Rc::as_ptr(expr_ptr), (expr_ptr.clone(), t.clone()));
+                t
+            }
+            Expr::Unary(op, a) => {
+                let (arg, res) = unary_type(*op);
+                let found = self.sub(0, a);
+                self.expect(&found, &arg, &[0]);
+                res
+            }
+            Expr::Binary('$', f, arg) => {
+                let is_let = !(self.permissive && fixpoint_combinator(&f.borrow()).is_some());
+                if let (Expr::Lambda(x, body), true) = (&*f.borrow(), is_let) {
+                    // let x = arg in body
+                    let ta = self.sub(1, arg);
+                    let scheme = self.generalize(&ta);
+                    self.env.push((*x, scheme));
+                    self.path.push(0);
+                    let tb = self.sub(0, body);
+                    self.path.pop();
+                    self.env.pop();
+                    self.lambdas
+                        .insert(Rc::as_ptr(f), (f.clone(), fun(ta, tb.clone())));
+                    return tb;
+                }
+                let tf = self.sub(0, f);
+                let ta = self.sub(1, arg);
+                let res = self.fresh();
+                self.expect(&tf, &fun(ta, res.clone()), &[0]);
+                res
+            }
+            Expr::Binary('=', a, b) => {
+                let ta = self.sub(0, a);
+                let tb = self.sub(1, b);
+                self.expect(&tb, &ta, &[1]);
+                Type::Bool
+            }
+            Expr::Binary(op, a, b) => {
+                let (left, right, res) = binary_type(*op);
+                let ta = self.sub(0, a);
+                self.expect(&ta, &left, &[0]);
+                let tb = self.sub(1, b);
+                self.expect(&tb, &right, &[1]);
+                res
+            }
+            Expr::If(cond, then, otherwise) => {
+                let tc = self.sub(0, cond);
+                self.expect(&tc, &Type::Bool, &[0]);
+                let tt = self.sub(1, then);
+                let to = self.sub(2, otherwise);
+                self.expect(&to, &tt, &[2]);
+                tt
+            }
+        }
+    }
+}
+
+pub fn infer(expr: &Expr, options: &Options) -> Result<Type, Vec<TypeError>> {
+    let mut inferer = Inferer::new(options.permissive);
+    let t = inferer.infer(&as_ptr(expr.clone()));
+    if inferer.errors.is_empty() {
+        Ok(inferer.resolve(&t).normalized())
+    } else {
+        Err(inferer.errors)
+    }
+}
+
+// Types of a program after `extract_recursion`, the combinators already
+// taken out, see `infer_extracted`
+pub struct Typing {
+    pub main: Type,
+    pub errors: Vec<TypeError>,
+    definitions: HashMap<i64, Type>,
+    lambdas: HashMap<*const RefCell<Expr>, (ExprPtr, Type)>,
+}
+
+impl Typing {
+    pub fn definition(&self, name: i64) -> Option<&Type> {
+        self.definitions.get(&name)
+    }
+
+    // Type of a lambda in the extracted program, `L x. body` being `a -> b`
+    // also when it is a `let`
+    pub fn lambda(&self, expr_ptr: &ExprPtr) -> Option<&Type> {
+        self.lambdas.get(&Rc::as_ptr(expr_ptr)).map(|(_, t)| t)
+    }
+}
+
+// A definition is typed where it is first used, in the scope it was defined
+// in, and is polymorphic after that. Always permissive, for the combinators
+// `extract_recursion` left in place. Error paths are only meaningful in
+// `main`.
+pub fn infer_extracted(extracted: &Extracted) -> Typing {
+    let mut inferer = Inferer::new(true);
+    for def in extracted.definitions.iter() {
+        inferer.pending.insert(def.name, def.clone());
+    }
+    let main = inferer.infer(&extracted.main);
+    // Unused ones, in their scope nothing is known about free variables
+    for def in extracted.definitions.iter() {
+        if inferer.pending.contains_key(&def.name) {
+            inferer.var(def.name);
+        }
+    }
+    let definitions = inferer
+        .definitions
+        .iter()
+        .map(|(name, scheme)| (*name, inferer.resolve(&scheme.ty).normalized()))
+        .collect();
+    let lambdas = inferer
+        .lambdas
+        .iter()
+        .map(|(key, (e, t))| (*key, (e.clone(), inferer.resolve(t).normalized())))
+        .collect();
+    Typing {
+        main: inferer.resolve(&main).normalized(),
+        errors: inferer.errors,
+        definitions,
+        lambdas,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recursion::extract_recursion;
+
+    fn type_of(program: &str, permissive: bool) -> Result<String, Vec<String>> {
+        let expr = parse_into_ast(program.to_string());
+        let res = infer(&expr.borrow(), &Options { permissive });
+        res.map(|t| t.to_string())
+            .map_err(|errors| errors.iter().map(|e| e.to_string()).collect())
+    }
+
+    #[test]
+    fn test_infer() {
+        assert_eq!(type_of("B+ I\" I#", false), Ok("int".to_string()));
+        assert_eq!(type_of("L# B. v# S4", false), Ok("str -> str".to_string()));
+        assert_eq!(type_of("L# v#", false), Ok("'a -> 'a".to_string()));
+        assert_eq!(
+            type_of("L# L$ B$ v# v$", false),
+            Ok("('a -> 'b) -> 'a -> 'b".to_string())
+        );
+        assert_eq!(type_of("L# B= v# T", false), Ok("bool -> bool".to_string()));
+        // let id = L$ v$ in if id T then id 1 else 2
+        assert_eq!(
+            type_of("B$ L# ? B$ v# T B$ v# I\" I# L$ v$", false),
+            Ok("int".to_string())
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            type_of("B+ I\" S4", false),
+            Err(vec!["at 1: expected int, found str".to_string()])
+        );
+        // Every error, not just the first one
+        assert_eq!(
+            type_of("? I\" U- T B. S4 I\"", false),
+            Err(vec![
+                "at 0: expected bool, found int".to_string(),
+                "at 1.0: expected int, found bool".to_string(),
+                "at 2.1: expected str, found int".to_string(),
+                "at 2: expected int, found str".to_string(),
+            ])
+        );
+        assert_eq!(
+            type_of("L# B$ v# I\"", false),
+            Ok("(int -> 'a) -> 'a".to_string())
+        );
+        assert_eq!(
+            type_of("B$ I\" I#", false),
+            Err(vec!["at 0: expected int -> 'a, found int".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_combinators() {
+        let text = fs::read_to_string("problems/4.txt").unwrap();
+        let Err(errors) = type_of(text.trim(), false) else {
+            panic!("self-application has no type");
+        };
+        assert_eq!(errors[0], "at 0.0.0.1.0.1.0: infinite type 'a = 'a -> 'b");
+        assert_eq!(type_of(text.trim(), true), Ok("int".to_string()));
+        for n in [5, 6, 7, 8, 12, 13] {
+            let text = fs::read_to_string(format!("problems/{}.txt", n)).unwrap();
+            assert!(type_of(text.trim(), true).is_ok(), "problem {}", n);
+        }
+    }
+
+    #[test]
+    fn test_conformance_types() {
+        let text = fs::read_to_string("tests/conformance.txt").unwrap();
+        let cases = conformance::parse_cases(&text, ".").unwrap();
+        for case in cases.iter() {
+            let Ok(expr) = parser::parse_checked(&case.program) else {
+                continue;
+            };
+            let expected = match case.expected {
+                Expr::Boolean(_) => Type::Bool,
+                Expr::Integer(_) => Type::Int,
+                _ => Type::Str,
+            };
+            let options = Options { permissive: true };
+            assert_eq!(
+                infer(&expr.borrow(), &options),
+                Ok(expected),
+                "{}",
+                case.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_extracted() {
+        let text = fs::read_to_string("problems/5.txt").unwrap();
+        let extracted = extract_recursion(&parse_into_ast(text.trim().to_string()).borrow());
+        let typing = infer_extracted(&extracted);
+        assert!(typing.errors.is_empty());
+        assert_eq!(typing.main, Type::Int);
+        let types: Vec<String> = extracted
+            .definitions
+            .iter()
+            .map(|def| typing.definition(def.name).unwrap().to_string())
+            .collect();
+        assert_eq!(types, vec!["int -> int", "int -> bool", "int -> bool"]);
+    }
+}
@@ -0,0 +1,39 @@
+use icfpc_2024::*;
+
+use std::fs;
+
+// Runs the conformance cases compiled by `transpile`, see
+// tests/transpiled/cases.rs, and compares them with the evaluator. Two
+// problems are compiled as well.
+#[path = "transpiled/cases.rs"]
+mod cases;
+
+#[path = "transpiled/problem_1.rs"]
+#[allow(dead_code)]
+mod problem_1;
+
+// Only compiled. Its accumulator no longer builds a chain of thunks, but
+// the loop over 1234567 numbers calls a function looping up to each of them.
+#[path = "transpiled/problem_12.rs"]
+#[allow(dead_code)]
+mod problem_12;
+
+#[test]
+fn test_transpiled_cases() {
+    set_debug(false);
+    let text = fs::read_to_string("tests/conformance.txt").unwrap();
+    let programs = conformance::parse_cases(&text, ".").unwrap();
+    let compiled = cases::cases();
+    assert!(!compiled.is_empty());
+    for (name, run) in compiled {
+        let case = programs.iter().find(|case| case.name == name).unwrap();
+        let expected = eval_example(&case.program);
+        assert_eq!(run().to_expr(), Some(expected), "{}", name);
+    }
+}
+
+#[test]
+fn test_transpiled_problems() {
+    // 4^22, the arguments are shared where the evaluator repeats them
+    assert_eq!(problem_1::run().to_string(), "17592186044416");
+}
@@ -0,0 +1,1072 @@
+// Generated by `transpile --cases tests/conformance.txt`, do not edit.
+
+use icfpc_2024::native::Value;
+
+pub type Case = (&'static str, fn() -> Value);
+
+#[allow(dead_code)]
+pub mod bool_true {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        Value::Bool(true)
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod bool_false {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        Value::Bool(false)
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod int_zero {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        int(0)
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod int_single_digit_max {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        int(93)
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod int_two_digits {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        int(94)
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod int_spec_example {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        int(1337)
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod int_large {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        int(572994802228616703)
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod string_empty {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        string("")
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod string_spec_example {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        string("Hello World!")
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod string_full_table {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        string("abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!\"#$%&'()*+,-./:;<=>?@[\\]^_`|~ \n")
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod unary_neg {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        neg(int(3))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod unary_neg_neg {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        neg(neg(int(3)))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod unary_not {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        not(Value::Bool(true))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod unary_str_to_int {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        str_to_int(string("test"))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod unary_str_to_int_empty {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        str_to_int(string(""))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod unary_int_to_str {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        int_to_str(int(15818151))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod unary_int_to_str_zero {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        int_to_str(int(0))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod unary_round_trip {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        str_to_int(int_to_str(int(94)))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod add {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        add(int(2), int(3))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod sub {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        sub(int(3), int(2))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod sub_negative_result {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        sub(int(2), int(3))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod mul {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        mul(int(3), int(2))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod div_truncates_negative_dividend {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        div(neg(int(7)), int(2))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod div_truncates_negative_divisor {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        div(int(7), neg(int(2)))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod div_both_negative {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        div(neg(int(7)), neg(int(2)))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod mod_negative_dividend {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        rem(neg(int(7)), int(2))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod mod_negative_divisor {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        rem(int(7), neg(int(2)))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod mod_both_negative {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        rem(neg(int(7)), neg(int(2)))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod div_exact {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        div(int(6), int(3))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod lt {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        lt(int(3), int(2))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod gt {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        gt(int(3), int(2))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod lt_negative {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        lt(neg(int(3)), neg(int(2)))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod eq_int {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        eq(int(3), int(2))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod eq_int_same {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        eq(int(3), int(3))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod eq_bool {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        eq(Value::Bool(true), Value::Bool(true))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod eq_bool_different {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        eq(Value::Bool(true), Value::Bool(false))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod eq_string {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        eq(string("test"), string("test"))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod eq_string_different {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        eq(string("te"), string("st"))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod eq_string_empty {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        eq(string(""), string(""))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod eq_string_prefix {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        eq(string("te"), string("test"))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod or {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        or(Value::Bool(true), Value::Bool(false))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod or_false {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        or(Value::Bool(false), Value::Bool(false))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod and {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        and(Value::Bool(true), Value::Bool(false))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod and_true {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        and(Value::Bool(true), Value::Bool(true))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod concat {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        concat(string("te"), string("st"))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod concat_empty {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        concat(string(""), string("test"))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod take {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        take(int(3), string("test"))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod take_zero {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        take(int(0), string("test"))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod take_all {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        take(int(4), string("test"))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod drop {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        drop(int(3), string("test"))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod drop_all {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        drop(int(4), string("test"))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod drop_zero {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        drop(int(0), string("test"))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod if_spec_example {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        if gt(int(2), int(3)).as_bool() {
+            string("yes")
+        } else {
+            string("no")
+        }
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod if_true_branch {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        if Value::Bool(true).as_bool() { int(1) } else { int(2) }
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod if_skips_other_branch {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        if Value::Bool(true).as_bool() {
+            int(1)
+        } else {
+            div(int(1), int(0))
+        }
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod lambda_spec_example {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        apply({
+            let x2 = Thunk::new(move || concat(string("Hello"), string(" World!")));
+            {
+                let x2 = x2.clone();
+                Value::fun(move |_| x2.force())
+            }
+        }, Thunk::ready(int(42)))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod lambda_identity {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        let x2 = Thunk::ready(int(3));
+        x2.force()
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod lambda_unused_argument_is_lazy {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        int(1)
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod lambda_argument_used_twice {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        let x2 = Thunk::ready(int(3));
+        add(x2.force(), x2.force())
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod lambda_shadowed {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        let x2 = Thunk::ready(int(3));
+        x2.force()
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod lambda_shadowed_outer_visible {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        apply({
+            let x2 = Thunk::ready(int(6));
+            {
+                let x2 = x2.clone();
+                Value::fun(move |x3| sub(x2.force(), x3.force()))
+            }
+        }, Thunk::ready(int(2)))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod lambda_shadowed_in_if {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        let x2 = Thunk::ready(int(1));
+        if eq(x2.force(), int(1)).as_bool() {
+            let x2 = Thunk::ready(int(3));
+            x2.force()
+        } else {
+            int(4)
+        }
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod lambda_zero_variable {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        let x0 = Thunk::ready(int(4));
+        mul(x0.force(), x0.force())
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod lambda_recursion_spec_example {
+    use icfpc_2024::native::*;
+
+    fn f0(x2: Thunk) -> Value {
+        if eq(x2.force(), int(0)).as_bool() {
+            int(1)
+        } else {
+            let x3 = {
+                let x2 = x2.clone();
+                Thunk::new(move || sub(x2.force(), int(1)))
+            };
+            add(f0(Thunk::ready(x3.force())), f0(Thunk::ready(x3.force())))
+        }
+    }
+
+    pub fn run() -> Value {
+        f0(Thunk::ready(int(4)))
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+#[allow(dead_code)]
+pub mod language_test {
+    use icfpc_2024::native::*;
+
+    pub fn run() -> Value {
+        if eq(apply(apply(apply(Value::fun(move |_| Value::fun(move |x3| {
+            let x3 = x3.clone();
+            Value::fun(move |_| x3.force())
+        })), Thunk::ready(int(2))), Thunk::ready(int(3))), Thunk::ready(int(4))), int(3)).as_bool() {
+            if eq({
+                let x3 = Thunk::ready(int(10));
+                x3.force()
+            }, int(10)).as_bool() {
+                if eq(drop(int(3), string("test")), string("t")).as_bool() {
+                    if eq(take(int(3), string("test")), string("tes")).as_bool() {
+                        if eq(concat(string("te"), string("st")), string("test")).as_bool() {
+                            if not(and(Value::Bool(true), Value::Bool(false))).as_bool() {
+                                if and(Value::Bool(true), Value::Bool(true)).as_bool() {
+                                    if not(or(Value::Bool(false), Value::Bool(false))).as_bool() {
+                                        if or(Value::Bool(false), Value::Bool(true)).as_bool() {
+                                            if lt(neg(int(3)), neg(int(2))).as_bool() {
+                                                if gt(int(3), int(2)).as_bool() {
+                                                    if eq(neg(int(1)), rem(neg(int(3)), int(2))).as_bool() {
+                                                        if eq(int(1), rem(int(7), int(3))).as_bool() {
+                                                            if eq(neg(int(1)), div(neg(int(3)), int(2))).as_bool() {
+                                                                if eq(int(2), div(int(7), int(3))).as_bool() {
+                                                                    if eq(int(6), mul(int(2), int(3))).as_bool() {
+                                                                        if eq(int(3), add(int(1), int(2))).as_bool() {
+                                                                            if eq(int_to_str(int(15818151)), string("test")).as_bool() {
+                                                                                if eq(str_to_int(string("test")), int(15818151)).as_bool() {
+                                                                                    if not(Value::Bool(false)).as_bool() {
+                                                                                        if eq(neg(int(3)), sub(int(2), int(5))).as_bool() {
+                                                                                            if eq(int(3), sub(int(5), int(2))).as_bool() {
+                                                                                                if eq(string("test"), string("test")).as_bool() {
+                                                                                                    if eq(Value::Bool(false), Value::Bool(false)).as_bool() {
+                                                                                                        if eq(int(3), int(3)).as_bool() {
+                                                                                                            if Value::Bool(true).as_bool() {
+                                                                                                                concat(concat(string("Self-check OK, send `solve language_test "), int_to_str(add(int(2), mul(int(311), int(124753942619))))), string("` to claim points for it"))
+                                                                                                            } else {
+                                                                                                                string("if is not correct")
+                                                                                                            }
+                                                                                                        } else {
+                                                                                                            string("binary = is not correct")
+                                                                                                        }
+                                                                                                    } else {
+                                                                                                        string("binary = is not correct")
+                                                                                                    }
+                                                                                                } else {
+                                                                                                    string("binary = is not correct")
+                                                                                                }
+                                                                                            } else {
+                                                                                                string("binary - is not correct")
+                                                                                            }
+                                                                                        } else {
+                                                                                            string("unary - is not correct")
+                                                                                        }
+                                                                                    } else {
+                                                                                        string("unary ! is not correct")
+                                                                                    }
+                                                                                } else {
+                                                                                    string("unary # is not correct")
+                                                                                }
+                                                                            } else {
+                                                                                string("unary $ is not correct")
+                                                                            }
+                                                                        } else {
+                                                                            string("binary + is not correct")
+                                                                        }
+                                                                    } else {
+                                                                        string("binary * is not correct")
+                                                                    }
+                                                                } else {
+                                                                    string("binary / is not correct")
+                                                                }
+                                                            } else {
+                                                                string("binary / is not correct")
+                                                            }
+                                                        } else {
+                                                            string("binary % is not correct")
+                                                        }
+                                                    } else {
+                                                        string("binary % is not correct")
+                                                    }
+                                                } else {
+                                                    string("binary > is not correct")
+                                                }
+                                            } else {
+                                                string("binary < is not correct")
+                                            }
+                                        } else {
+                                            string("binary | is not correct")
+                                        }
+                                    } else {
+                                        string("binary | is not correct")
+                                    }
+                                } else {
+                                    string("binary & is not correct")
+                                }
+                            } else {
+                                string("binary & is not correct")
+                            }
+                        } else {
+                            string("binary . is not correct")
+                        }
+                    } else {
+                        string("binary T is not correct")
+                    }
+                } else {
+                    string("binary D is not correct")
+                }
+            } else {
+                string("application is not correct")
+            }
+        } else {
+            string("application is not correct")
+        }
+    }
+
+    fn main() {
+        run_main(run);
+    }
+}
+
+pub fn cases() -> Vec<Case> {
+    vec![
+        ("bool_true", bool_true::run),
+        ("bool_false", bool_false::run),
+        ("int_zero", int_zero::run),
+        ("int_single_digit_max", int_single_digit_max::run),
+        ("int_two_digits", int_two_digits::run),
+        ("int_spec_example", int_spec_example::run),
+        ("int_large", int_large::run),
+        ("string_empty", string_empty::run),
+        ("string_spec_example", string_spec_example::run),
+        ("string_full_table", string_full_table::run),
+        ("unary_neg", unary_neg::run),
+        ("unary_neg_neg", unary_neg_neg::run),
+        ("unary_not", unary_not::run),
+        ("unary_str_to_int", unary_str_to_int::run),
+        ("unary_str_to_int_empty", unary_str_to_int_empty::run),
+        ("unary_int_to_str", unary_int_to_str::run),
+        ("unary_int_to_str_zero", unary_int_to_str_zero::run),
+        ("unary_round_trip", unary_round_trip::run),
+        ("add", add::run),
+        ("sub", sub::run),
+        ("sub_negative_result", sub_negative_result::run),
+        ("mul", mul::run),
+        ("div_truncates_negative_dividend", div_truncates_negative_dividend::run),
+        ("div_truncates_negative_divisor", div_truncates_negative_divisor::run),
+        ("div_both_negative", div_both_negative::run),
+        ("mod_negative_dividend", mod_negative_dividend::run),
+        ("mod_negative_divisor", mod_negative_divisor::run),
+        ("mod_both_negative", mod_both_negative::run),
+        ("div_exact", div_exact::run),
+        ("lt", lt::run),
+        ("gt", gt::run),
+        ("lt_negative", lt_negative::run),
+        ("eq_int", eq_int::run),
+        ("eq_int_same", eq_int_same::run),
+        ("eq_bool", eq_bool::run),
+        ("eq_bool_different", eq_bool_different::run),
+        ("eq_string", eq_string::run),
+        ("eq_string_different", eq_string_different::run),
+        ("eq_string_empty", eq_string_empty::run),
+        ("eq_string_prefix", eq_string_prefix::run),
+        ("or", or::run),
+        ("or_false", or_false::run),
+        ("and", and::run),
+        ("and_true", and_true::run),
+        ("concat", concat::run),
+        ("concat_empty", concat_empty::run),
+        ("take", take::run),
+        ("take_zero", take_zero::run),
+        ("take_all", take_all::run),
+        ("drop", drop::run),
+        ("drop_all", drop_all::run),
+        ("drop_zero", drop_zero::run),
+        ("if_spec_example", if_spec_example::run),
+        ("if_true_branch", if_true_branch::run),
+        ("if_skips_other_branch", if_skips_other_branch::run),
+        ("lambda_spec_example", lambda_spec_example::run),
+        ("lambda_identity", lambda_identity::run),
+        ("lambda_unused_argument_is_lazy", lambda_unused_argument_is_lazy::run),
+        ("lambda_argument_used_twice", lambda_argument_used_twice::run),
+        ("lambda_shadowed", lambda_shadowed::run),
+        ("lambda_shadowed_outer_visible", lambda_shadowed_outer_visible::run),
+        ("lambda_shadowed_in_if", lambda_shadowed_in_if::run),
+        ("lambda_zero_variable", lambda_zero_variable::run),
+        ("lambda_recursion_spec_example", lambda_recursion_spec_example::run),
+        ("language_test", language_test::run),
+    ]
+}
@@ -0,0 +1,73 @@
+use icfpc_2024::native::*;
+
+pub fn run() -> Value {
+    let x0 = Thunk::ready(Value::fun(move |x0| add(add(x0.force(), x0.force()), add(x0.force(), x0.force()))));
+    apply(x0.force(), {
+        let x0 = x0.clone();
+        Thunk::new(move || apply(x0.force(), {
+            let x0 = x0.clone();
+            Thunk::new(move || apply(x0.force(), {
+                let x0 = x0.clone();
+                Thunk::new(move || apply(x0.force(), {
+                    let x0 = x0.clone();
+                    Thunk::new(move || apply(x0.force(), {
+                        let x0 = x0.clone();
+                        Thunk::new(move || apply(x0.force(), {
+                            let x0 = x0.clone();
+                            Thunk::new(move || apply(x0.force(), {
+                                let x0 = x0.clone();
+                                Thunk::new(move || apply(x0.force(), {
+                                    let x0 = x0.clone();
+                                    Thunk::new(move || apply(x0.force(), {
+                                        let x0 = x0.clone();
+                                        Thunk::new(move || apply(x0.force(), {
+                                            let x0 = x0.clone();
+                                            Thunk::new(move || apply(x0.force(), {
+                                                let x0 = x0.clone();
+                                                Thunk::new(move || apply(x0.force(), {
+                                                    let x0 = x0.clone();
+                                                    Thunk::new(move || apply(x0.force(), {
+                                                        let x0 = x0.clone();
+                                                        Thunk::new(move || apply(x0.force(), {
+                                                            let x0 = x0.clone();
+                                                            Thunk::new(move || apply(x0.force(), {
+                                                                let x0 = x0.clone();
+                                                                Thunk::new(move || apply(x0.force(), {
+                                                                    let x0 = x0.clone();
+                                                                    Thunk::new(move || apply(x0.force(), {
+                                                                        let x0 = x0.clone();
+                                                                        Thunk::new(move || apply(x0.force(), {
+                                                                            let x0 = x0.clone();
+                                                                            Thunk::new(move || apply(x0.force(), {
+                                                                                let x0 = x0.clone();
+                                                                                Thunk::new(move || apply(x0.force(), {
+                                                                                    let x0 = x0.clone();
+                                                                                    Thunk::new(move || apply(x0.force(), {
+                                                                                        let x0 = x0.clone();
+                                                                                        Thunk::new(move || apply(x0.force(), Thunk::ready(int(1))))
+                                                                                    }))
+                                                                                }))
+                                                                            }))
+                                                                        }))
+                                                                    }))
+                                                                }))
+                                                            }))
+                                                        }))
+                                                    }))
+                                                }))
+                                            }))
+                                        }))
+                                    }))
+                                }))
+                            }))
+                        }))
+                    }))
+                }))
+            }))
+        }))
+    })
+}
+
+fn main() {
+    run_main(run);
+}
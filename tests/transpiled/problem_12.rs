@@ -0,0 +1,53 @@
+use icfpc_2024::native::*;
+
+pub fn run() -> Value {
+    apply(Thunk::fix(move |f0| Value::fun(move |x4| apply({
+        let x1 = x4.clone();
+        {
+            let x1 = x1.clone();
+            Value::fun(move |x2| {
+                if lt(x1.force(), x2.force()).as_bool() {
+                    x1.force()
+                } else {
+                    x2.force()
+                }
+            })
+        }
+    }, {
+        let f0 = f0.clone();
+        let x4 = x4.clone();
+        Thunk::new(move || add(int(1), if gt(x4.force(), int(2)).as_bool() {
+            apply(apply({
+                let f0 = f0.clone();
+                let x4 = x4.clone();
+                Thunk::fix(move |f1| Value::fun(move |x6| {
+                    let f0 = f0.clone();
+                    let f1 = f1.clone();
+                    let x4 = x4.clone();
+                    let x6 = x6.clone();
+                    Value::fun(move |x7| {
+                        if eq(x6.force(), x4.force()).as_bool() {
+                            x7.force()
+                        } else {
+                            apply(apply(f1.force(), Thunk::ready(add(x6.force(), int(1)))), Thunk::ready(if gt(apply(f0.force(), x6.clone()), sub(x6.force(), int(1))).as_bool() {
+                                if eq(rem(x4.force(), x6.force()), int(0)).as_bool() {
+                                    mul(div(x7.force(), apply(f0.force(), x6.clone())), sub(apply(f0.force(), x6.clone()), int(1)))
+                                } else {
+                                    x7.force()
+                                }
+                            } else {
+                                x7.force()
+                            }))
+                        }
+                    })
+                }))
+            }.force(), Thunk::ready(int(2))), Thunk::ready(x4.force()))
+        } else {
+            x4.force()
+        }))
+    }))).force(), Thunk::ready(int(1234567)))
+}
+
+fn main() {
+    run_main(run);
+}